@@ -0,0 +1,89 @@
+#[cfg(unix)]
+use std::io;
+use std::str::FromStr;
+
+#[cfg(unix)]
+use fehler::throws;
+#[cfg(unix)]
+use log::{info, trace, warn};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+/// A command for inspecting or controlling a running protocol over the admin socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// `view`: reports the installed and last attempted views
+    View,
+    /// `leader`: reports the leader of the installed view
+    Leader,
+    /// `force-vc <n>`: starts a view change to the given view, if it's past the installed one
+    ForceViewChange(u32),
+    /// `step-down`: starts a view change to the next view, if leading the installed one
+    StepDown,
+    /// `dump`: reports the view changes received for every view still being tracked
+    Dump,
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AdminCommand, String> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        match words[..] {
+            ["view"] => Ok(AdminCommand::View),
+            ["leader"] => Ok(AdminCommand::Leader),
+            ["dump"] => Ok(AdminCommand::Dump),
+            ["step-down"] => Ok(AdminCommand::StepDown),
+            ["force-vc", view] => view.parse()
+                .map(AdminCommand::ForceViewChange)
+                .map_err(|_| format!("invalid view: {}", view)),
+            _ => Err(format!("unknown command: {}", s.trim())),
+        }
+    }
+}
+
+/// A command for the protocol along with where to send its one line response.
+pub type AdminRequest = (AdminCommand, oneshot::Sender<String>);
+
+/// Serves the admin socket, answering each line a client sends with one line in response. Commands
+/// are forwarded to the protocol over the given channel, which answers them as it runs.
+#[cfg(unix)]
+#[throws(io::Error)]
+pub async fn serve(mut listener: UnixListener, requests: Sender<AdminRequest>) -> () {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        info!("admin connection opened");
+        // connections are served one at a time, which is plenty for poking at a single node
+        if let Err(e) = serve_connection(stream, requests.clone()).await {
+            warn!("admin connection failed: {}", e);
+        }
+    }
+}
+
+/// Answers every command sent over the given connection until the client hangs up.
+#[cfg(unix)]
+#[throws(io::Error)]
+async fn serve_connection(stream: UnixStream, mut requests: Sender<AdminRequest>) -> () {
+    let mut conn = BufReader::new(stream);
+    let mut line = String::new();
+    while conn.read_line(&mut line).await? != 0 {
+        trace!("admin command: {}", line.trim());
+        let response = match line.parse() {
+            Ok(cmd) => {
+                let (tx, rx) = oneshot::channel();
+                if requests.send((cmd, tx)).await.is_err() {
+                    return
+                }
+                rx.await.unwrap_or_else(|_| "protocol stopped".to_owned())
+            },
+            Err(reason) => reason,
+        };
+        conn.get_mut().write_all(format!("{}\n", response).as_bytes()).await?;
+        line.clear();
+    }
+}
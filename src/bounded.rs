@@ -0,0 +1,104 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// The default number of entries kept by a `BoundedMap`.
+pub const TRACKING_CAPACITY: usize = 1024;
+
+/// A map holding at most a fixed number of entries, for tracking things per sender or per view
+/// over a long run without growing for good. Once it's full, inserting a new key evicts the least
+/// recently inserted (or updated) entry.
+#[derive(Clone, Debug)]
+pub struct BoundedMap<K: Hash + Eq, V> {
+    /// the most entries to hold at once
+    capacity: usize,
+    /// the entries held
+    entries: HashMap<K, V>,
+    /// the keys of the entries held, from the least recently inserted or updated to the most
+    order: VecDeque<K>,
+}
+
+impl<K: Hash + Eq + Clone, V> BoundedMap<K, V> {
+    /// Creates an empty map holding at most the given number of entries (and at least one).
+    pub fn new(capacity: usize) -> BoundedMap<K, V> {
+        let capacity = capacity.max(1);
+        BoundedMap { capacity, entries: HashMap::new(), order: VecDeque::new() }
+    }
+
+    /// Inserts the given value for the given key, making it the most recently used entry, and
+    /// returns the value it replaced, if any. Inserting a new key into a full map evicts the least
+    /// recently used entry first.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let replaced = self.entries.insert(key.clone(), value);
+        if replaced.is_some() {
+            self.order.retain(|held| *held != key);
+        } else if self.entries.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(key);
+        replaced
+    }
+
+    /// Gets the value for the given key, if it's held.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Gets the number of entries held.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Determines whether or not the map holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets the most entries the map holds at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Iterates over the entries held, from the least recently used to the most.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let entries = &self.entries;
+        self.order.iter().filter_map(move |key| entries.get_key_value(key))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Default for BoundedMap<K, V> {
+    fn default() -> BoundedMap<K, V> {
+        BoundedMap::new(TRACKING_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_entries_are_evicted_past_the_capacity() {
+        let mut map = BoundedMap::new(3);
+        for key in 0..10 {
+            map.insert(key, key * 10);
+            assert!(map.len() <= 3);
+        }
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&6), None);
+        let held: Vec<_> = map.iter().map(|(key, value)| (*key, *value)).collect();
+        assert_eq!(held, vec![(7, 70), (8, 80), (9, 90)]);
+    }
+
+    #[test]
+    fn updating_an_entry_keeps_it_from_being_evicted() {
+        let mut map = BoundedMap::new(2);
+        map.insert("a", 1);
+        map.insert("b", 2);
+        assert_eq!(map.insert("a", 3), Some(1));
+        map.insert("c", 4);
+        assert_eq!(map.get(&"a"), Some(&3));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,341 @@
+use std::fs::File;
+use std::io::{self, BufReader, LineWriter};
+use std::io::prelude::*;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use fehler::{throw, throws};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::codec::{Decoder, Encoder};
+use tokio::timer;
+
+use crate::msg::{Message, MessageCodec};
+
+/// A message received by the system, as recorded in a capture file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Captured {
+    /// the message that was received
+    pub msg: Message,
+    /// the address the message was received from
+    pub from: SocketAddr,
+    /// when the message was received, in milliseconds since recording started
+    pub elapsed_ms: u64,
+}
+
+/// A file recording every message received by the system, one JSON object per line, so that a
+/// nondeterministic run over the network can be replayed deterministically later.
+pub struct Capture {
+    /// the capture file
+    writer: LineWriter<File>,
+    /// when recording started
+    start: Instant,
+}
+
+impl Capture {
+    /// Creates a new capture file at the given path, truncating any existing one.
+    #[throws(io::Error)]
+    pub fn create<P: AsRef<Path>>(path: P) -> Capture {
+        Capture { writer: LineWriter::new(File::create(path)?), start: Instant::now() }
+    }
+
+    /// Records the given message as received from the given address. Failing to record a message
+    /// does not fail the protocol, and is only logged.
+    pub fn record(&mut self, msg: Message, from: SocketAddr) {
+        let elapsed_ms = self.start.elapsed().as_millis() as u64;
+        let captured = Captured { msg, from, elapsed_ms };
+        let res = serde_json::to_writer(&mut self.writer, &captured)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(self.writer));
+        if let Err(e) = res {
+            warn!("failed to capture {:?}: {}", msg, e);
+        }
+    }
+}
+
+/// Loads every message recorded in the capture file at the given path, in recorded order.
+#[throws(io::Error)]
+pub fn load<P: AsRef<Path>>(path: P) -> Vec<Captured> {
+    let mut captured = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue }
+        captured.push(serde_json::from_str(&line)?);
+    }
+    captured
+}
+
+/// A message received by the system, as recorded in a binary capture file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedDatagram {
+    /// when the message was received, in nanoseconds since recording started
+    pub elapsed_ns: u64,
+    /// the message as encoded on the wire, less any sequence number
+    pub bytes: Bytes,
+}
+
+/// A file recording every message received by the system in the binary wire format, for replaying
+/// with the original timing at a fraction of the size and cost of a JSON capture. Every record is
+/// a `u64` of the nanoseconds since recording started, then a `u32` of the length of the encoded
+/// message, then the encoded message itself. Senders are not recorded.
+///
+/// Messages are encoded by the system's own codec, so that replaying decodes them with the very
+/// same one, but without sequence numbers, which recording would otherwise take from those of the
+/// messages actually sent. Replaying a capture takes the same codec settings it was recorded with.
+pub struct BinaryCapture {
+    /// the capture file
+    file: File,
+    /// the codec to encode received messages with
+    codec: MessageCodec,
+    /// when recording started
+    start: Instant,
+}
+
+impl BinaryCapture {
+    /// Creates a new binary capture file at the given path, truncating any existing one, which
+    /// records messages as encoded by the given codec.
+    #[throws(io::Error)]
+    pub fn create<P: AsRef<Path>>(path: P, codec: MessageCodec) -> BinaryCapture {
+        BinaryCapture {
+            file: File::create(path)?,
+            codec: MessageCodec { sequencing: None, ..codec },
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the given message as received just now. Failing to record a message does not fail
+    /// the protocol, and is only logged.
+    pub fn record(&mut self, msg: Message) {
+        let elapsed_ns = self.start.elapsed().as_nanos() as u64;
+        let mut encoded = BytesMut::new();
+        let res = self.codec.encode(msg, &mut encoded).and_then(|()| {
+            let mut record = BytesMut::with_capacity(12 + encoded.len());
+            record.put_u64_be(elapsed_ns);
+            record.put_u32_be(encoded.len() as u32);
+            record.extend_from_slice(&encoded);
+            // each record goes out in a single write, so a crash can only cut off the last one
+            self.file.write_all(&record)
+        });
+        if let Err(e) = res {
+            warn!("failed to capture {:?}: {}", msg, e);
+        }
+    }
+}
+
+/// Loads every message recorded in the binary capture file at the given path, in recorded order.
+/// Fails with `UnexpectedEof` if the last record was cut off.
+#[throws(io::Error)]
+pub fn load_binary<P: AsRef<Path>>(path: P) -> Vec<CapturedDatagram> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut captured = Vec::new();
+    let mut header = [0; 12];
+    loop {
+        // a capture can only end cleanly between records
+        match file.read(&mut header[..1])? {
+            0 => break,
+            _ => file.read_exact(&mut header[1..])?,
+        }
+        let mut elapsed_ns = [0; 8];
+        let mut len = [0; 4];
+        elapsed_ns.copy_from_slice(&header[..8]);
+        len.copy_from_slice(&header[8..]);
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        file.read_exact(&mut bytes)?;
+        captured.push(CapturedDatagram {
+            elapsed_ns: u64::from_be_bytes(elapsed_ns),
+            bytes: bytes.into(),
+        });
+    }
+    captured
+}
+
+/// Decodes a single recorded message with the given codec, which must hold exactly one message.
+#[throws(io::Error)]
+fn decode_recorded(codec: &mut MessageCodec, bytes: &[u8]) -> Message {
+    let mut src = BytesMut::from(bytes);
+    match codec.decode(&mut src)? {
+        Some(msg) if src.is_empty() => msg,
+        _ => throw!(io::Error::new(io::ErrorKind::InvalidData, "malformed captured message")),
+    }
+}
+
+/// Produces the messages decoded from the given captured datagrams in order, waiting out their
+/// original inter-arrival delays. Datagrams that fail to decode are skipped.
+fn replay_binary(
+    captured: Vec<CapturedDatagram>, codec: MessageCodec
+) -> BoxStream<'static, io::Result<(Message, SocketAddr)>> {
+    let mut codec = MessageCodec { sequencing: None, ..codec };
+    // senders aren't recorded, so every message seems to come from nowhere in particular
+    let from = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+    let mut last_ns = 0;
+    let delayed: Vec<_> = captured.into_iter().filter_map(|captured| {
+        let gap = Duration::from_nanos(captured.elapsed_ns.saturating_sub(last_ns));
+        last_ns = captured.elapsed_ns;
+        match decode_recorded(&mut codec, &captured.bytes) {
+            Ok(msg) => Some((gap, msg)),
+            Err(e) => {
+                warn!("skipping captured datagram: {}", e);
+                None
+            },
+        }
+    }).collect();
+
+    stream::iter(delayed).then(move |(gap, msg)| async move {
+        timer::delay_for(gap).await;
+        Ok((msg, from))
+    }).boxed()
+}
+
+/// Produces the given captured messages in order, waiting out their original inter-arrival delays.
+fn replay(captured: Vec<Captured>) -> BoxStream<'static, io::Result<(Message, SocketAddr)>> {
+    let mut last_ms = 0;
+    let delayed: Vec<_> = captured.into_iter().map(|captured| {
+        let gap = Duration::from_millis(captured.elapsed_ms.saturating_sub(last_ms));
+        last_ms = captured.elapsed_ms;
+        (gap, captured)
+    }).collect();
+
+    stream::iter(delayed).then(|(gap, captured)| async move {
+        timer::delay_for(gap).await;
+        Ok((captured.msg, captured.from))
+    }).boxed()
+}
+
+/// Wraps the stream of received messages so that each one is recorded in the given capture, or
+/// replaces it entirely with the given captured messages when replaying.
+pub(crate) fn wrap<S>(
+    received: S, capture: Option<Capture>, captured: Option<Vec<Captured>>
+) -> BoxStream<'static, io::Result<(Message, SocketAddr)>>
+where S: Stream<Item = io::Result<(Message, SocketAddr)>> + Send + 'static {
+    match (captured, capture) {
+        (Some(captured), _) => replay(captured),
+        (None, Some(mut capture)) => received.map(move |res| {
+            if let Ok((msg, from)) = res {
+                capture.record(msg, from);
+            }
+            res
+        }).boxed(),
+        (None, None) => received.boxed(),
+    }
+}
+
+/// Wraps the stream of received messages so that each one is recorded in the given binary capture,
+/// or replaces it entirely with the given captured datagrams, decoded with the given codec, when
+/// replaying.
+pub(crate) fn wrap_binary<S>(
+    received: S, capture: Option<BinaryCapture>,
+    captured: Option<(Vec<CapturedDatagram>, MessageCodec)>,
+) -> BoxStream<'static, io::Result<(Message, SocketAddr)>>
+where S: Stream<Item = io::Result<(Message, SocketAddr)>> + Send + 'static {
+    match (captured, capture) {
+        (Some((captured, codec)), _) => replay_binary(captured, codec),
+        (None, Some(mut capture)) => received.map(move |res| {
+            if let Ok((msg, _)) = res {
+                capture.record(msg);
+            }
+            res
+        }).boxed(),
+        (None, None) => received.boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use futures::stream::TryStreamExt;
+
+    use super::*;
+
+    /// Gets a path for a capture file unique to the given test.
+    fn scratch(test: &str) -> PathBuf {
+        env::temp_dir().join(format!("paxos-capture-{}-{}", process::id(), test))
+    }
+
+    /// Gets the messages of a short session, in which the first of three nodes installs view 2.
+    fn session() -> Vec<Message> {
+        vec![
+            Message::ViewChange { server_id: 1, attempted: 1 },
+            Message::ViewChange { server_id: 2, attempted: 2 },
+            Message::ViewChange { server_id: 1, attempted: 2 },
+        ]
+    }
+
+    #[tokio::test]
+    async fn recorded_session_replays_the_same_messages() {
+        let path = scratch("json");
+        let from = SocketAddr::from(([127, 0, 0, 1], 42069));
+        let received = stream::iter(session()).map(move |msg| Ok((msg, from)));
+        let capture = Capture::create(&path).unwrap();
+        let recorded: Vec<_> = wrap(received, Some(capture), None).try_collect().await.unwrap();
+        assert_eq!(recorded, session().into_iter().map(|msg| (msg, from)).collect::<Vec<_>>());
+
+        let captured = load(&path).unwrap();
+        let msgs: Vec<_> = captured.iter().map(|captured| captured.msg).collect();
+        assert_eq!(msgs, session());
+        assert!(captured.iter().all(|captured| captured.from == from));
+        let nothing = stream::empty::<io::Result<(Message, SocketAddr)>>();
+        let replayed: Vec<_> = wrap(nothing, None, Some(captured)).try_collect().await.unwrap();
+        assert_eq!(replayed, recorded);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn binary_capture_replays_the_same_messages_with_their_timing() {
+        let path = scratch("binary");
+        let codec = MessageCodec { checksum: true, ..MessageCodec::datagram() };
+        let mut capture = BinaryCapture::create(&path, codec.clone()).unwrap();
+        for msg in session() {
+            capture.record(msg);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        drop(capture);
+
+        // every record holds the message just as the codec encodes it, after its arrival time
+        let captured = load_binary(&path).unwrap();
+        let encoded: Vec<_> = session().into_iter()
+            .map(|msg| {
+                let mut buf = BytesMut::new();
+                codec.clone().encode(msg, &mut buf).unwrap();
+                buf.freeze()
+            })
+            .collect();
+        assert_eq!(captured.iter().map(|captured| captured.bytes.clone()).collect::<Vec<_>>(),
+                   encoded);
+        for pair in captured.windows(2) {
+            assert!(pair[1].elapsed_ns - pair[0].elapsed_ns >= 20_000_000, "{:?}", pair);
+        }
+
+        let span = captured[2].elapsed_ns - captured[0].elapsed_ns;
+        let start = Instant::now();
+        let nothing = stream::empty::<io::Result<(Message, SocketAddr)>>();
+        let replayed: Vec<_> = wrap_binary(nothing, None, Some((captured, codec)))
+            .map_ok(|(msg, _)| msg)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(replayed, session());
+        assert!(start.elapsed() >= Duration::from_nanos(span));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn binary_capture_cut_off_mid_record_fails_to_load() {
+        let path = scratch("truncated");
+        let mut capture = BinaryCapture::create(&path, MessageCodec::datagram()).unwrap();
+        capture.record(session()[0]);
+        drop(capture);
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 1).unwrap();
+
+        let err = load_binary(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        fs::remove_file(&path).unwrap();
+    }
+}
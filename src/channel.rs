@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Poll, Sink, Stream, StreamExt};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::task::Context;
+use log::trace;
+use tokio::codec::Decoder;
+use tokio::net::UdpSocket;
+
+/// The largest datagram that can be received, which is the most that UDP can carry.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// A channel exchanging datagrams with other addresses, which is a UDP socket for real, but can be
+/// anything routing datagrams by address (e.g. a `MemoryMesh` simulating many nodes at once).
+pub trait DatagramChannel: Send {
+    /// Attempts to send the datagram in the buffer to the given address, returning the number of
+    /// bytes sent.
+    fn poll_send_to(&mut self, ctx: &mut Context<'_>, buf: &[u8], target: &SocketAddr)
+        -> Poll<io::Result<usize>>;
+
+    /// Attempts to receive a datagram into the buffer, returning its length and where it came
+    /// from. A datagram too long for the buffer is cut short.
+    fn poll_recv_from(&mut self, ctx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<io::Result<(usize, SocketAddr)>>;
+
+    /// Gets the address the channel receives on.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl DatagramChannel for UdpSocket {
+    fn poll_send_to(&mut self, ctx: &mut Context<'_>, buf: &[u8], target: &SocketAddr)
+        -> Poll<io::Result<usize>>
+    {
+        UdpSocket::poll_send_to(self, ctx, buf, target)
+    }
+
+    fn poll_recv_from(&mut self, ctx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<io::Result<(usize, SocketAddr)>>
+    {
+        UdpSocket::poll_recv_from(self, ctx, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+/// A datagram channel framed by a codec: a `Stream` of the frames decoded from each datagram
+/// received, along with their senders, and a `Sink` of datagrams (already encoded) to send.
+pub struct DatagramFramed<C> {
+    /// the channel the datagrams are exchanged over
+    channel: Box<dyn DatagramChannel>,
+    /// the codec decoding the datagrams received
+    codec: C,
+    /// the buffer datagrams are received into
+    buf: Vec<u8>,
+    /// the datagram waiting to be sent, along with its destination, if any
+    pending: Option<(Bytes, SocketAddr)>,
+}
+
+impl<C> DatagramFramed<C> {
+    /// Frames the given channel with the given codec.
+    pub fn new<D: DatagramChannel + 'static>(channel: D, codec: C) -> DatagramFramed<C> {
+        DatagramFramed {
+            channel: Box::new(channel),
+            codec,
+            buf: vec![0; MAX_DATAGRAM],
+            pending: None,
+        }
+    }
+
+    /// Gets the underlying channel.
+    pub fn get_ref(&self) -> &dyn DatagramChannel {
+        &*self.channel
+    }
+
+    /// Frames the underlying channel with another codec instead, dropping any datagram still
+    /// waiting to be sent.
+    pub fn with_codec<D>(self, codec: D) -> DatagramFramed<D> {
+        DatagramFramed { channel: self.channel, codec, buf: self.buf, pending: None }
+    }
+}
+
+impl<C: Decoder + Unpin> Stream for DatagramFramed<C> {
+    type Item = Result<(C::Item, SocketAddr), C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let (len, addr) = match this.channel.poll_recv_from(ctx, &mut this.buf) {
+                Poll::Ready(Ok(received)) => received,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Pending => return Poll::Pending,
+            };
+            // every datagram is decoded on its own, so one that's incomplete never will be
+            let mut src = BytesMut::from(&this.buf[..len]);
+            match this.codec.decode(&mut src) {
+                Ok(Some(frame)) => return Poll::Ready(Some(Ok((frame, addr)))),
+                Ok(None) => trace!("skipping incomplete datagram from {}", addr),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+impl<C: Unpin> Sink<(Bytes, SocketAddr)> for DatagramFramed<C> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(ctx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, datagram: (Bytes, SocketAddr)) -> io::Result<()> {
+        self.get_mut().pending = Some(datagram);
+        Ok(())
+    }
+
+    /// Sends the pending datagram, if any. If sending fails, the datagram stays pending, so that
+    /// flushing again re-sends it.
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some((ref datagram, ref target)) = this.pending {
+            let sent = match this.channel.poll_send_to(ctx, datagram, target) {
+                Poll::Ready(Ok(sent)) => sent,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            let len = datagram.len();
+            this.pending = None;
+            if sent != len {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Other, format!("sent {} of {} bytes of a datagram", sent, len)
+                )))
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(ctx)
+    }
+}
+
+/// An in-memory network routing datagrams between the channels attached to it by address, for
+/// simulating many nodes in one process without any sockets. Like UDP, datagrams sent to an
+/// address that nothing is attached to are dropped.
+#[derive(Clone, Default)]
+pub struct MemoryMesh {
+    /// the inbox of every channel attached, by its address
+    inboxes: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<(Bytes, SocketAddr)>>>>,
+}
+
+impl MemoryMesh {
+    /// Creates a mesh without any channels attached.
+    pub fn new() -> MemoryMesh {
+        MemoryMesh::default()
+    }
+
+    /// Attaches a channel to the mesh at the given address, receiving every datagram sent to it
+    /// from then on. A channel attached earlier at the same address is cut off.
+    pub fn attach(&self, addr: SocketAddr) -> MeshChannel {
+        let (tx, inbox) = mpsc::unbounded();
+        self.inboxes.lock().unwrap().insert(addr, tx);
+        MeshChannel { addr, mesh: self.clone(), inbox }
+    }
+}
+
+/// A channel attached to a `MemoryMesh` at some address.
+pub struct MeshChannel {
+    /// the address the channel is attached at
+    addr: SocketAddr,
+    /// the mesh routing the datagrams
+    mesh: MemoryMesh,
+    /// the datagrams sent to the channel, along with their senders
+    inbox: UnboundedReceiver<(Bytes, SocketAddr)>,
+}
+
+impl DatagramChannel for MeshChannel {
+    fn poll_send_to(&mut self, _ctx: &mut Context<'_>, buf: &[u8], target: &SocketAddr)
+        -> Poll<io::Result<usize>>
+    {
+        match self.mesh.inboxes.lock().unwrap().get(target) {
+            // a channel that was dropped is as good as one that was never attached
+            Some(tx) => {
+                let _ = tx.unbounded_send((Bytes::from(buf), self.addr));
+            },
+            None => trace!("dropping datagram to {}, which nothing is attached at", target),
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(&mut self, ctx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<io::Result<(usize, SocketAddr)>>
+    {
+        match self.inbox.poll_next_unpin(ctx) {
+            Poll::Ready(Some((datagram, from))) => {
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Poll::Ready(Ok((len, from)))
+            },
+            Poll::Ready(None) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::NotConnected, format!("channel at {} was cut off", self.addr)
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use futures::future;
+    use tokio::sync::mpsc;
+
+    use crate::{MessageCodec, PaxosConfig, System, TestCase, ViewInstalled, PORT_NUMBER};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn three_nodes_simulated_over_a_mesh_converge_on_a_view() {
+        let mesh = MemoryMesh::new();
+        let hosts: Vec<_> = (1..=3).map(|host| format!("10.0.0.{}", host)).collect();
+        let mut runs = Vec::new();
+        let mut notifications = Vec::new();
+        for host in &hosts {
+            // each node receives on the port and sends from the one after it, as over sockets
+            let ip: IpAddr = host.parse().unwrap();
+            let incoming = mesh.attach(SocketAddr::new(ip, PORT_NUMBER));
+            let outgoing = mesh.attach(SocketAddr::new(ip, PORT_NUMBER + 1));
+            let system = System::from_hosts_over(
+                hosts.clone(), host, PORT_NUMBER, MessageCodec::datagram(), incoming, outgoing
+            ).await.unwrap();
+            let (tx, rx) = mpsc::channel(8);
+            let config = PaxosConfig::builder(system.pid(), system.nodes())
+                // this test case finishes the protocol once view 1 is installed
+                .schedule(TestCase::NormalCase)
+                .progress_timer_length(2)
+                .view_installed(Some(tx))
+                .build()
+                .unwrap();
+            runs.push(Box::pin(system.paxos(config)));
+            notifications.push(rx);
+        }
+
+        // every node times out of view 0 together, and they all settle on view 1
+        for res in future::join_all(runs).await {
+            res.unwrap();
+        }
+        for mut rx in notifications {
+            assert_eq!(rx.recv().await, Some(ViewInstalled { view: 1, leader: 1 }));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Poll;
+use futures::task::{Context, Waker};
+use tokio::timer;
+
+/// A future that completes once a clock reaches some deadline.
+pub type ClockDelay = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A source of time for the protocol's timers, so that they can be driven by something other than
+/// the wall clock, e.g. to make tests reproducible.
+pub trait Clock {
+    /// Gets the current time according to this clock.
+    fn now(&self) -> Instant;
+
+    /// Creates a delay that completes once this clock reaches the given deadline.
+    fn delay_until(&self, deadline: Instant) -> ClockDelay;
+}
+
+/// The wall clock, driven by tokio's timer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay_until(&self, deadline: Instant) -> ClockDelay {
+        Box::pin(timer::delay(deadline))
+    }
+}
+
+/// A clock that stands still until it's advanced by hand. Clones share the same time, so one can
+/// be handed to `Paxos` while another advances it.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    /// the shared state of the clock
+    inner: Arc<Mutex<MockState>>,
+}
+
+/// The state shared by every clone of a `MockClock`.
+#[derive(Debug)]
+struct MockState {
+    /// the current time of the clock
+    now: Instant,
+    /// the wakers of delays that were still pending when they were last polled
+    waiters: Vec<Waker>,
+}
+
+impl MockClock {
+    /// Creates a clock starting at the current time.
+    pub fn new() -> MockClock {
+        let state = MockState { now: Instant::now(), waiters: vec![] };
+        MockClock { inner: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Moves the clock forward by the given duration, waking any delays that might have completed.
+    pub fn advance(&self, by: Duration) {
+        let waiters = {
+            let mut state = self.inner.lock().unwrap();
+            state.now += by;
+            std::mem::replace(&mut state.waiters, vec![])
+        };
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn delay_until(&self, deadline: Instant) -> ClockDelay {
+        Box::pin(MockDelay { clock: self.clone(), deadline })
+    }
+}
+
+/// A delay until a `MockClock` is advanced past its deadline.
+struct MockDelay {
+    /// the clock being waited on
+    clock: MockClock,
+    /// the time at which the delay completes
+    deadline: Instant,
+}
+
+impl Future for MockDelay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.clock.inner.lock().unwrap();
+        if state.now >= self.deadline {
+            return Poll::Ready(())
+        }
+        state.waiters.push(ctx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// A timer driven by a `Clock` that fires once every period, in place of an `Interval`.
+pub(crate) struct Ticker {
+    /// the time between firings
+    period: Duration,
+    /// a delay until the next firing
+    delay: ClockDelay,
+}
+
+impl Ticker {
+    /// Creates a ticker that first fires one period from now.
+    pub(crate) fn new(clock: &dyn Clock, period: Duration) -> Ticker {
+        Ticker { period, delay: clock.delay_until(clock.now() + period) }
+    }
+
+    /// Polls whether the ticker has fired, scheduling its next firing one period from now if so.
+    pub(crate) fn poll_tick(&mut self, clock: &dyn Clock, ctx: &mut Context<'_>) -> Poll<()> {
+        match self.delay.as_mut().poll(ctx) {
+            Poll::Ready(()) => {
+                self.delay = clock.delay_until(clock.now() + self.period);
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
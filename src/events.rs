@@ -0,0 +1,189 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, LineWriter};
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fehler::throws;
+use log::{trace, warn};
+
+/// The syslog facility that events are sent under, which is local0.
+const SYSLOG_FACILITY: u8 = 16;
+
+/// A protocol transition recorded in the event log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// the node started a view change to its last attempted view
+    ViewChangeStarted,
+    /// the node found a majority attempting to install its last attempted view
+    QuorumReached,
+    /// the node installed its last attempted view
+    ViewInstalled,
+    /// the node received a proof that another node installed a view
+    ProofReceived,
+    /// the node's progress timer expired on too many view changes in a row without any view
+    /// installing in between
+    LivelockSuspected,
+    /// the node's progress timer expired on a full rotation of view changes, one led by every
+    /// node, without any view installing, so more nodes failed than a quorum can do without
+    FailureBudgetExceeded,
+}
+
+impl Event {
+    /// Gets the name of the event as it appears in the log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ViewChangeStarted => "view_change_started",
+            Event::QuorumReached => "quorum_reached",
+            Event::ViewInstalled => "view_installed",
+            Event::ProofReceived => "proof_received",
+            Event::LivelockSuspected => "livelock_suspected",
+            Event::FailureBudgetExceeded => "failure_budget_exceeded",
+        }
+    }
+
+    /// Gets the syslog severity of the event, which is a warning for the events that mean the
+    /// protocol is stuck, and informational otherwise.
+    fn severity(&self) -> u8 {
+        match self {
+            Event::LivelockSuspected | Event::FailureBudgetExceeded => 4,
+            _ => 6,
+        }
+    }
+}
+
+/// A remote syslog collector that events are sent to over UDP.
+struct Syslog {
+    /// the socket the events are sent from, which never blocks
+    socket: UdpSocket,
+    /// the address of the collector
+    collector: SocketAddr,
+    /// the hostname of the local node, as it appears in the messages
+    hostname: String,
+}
+
+/// An opt-in log of protocol transitions, written as one JSON object per line so that the runs of
+/// several nodes can be reconstructed and compared mechanically, and optionally also sent to a
+/// remote syslog collector in the RFC 5424 format.
+pub struct EventLog {
+    /// where to write events, or `None` if the log is disabled
+    writer: Option<Box<dyn Write + Send>>,
+    /// the collector to send events to, if any
+    syslog: Option<Syslog>,
+}
+
+impl EventLog {
+    /// Creates an event log that discards every event.
+    pub fn disabled() -> EventLog {
+        EventLog { writer: None, syslog: None }
+    }
+
+    /// Creates an event log writing to the given writer.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> EventLog {
+        EventLog { writer: Some(Box::new(writer)), syslog: None }
+    }
+
+    /// Creates an event log appending to a file for the given host in the given directory.
+    #[throws(io::Error)]
+    pub fn in_dir<P: AsRef<Path>>(dir: P, hostname: &str) -> EventLog {
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(format!("{}.events.jsonl", hostname));
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        EventLog::new(LineWriter::new(file))
+    }
+
+    /// Sends every event to the syslog collector at the given address as well, on behalf of the
+    /// given host. Events are sent fire-and-forget, so a collector that can't be reached never
+    /// holds up the protocol.
+    #[throws(io::Error)]
+    pub fn syslog_to(self, collector: SocketAddr, hostname: &str) -> EventLog {
+        let unspecified = match collector {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind((unspecified, 0))?;
+        socket.set_nonblocking(true)?;
+        let syslog = Syslog { socket, collector, hostname: hostname.to_owned() };
+        EventLog { syslog: Some(syslog), ..self }
+    }
+
+    /// Records the given event along with the state of the node. Failing to write an event does
+    /// not fail the protocol, and is only logged.
+    pub fn record(&mut self, event: Event, pid: u32, current_view: u32, last_attempted_view: u32) {
+        if self.writer.is_none() && self.syslog.is_none() { return }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis())
+            .unwrap_or(0);
+        if let Some(ref syslog) = self.syslog {
+            let line = format!(
+                "<{}>1 {} {} prj2 {} {} [paxos@32473 pid=\"{}\" current_view=\"{}\" \
+                 last_attempted_view=\"{}\"] {}",
+                SYSLOG_FACILITY * 8 + event.severity(), rfc3339(timestamp), syslog.hostname,
+                process::id(), event.name(), pid, current_view, last_attempted_view, event.name(),
+            );
+            if let Err(e) = syslog.socket.send_to(line.as_bytes(), syslog.collector) {
+                trace!("failed to send {:?} to syslog at {}: {}", event, syslog.collector, e);
+            }
+        }
+
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => return,
+        };
+        let res = writeln!(
+            writer,
+            "{{\"event\":\"{}\",\"pid\":{},\"current_view\":{},\"last_attempted_view\":{},\
+             \"timestamp\":{}}}",
+            event.name(), pid, current_view, last_attempted_view, timestamp
+        );
+        if let Err(e) = res {
+            warn!("failed to record {:?} in the event log: {}", event, e);
+        }
+    }
+}
+
+/// Formats the given milliseconds since the Unix epoch as an RFC 3339 timestamp in UTC, e.g.
+/// `2019-10-07T18:30:00.250Z`.
+fn rfc3339(millis: u128) -> String {
+    let secs = (millis / 1000) as i64;
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // converts the days since the epoch to a civil date, after Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, secs_of_day / 3600, secs_of_day % 3600 / 60, secs_of_day % 60,
+        millis % 1000,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_formatted_as_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(1_570_473_000_250), "2019-10-07T18:30:00.250Z");
+    }
+
+    #[test]
+    fn leap_days_and_the_days_around_them_are_dated_correctly() {
+        assert_eq!(rfc3339(1_582_934_400_000), "2020-02-29T00:00:00.000Z");
+        // 2000 is a leap year despite being a century, unlike 2100
+        assert_eq!(rfc3339(951_868_799_999), "2000-02-29T23:59:59.999Z");
+        assert_eq!(rfc3339(951_868_800_000), "2000-03-01T00:00:00.000Z");
+        assert_eq!(rfc3339(4_107_542_400_000), "2100-03-01T00:00:00.000Z");
+    }
+}
@@ -0,0 +1,167 @@
+//! paxos-vc is an implementation of the view change (also known as leader election) portion of the
+//! Paxos protocol. The binary runs a single node, but the protocol can also be embedded by driving
+//! `Paxos` (a `Sink` of incoming messages and a `Stream` of timer-driven work) directly.
+
+mod admin;
+mod bounded;
+#[cfg(feature = "capture")]
+mod capture;
+mod channel;
+mod clock;
+mod events;
+mod metrics;
+mod msg;
+mod net;
+mod paxos;
+mod roles;
+mod state;
+
+use std::io;
+use std::str::FromStr;
+
+use fehler::{throw, throws};
+
+pub use crate::admin::{AdminCommand, AdminRequest};
+pub use crate::bounded::{BoundedMap, TRACKING_CAPACITY};
+#[cfg(feature = "capture")]
+pub use crate::capture::{BinaryCapture, Capture, Captured, CapturedDatagram};
+pub use crate::channel::{DatagramChannel, DatagramFramed, MemoryMesh, MeshChannel};
+pub use crate::clock::{Clock, ClockDelay, MockClock, TokioClock};
+pub use crate::events::{Event, EventLog};
+pub use crate::metrics::{Metrics, TrafficCount};
+pub use crate::msg::{
+    AuthKey, GroupedCodec, Message, MessageCodec, Rejected, Sequencing, PROTOCOL_VERSION,
+};
+pub use crate::net::{
+    load_hostfile, read_hostfile, Nodes, ProtocolSocket, RateLimitPolicy, Reachability, System,
+    Topology, Transport, BIND_ADDRESS, BIND_WINDOW, FAILURE_THRESHOLD, OUTGOING_CAPACITY,
+    PORT_NUMBER, RESOLVE_TIMEOUT, SEND_RETRIES,
+};
+#[cfg(feature = "inject")]
+pub use crate::net::Injector;
+pub use crate::paxos::{
+    ConfigError, FailureSchedule, LeaderFn, NeverFail, OutputFormat, Paxos, PaxosConfig,
+    PaxosConfigBuilder, SkipSuspected, Successor, ViewInstalled, ViewStrategy, MIN_TIMER_LENGTH,
+};
+pub use crate::state::DurableViews;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestCase {
+    /// Basic change: Start with container 0 as leader (view 0) and successfully change to container
+    /// 1 (view 1) when the timeout was triggered. There is no leader crash in this scenario.
+    NormalCase = 1,
+
+    /// Full rotation: Start with container 0 and successfully do a full rotation of all containers
+    /// as leaders till coming back to container 0 as leader.  That is, first view change switch to
+    /// container 1, second to container 2 and so on.  There is no leader crash in this scenario.
+    FullRotation = 2,
+
+    /// New leader crashes before installing new view: Start with container 0 as leader and when
+    /// timeout triggered start view change to switch to container 1. After receiving enough
+    /// ViewChange messages, container 1 exits and does not complete the protocol. A correct
+    /// implementation should trigger another view change to container 2 and finish the protocol.
+    SingleCrash = 3,
+
+    /// Two cascading failures of new leaders (container 1 and container 2): As above but
+    /// container 2 also crashes in the middle of the protocol after receiving enough View Changes.
+    /// Correct implementation should finish the view change and end up with container 3 as leader.
+    TwoCrashes = 4,
+
+    /// Three cascading failures of new leaders (containers 1, 2, and 3): As above but now there are
+    /// three failed view changes because containers 1, 2, 3 exit before finishing the protocol.
+    /// Correct implementation should block because there are more than 2 failures.
+    ThreeCrashes = 5,
+}
+
+impl Default for TestCase {
+    fn default() -> TestCase {
+        TestCase::NormalCase
+    }
+}
+
+impl FromStr for TestCase {
+    type Err = fehler::Exception;
+
+    #[throws]
+    fn from_str(s: &str) -> TestCase  {
+        match s.parse()? {
+            1u8 => TestCase::NormalCase,
+            2 => TestCase::FullRotation,
+            3 => TestCase::SingleCrash,
+            4 => TestCase::TwoCrashes,
+            5 => TestCase::ThreeCrashes,
+            n => throw!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected a test case from 1 to 5, got {}", n),
+            )),
+        }
+    }
+}
+
+/// Test cases are deserialized from their numbers, as they are parsed from the command line.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TestCase {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<TestCase, D::Error> {
+        use serde::de::{Deserialize, Error, Unexpected};
+
+        Ok(match u8::deserialize(deserializer)? {
+            1 => TestCase::NormalCase,
+            2 => TestCase::FullRotation,
+            3 => TestCase::SingleCrash,
+            4 => TestCase::TwoCrashes,
+            5 => TestCase::ThreeCrashes,
+            n => {
+                let expected = &"a test case from 1 to 5";
+                return Err(D::Error::invalid_value(Unexpected::Unsigned(n.into()), expected));
+            }
+        })
+    }
+}
+
+/// The test cases follow the schedule of the assignment description.
+///
+/// The crash behavior is defined as follows, where crashing nodes crash as soon as they find a
+/// majority attempting to install a view:
+/// ```text
+/// /------------------------------\
+/// | pid | test case  | behavior  |
+/// |------------------------------|
+/// | 1   | 1, 2       | nop       |
+/// | 1   | 3, 4, 5    | crash     |
+/// |------------------------------|
+/// | 2   | 1, 2, 3    | nop       |
+/// | 2   | 4, 5       | crash     |
+/// |------------------------------|
+/// | 3   | 1, 2, 3, 4 | nop       |
+/// | 3   | 5          | crash     |
+/// |------------------------------|
+/// | 4   | *          | nop       |
+/// |------------------------------|
+/// | 5   | *          | nop       |
+/// \------------------------------/
+/// ```
+impl FailureSchedule for TestCase {
+    fn should_crash(&self, pid: u32, _view: u32) -> bool {
+        use TestCase::*;
+
+        match self {
+            SingleCrash => pid == 1,
+            TwoCrashes => pid < 3 && pid > 0,
+            ThreeCrashes => pid < 4 && pid > 0,
+            _ => false,
+        }
+    }
+
+    fn should_exit(&self, _pid: u32, view: u32, leader: u32) -> bool {
+        use TestCase::*;
+
+        match self {
+            NormalCase => view == 1,
+            FullRotation => view != 0 && leader == 0,
+            SingleCrash => view == 2,
+            TwoCrashes => view == 3,
+            ThreeCrashes => view == 4,
+        }
+    }
+}
@@ -1,23 +1,218 @@
-#![feature(never_type)]
-
-mod msg;
-mod net;
-mod paxos;
-
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::{value_t, Arg, App};
-use fehler::throws;
+use clap::{value_t, App, Arg, ArgMatches};
+use fehler::{throw, throws};
 use log::info;
 
-use crate::net::System;
+use prj2::{
+    load_hostfile, AuthKey, EventLog, MessageCodec, Metrics, OutputFormat, PaxosConfig,
+    RateLimitPolicy, Sequencing, SkipSuspected, System, TestCase, BIND_ADDRESS, BIND_WINDOW,
+    FAILURE_THRESHOLD, OUTGOING_CAPACITY, PORT_NUMBER, RESOLVE_TIMEOUT, SEND_RETRIES,
+    TRACKING_CAPACITY,
+};
 
 #[tokio::main]
-async fn main() -> Result<!, fehler::Exception> {
+async fn main() -> Result<(), fehler::Exception> {
+    let matches = cli().get_matches();
+    #[cfg(feature = "config")]
+    let file = match matches.value_of("config") {
+        Some(path) => FileConfig::load(path)?,
+        None => FileConfig::default(),
+    };
+    #[cfg(not(feature = "config"))]
+    let file = FileConfig::default();
+    let settings = file.overridden_by(&matches);
+    let hostname = match settings.hostname {
+        Some(ref hostname) => hostname.as_str(),
+        None => clap::Error::with_description(
+            "a hostname must be set with --name or in the config file",
+            clap::ErrorKind::MissingRequiredArgument,
+        ).exit(),
+    };
+    let hostfile_path = settings.hostfile.unwrap_or_else(|| PathBuf::from("hosts"));
+    let test_case = settings.test_case.unwrap_or_default();
+    let progress_timer_length = settings.progress_timer_length.unwrap_or(3);
+    let vc_proof_timer_length = settings.vc_proof_timer_length.unwrap_or(1);
+    let port = arg::<u16>(&matches, "port").unwrap_or(PORT_NUMBER);
+    let bind = arg::<IpAddr>(&matches, "bind").unwrap_or(BIND_ADDRESS);
+    let outgoing_capacity =
+        arg::<usize>(&matches, "outgoing_capacity").unwrap_or(OUTGOING_CAPACITY);
+    let send_retries = arg::<u32>(&matches, "send_retries").unwrap_or(SEND_RETRIES);
+    let failure_threshold =
+        arg::<u32>(&matches, "failure_threshold").unwrap_or(FAILURE_THRESHOLD);
+    let max_retransmits = arg::<u32>(&matches, "max_retransmits");
+    let quorum_size = arg::<usize>(&matches, "quorum_size");
+    let tolerated_failures = arg::<u32>(&matches, "tolerated_failures");
+    let ping_interval = arg::<u64>(&matches, "ping_interval");
+    let max_view = arg::<u32>(&matches, "max_view");
+    let initial_view = arg::<u32>(&matches, "initial_view");
+    let livelock_threshold = arg::<u32>(&matches, "livelock_threshold");
+    let state_path = matches.value_of("state_file").map(PathBuf::from);
+    let progress_backoff_multiplier = arg::<u32>(&matches, "progress_backoff").unwrap_or(1);
+    let progress_timer_cap = arg::<u64>(&matches, "progress_timer_cap").unwrap_or(60);
+    let progress_jitter_ms = arg::<u64>(&matches, "progress_jitter").unwrap_or(0);
+    let initial_spread_ms = arg::<u64>(&matches, "initial_spread").unwrap_or(0);
+    let vc_cooldown = Duration::from_millis(arg::<u64>(&matches, "vc_cooldown").unwrap_or(0));
+    let output_format =
+        arg::<OutputFormat>(&matches, "output_format").unwrap_or(OutputFormat::Human);
+    let seed = arg::<u64>(&matches, "seed");
+    let drop_rate = arg::<f64>(&matches, "drop_rate");
+    let multicast_rate = arg::<f64>(&matches, "multicast_rate");
+    let rate_limit_policy =
+        arg::<RateLimitPolicy>(&matches, "rate_limit_policy").unwrap_or(RateLimitPolicy::Queue);
+    let latencies = matches.values_of("latency").into_iter().flatten()
+        .map(parse_latency)
+        .collect::<io::Result<Vec<_>>>()?;
+    let metrics_port = arg::<u16>(&matches, "metrics_port");
+    let tracking_capacity =
+        arg::<usize>(&matches, "tracking_capacity").unwrap_or(TRACKING_CAPACITY);
+    let re_resolve_interval = arg::<u64>(&matches, "re_resolve_interval");
+    let resolve_timeout = arg::<u64>(&matches, "resolve_timeout")
+        .map_or(RESOLVE_TIMEOUT, Duration::from_secs);
+    let bind_window = arg::<u64>(&matches, "bind_window").map_or(BIND_WINDOW, Duration::from_secs);
+    let auth_key = match matches.value_of("auth_key") {
+        Some(path) => Some(AuthKey::load(path)?),
+        None => None,
+    };
+    let codec = MessageCodec {
+        checksum: matches.is_present("checksum"),
+        auth_key,
+        sequencing: if matches.is_present("sequenced") {
+            Some(Arc::new(Sequencing::with_capacity(tracking_capacity)))
+        } else {
+            None
+        },
+        varint: matches.is_present("varint"),
+        ..MessageCodec::datagram()
+    };
+
+    let mut logger = flexi_logger::Logger::with_env_or_str("info");
+    if let Some(logfile) = settings.log_dir {
+        logger = logger.log_to_file().directory(logfile).discriminant(hostname);
+    }
+    logger.start()?;
+
+    let hostfile = load_hostfile(&hostfile_path)?;
+    info!("loaded hostfile: {}", hostfile_path.display());
+    if matches.is_present("topology") {
+        let topology = System::topology(hostfile, hostname, bind, port, resolve_timeout).await?;
+        print!("{}", topology);
+        process::exit(0)
+    }
+    let mut system = System::from_hosts_within(
+        hostfile, hostname, bind, port, codec, resolve_timeout, bind_window
+    ).await?;
+    // there's nothing more to read from stdin, so only a hostfile elsewhere can be reloaded
+    #[cfg(unix)]
+    {
+        if hostfile_path != Path::new("-") {
+            system.reload_on_hangup(hostfile_path)?;
+        }
+    }
+    system.set_outgoing_capacity(outgoing_capacity);
+    system.set_send_retries(send_retries);
+    system.set_failure_threshold(failure_threshold);
+    if let Some(rate) = drop_rate {
+        system.drop_outgoing(rate, seed)?;
+    }
+    if let Some(rate) = multicast_rate {
+        system.limit_multicast_rate(rate, rate_limit_policy)?;
+    }
+    for (target, latency) in latencies {
+        system.delay_outgoing(target, latency)?;
+    }
+    #[cfg(feature = "capture")]
+    let mut system = {
+        let mut system = system;
+        if let Some(path) = matches.value_of("record_file") {
+            system.record_to(path)?;
+        }
+        if let Some(path) = matches.value_of("replay_file") {
+            system.replay_from(path)?;
+        }
+        if let Some(path) = matches.value_of("capture_file") {
+            system.capture_to(path)?;
+        }
+        if let Some(path) = matches.value_of("replay_bin_file") {
+            system.replay_binary_from(path)?;
+        }
+        system
+    };
+    if matches.is_present("check") {
+        info!("created system, checking connectivity");
+        let report = system.check(CHECK_TIMEOUT).await?;
+        print!("{}", report);
+        process::exit(report.exit_code())
+    }
+    info!("created system, starting paxos");
+    if let Some(secs) = re_resolve_interval {
+        system.re_resolve_every(Duration::from_secs(secs));
+    }
+    let metrics = Arc::new(Metrics::with_rtt_capacity(tracking_capacity));
+    system.count_traffic(metrics.clone());
+    let mut events = match matches.value_of("events_dir") {
+        Some(dir) => EventLog::in_dir(dir, hostname)?,
+        None => EventLog::disabled(),
+    };
+    if let Some(collector) = arg::<SocketAddr>(&matches, "syslog") {
+        events = events.syslog_to(collector, hostname)?;
+    }
+    if let Some(port) = metrics_port {
+        system.serve_metrics(metrics.clone(), port);
+    }
+    #[cfg(unix)]
+    {
+        if let Some(path) = matches.value_of("admin_socket") {
+            system.serve_admin_on(path);
+        }
+    }
+    let mut builder = PaxosConfig::builder(system.pid(), system.nodes());
+    // serving keeps the builder's schedule, which never crashes or finishes, so it runs for good
+    if !matches.is_present("serve") {
+        builder = builder.schedule(test_case);
+    }
+    if matches.is_present("skip_suspected") {
+        builder = builder.view_strategy(SkipSuspected);
+    }
+    let config = builder
+        .progress_timer_length(progress_timer_length)
+        .progress_backoff_multiplier(progress_backoff_multiplier)
+        .progress_timer_cap(progress_timer_cap)
+        .vc_proof_timer_length(vc_proof_timer_length)
+        .max_retransmits(max_retransmits)
+        .quorum_size(quorum_size)
+        .tolerated_failures(tolerated_failures)
+        .leader_only_proofs(matches.is_present("leader_only_proofs"))
+        .ping_interval(ping_interval)
+        .max_view(max_view)
+        .initial_view(initial_view)
+        .livelock_threshold(livelock_threshold)
+        .stop_when_blocked(matches.is_present("stop_when_blocked"))
+        .state_path(state_path)
+        .progress_jitter_ms(progress_jitter_ms)
+        .initial_spread_ms(initial_spread_ms)
+        .view_change_cooldown(vc_cooldown)
+        .seed(seed)
+        .metrics(metrics)
+        .events(events)
+        .output_format(output_format)
+        .build()?;
+    system.paxos(config).await
+}
+
+/// How long `--check` waits for every peer to answer.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Describes the command line interface of the binary.
+fn cli() -> App<'static, 'static> {
     let cli = App::new("paxos-vc")
         .version("1.0")
         .author("Aaron Weiss <awe@pdgn.co>")
@@ -29,13 +224,12 @@ async fn main() -> Result<!, fehler::Exception> {
                 .value_name("HOSTNAME")
                 .help("Sets the hostname of the current process")
                 .takes_value(true)
-                .required(true)
         ).arg(
             Arg::with_name("hostfile")
                 .short("h")
                 .long("hosts")
                 .value_name("HOSTFILE")
-                .help("Sets the configuration for all hosts in the system")
+                .help("Sets the configuration for all hosts in the system, read from stdin if -")
         ).arg(
             Arg::with_name("test_case")
                 .short("t")
@@ -43,19 +237,24 @@ async fn main() -> Result<!, fehler::Exception> {
                 .value_name("TEST_CASE")
                 .help("Sets which test case to run, based on assignment description")
                 .takes_value(true)
+        ).arg(
+            Arg::with_name("serve")
+                .long("serve")
+                .conflicts_with("test_case")
+                .help("Runs as a long-lived service, never crashing or exiting on reaching a view")
         ).arg(
             Arg::with_name("progress_timer_length")
                 .short("p")
                 .long("progress")
                 .value_name("SECONDS")
-                .help("Sets the amount for the progress timer in seconds, defaults to 15 seconds")
+                .help("Sets the amount for the progress timer in seconds, defaults to 3 seconds")
                 .takes_value(true)
         ).arg(
             Arg::with_name("vc_proof_timer_length")
                 .short("v")
                 .long("vcproof")
                 .value_name("SECONDS")
-                .help("Sets the amount for the vc proof timer in seconds, defaults to 3 seconds")
+                .help("Sets the amount for the vc proof timer in seconds, defaults to 1 second")
                 .takes_value(true)
         ).arg(
             Arg::with_name("log_dir")
@@ -64,80 +263,435 @@ async fn main() -> Result<!, fehler::Exception> {
                 .value_name("LOGDIR")
                 .help("Sets the folder to dump logs into, defaults to stderr if unset")
                 .takes_value(true)
+        ).arg(
+            Arg::with_name("events_dir")
+                .short("e")
+                .long("events")
+                .value_name("EVENTDIR")
+                .help("Sets the folder to write JSON protocol events into, disabled if unset")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("syslog")
+                .long("syslog")
+                .value_name("ADDR")
+                .help("Sends protocol events to the syslog collector at ADDR over UDP (RFC 5424)")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("output_format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .possible_values(&["human", "json"])
+                .help("Outputs each new leader as a sentence or a JSON object, defaults to human")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("resolve_timeout")
+                .long("resolve-timeout")
+                .value_name("SECONDS")
+                .help("Gives up on resolving the hosts at startup after SECONDS, defaults to 300")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("bind_window")
+                .long("bind-window")
+                .value_name("SECONDS")
+                .help("Retries binding ports still in use for up to SECONDS, defaults to 10")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("re_resolve_interval")
+                .long("reresolve")
+                .value_name("SECONDS")
+                .help("Re-resolves every hostname on this interval, disabled if unset")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("port")
+                .short("P")
+                .long("port")
+                .value_name("PORT")
+                .help("Sets the port to receive on (sending from the next), defaults to 42069")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("bind")
+                .long("bind")
+                .value_name("ADDRESS")
+                .help("Sets the local address to bind to, defaults to 0.0.0.0")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("outgoing_capacity")
+                .short("q")
+                .long("queue")
+                .value_name("MESSAGES")
+                .help("Sets how many outgoing messages can be queued at once, defaults to 1024")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("send_retries")
+                .long("send-retries")
+                .value_name("RETRIES")
+                .help("Re-sends datagrams up to RETRIES times on transient failures, defaults to 3")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("failure_threshold")
+                .long("failure-threshold")
+                .value_name("FAILURES")
+                .help("Suspects a node is dead after FAILURES failures in a row, defaults to 3")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("max_retransmits")
+                .short("r")
+                .long("reliable")
+                .value_name("RETRIES")
+                .help("Resends unacknowledged view changes up to RETRIES times, must match peers")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("quorum_size")
+                .long("quorum")
+                .value_name("SIZE")
+                .help("Installs views once SIZE nodes attempt them, defaults to a majority")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("tolerated_failures")
+                .long("failures")
+                .value_name("F")
+                .help("Tolerates F failed nodes with a quorum of F + 1, requiring 2F + 1 nodes")
+                .takes_value(true)
+                .conflicts_with("quorum_size")
+        ).arg(
+            Arg::with_name("leader_only_proofs")
+                .long("leader-proofs")
+                .help("Only sends vc proofs on the timer from the leader, rather than every node")
+        ).arg(
+            Arg::with_name("ping_interval")
+                .long("ping")
+                .value_name("SECONDS")
+                .help("Measures round trip times to every node on this interval, disabled if unset")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("max_view")
+                .long("max-view")
+                .value_name("VIEW")
+                .help("Stops starting view changes past VIEW, unbounded if unset")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("initial_view")
+                .long("initial-view")
+                .value_name("VIEW")
+                .help("Starts out with VIEW installed rather than view 0, must match all hosts")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("stop_when_blocked")
+                .long("stop-when-blocked")
+                .help("Stops timing out once a full rotation of leaders fails to take over")
+        ).arg(
+            Arg::with_name("skip_suspected")
+                .long("skip-suspected")
+                .help("Skips past views led by nodes suspected dead when timing out")
+        ).arg(
+            Arg::with_name("livelock_threshold")
+                .long("livelock")
+                .value_name("COUNT")
+                .help("Warns of a suspected livelock after COUNT failed view changes in a row")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("progress_backoff")
+                .short("b")
+                .long("backoff")
+                .value_name("MULTIPLIER")
+                .help("Grows the progress timer by this factor after each failed view change")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("progress_timer_cap")
+                .long("backoff-cap")
+                .value_name("SECONDS")
+                .help("Sets the maximum the progress timer backs off to, defaults to 60 seconds")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("progress_jitter")
+                .short("j")
+                .long("jitter")
+                .value_name("MILLISECONDS")
+                .help("Adds up to this much random jitter to the progress timer, defaults to none")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("initial_spread")
+                .long("initial-spread")
+                .value_name("MILLISECONDS")
+                .help("Spreads the first timeouts of the nodes across this long by pid and seed")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("vc_cooldown")
+                .long("vc-cooldown")
+                .value_name("MILLISECONDS")
+                .help("Coalesces repeated view changes started within this long, defaults to none")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seeds the progress timer jitter, defaults to a random seed")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("drop_rate")
+                .long("drop-rate")
+                .value_name("PROBABILITY")
+                .help("Drops outgoing datagrams with this probability, seeded by --seed if set")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("multicast_rate")
+                .long("multicast-rate")
+                .value_name("PER_SECOND")
+                .help("Limits outgoing multicasts to this many per second, defaults to no limit")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("rate_limit_policy")
+                .long("rate-limit-policy")
+                .value_name("POLICY")
+                .possible_values(&["drop", "queue"])
+                .help("Drops or queues multicasts past --multicast-rate, defaults to queue")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("latency")
+                .long("latency")
+                .value_name("NODE:MILLISECONDS")
+                .help("Delays every datagram sent to the node with index NODE, may be repeated")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        ).arg(
+            Arg::with_name("state_file")
+                .short("s")
+                .long("state")
+                .value_name("STATEFILE")
+                .help("Sets the file to persist installed views in and recover them from")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("metrics_port")
+                .short("m")
+                .long("metrics")
+                .value_name("PORT")
+                .help("Serves Prometheus metrics over HTTP on PORT, disabled if unset")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("checksum")
+                .short("c")
+                .long("checksum")
+                .help("Appends and verifies a checksum on every message, must match all hosts")
+        ).arg(
+            Arg::with_name("varint")
+                .long("varint")
+                .help("Encodes message fields as varints to shrink datagrams, must match all hosts")
+        ).arg(
+            Arg::with_name("sequenced")
+                .long("sequenced")
+                .help("Numbers every message to detect reordering and loss, must match all hosts")
+        ).arg(
+            Arg::with_name("tracking_capacity")
+                .long("tracking-capacity")
+                .value_name("ENTRIES")
+                .help("Tracks sequence numbers and RTTs for up to ENTRIES nodes, defaults to 1024")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("auth_key")
+                .long("auth-key")
+                .value_name("KEYFILE")
+                .help("Authenticates messages with the secret in KEYFILE, must match all hosts")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Checks that every peer answers a view query, then exits instead of running")
+        ).arg(
+            Arg::with_name("topology")
+                .long("topology")
+                .help("Prints every host's resolved address and the local pid, then exits")
         );
-    let matches = cli.get_matches();
-    let hostname = matches.value_of("name").unwrap();
-    let hostfile_path = matches.value_of("hostfile").unwrap_or("hosts");
-    let test_case = value_t!(matches, "test_case", TestCase).unwrap_or_default();
-    let progress_timer_length = value_t!(matches, "progress_timer_length", u64).unwrap_or(3);
-    let vc_proof_timer_length = value_t!(matches, "vc_proof_timer_length", u64).unwrap_or(1);
+    #[cfg(unix)]
+    let cli = cli.arg(
+        Arg::with_name("admin_socket")
+            .long("admin-sock")
+            .value_name("PATH")
+            .help("Answers admin commands (view, leader, force-vc, step-down, dump) at socket PATH")
+            .takes_value(true)
+    );
+    #[cfg(feature = "capture")]
+    let cli = cli
+        .arg(
+            Arg::with_name("record_file")
+                .long("record")
+                .value_name("CAPTUREFILE")
+                .help("Records every received message in CAPTUREFILE for replaying later")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("replay_file")
+                .long("replay")
+                .value_name("CAPTUREFILE")
+                .help("Replays the messages recorded in CAPTUREFILE instead of receiving any")
+                .takes_value(true)
+                .conflicts_with("record_file")
+        ).arg(
+            Arg::with_name("capture_file")
+                .long("capture")
+                .value_name("CAPTUREFILE")
+                .help("Records every received message in CAPTUREFILE in the binary wire format")
+                .takes_value(true)
+        ).arg(
+            Arg::with_name("replay_bin_file")
+                .long("replay-bin")
+                .value_name("CAPTUREFILE")
+                .help("Replays the binary capture CAPTUREFILE instead of receiving any messages")
+                .takes_value(true)
+                .conflicts_with_all(&["capture_file", "replay_file"])
+        );
+    #[cfg(feature = "config")]
+    let cli = cli.arg(
+        Arg::with_name("config")
+            .long("config")
+            .value_name("CONFIGFILE")
+            .help("Loads settings from a TOML file, overridden by any flags also passed")
+            .takes_value(true)
+    );
+    cli
+}
 
-    let mut logger = flexi_logger::Logger::with_env_or_str("info");
-    if let Some(logfile) = matches.value_of("log_dir") {
-        logger = logger.log_to_file().directory(logfile).discriminant(hostname);
+/// The settings of a node that can be loaded from a TOML config file rather than passed as flags.
+#[derive(Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(deny_unknown_fields))]
+struct FileConfig {
+    /// the hostname of the current process
+    hostname: Option<String>,
+    /// the hostfile listing every host in the system
+    hostfile: Option<PathBuf>,
+    /// the test case to run
+    test_case: Option<TestCase>,
+    /// the length of the progress timer in seconds
+    progress_timer_length: Option<u64>,
+    /// the length of the vc proof timer in seconds
+    vc_proof_timer_length: Option<u64>,
+    /// the folder to dump logs into
+    log_dir: Option<PathBuf>,
+}
+
+impl FileConfig {
+    /// Loads the settings from the given TOML file.
+    #[cfg(feature = "config")]
+    #[throws]
+    fn load<P: AsRef<Path>>(path: P) -> FileConfig {
+        let mut buffer = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut buffer)?;
+        toml::from_str(&buffer)?
     }
-    logger.start()?;
 
-    let hostfile = load_hostfile(hostfile_path)?;
-    info!("loaded hostfile: {}", hostfile_path);
-    let system = System::from_hosts(hostfile, hostname).await?;
-    info!("created system, starting paxos");
-    system.paxos(test_case, progress_timer_length, vc_proof_timer_length).await
+    /// Replaces any of the settings that were also passed as flags with the flags' values.
+    fn overridden_by(self, matches: &ArgMatches) -> FileConfig {
+        FileConfig {
+            hostname: matches.value_of("name").map(String::from).or(self.hostname),
+            hostfile: matches.value_of("hostfile").map(PathBuf::from).or(self.hostfile),
+            test_case: arg(matches, "test_case").or(self.test_case),
+            progress_timer_length: arg(matches, "progress_timer_length")
+                .or(self.progress_timer_length),
+            vc_proof_timer_length: arg(matches, "vc_proof_timer_length")
+                .or(self.vc_proof_timer_length),
+            log_dir: matches.value_of("log_dir").map(PathBuf::from).or(self.log_dir),
+        }
+    }
 }
 
-#[throws(io::Error)]
-fn load_hostfile<P: AsRef<Path>>(path: P) -> Vec<String> {
-    let mut buffer = String::new();
-    File::open(path)?.read_to_string(&mut buffer)?;
-    buffer.lines().map(|s| s.to_owned()).collect()
+/// Parses the value of the given argument, or gives `None` if it wasn't passed. A value that was
+/// passed but is invalid (e.g. `-p 3s`) is an error, rather than being mistaken for an absent one
+/// and quietly replaced by a default.
+#[throws(clap::Error)]
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    match value_t!(matches, name, T) {
+        Ok(value) => Some(value),
+        Err(ref e) if e.kind == clap::ErrorKind::ArgumentNotFound => None,
+        Err(e) => throw!(e),
+    }
 }
 
-#[repr(u8)]
-pub enum TestCase {
-    /// Basic change: Start with container 0 as leader (view 0) and successfully change to container
-    /// 1 (view 1) when the timeout was triggered. There is no leader crash in this scenario.
-    NormalCase = 1,
-
-    /// Full rotation: Start with container 0 and successfully do a full rotation of all containers
-    /// as leaders till coming back to container 0 as leader.  That is, first view change switch to
-    /// container 1, second to container 2 and so on.  There is no leader crash in this scenario.
-    FullRotation = 2,
-
-    /// New leader crashes before installing new view: Start with container 0 as leader and when
-    /// timeout triggered start view change to switch to container 1. After receiving enough
-    /// ViewChange messages, container 1 exits and does not complete the protocol. A correct
-    /// implementation should trigger another view change to container 2 and finish the protocol.
-    SingleCrash = 3,
-
-    /// Two cascading failures of new leaders (container 1 and container 2): As above but
-    /// container 2 also crashes in the middle of the protocol after receiving enough View Changes.
-    /// Correct implementation should finish the view change and end up with container 3 as leader.
-    TwoCrashes = 4,
-
-    /// Three cascading failures of new leaders (containers 1, 2, and 3): As above but now there are
-    /// three failed view changes because containers 1, 2, 3 exit before finishing the protocol.
-    /// Correct implementation should block because there are more than 2 failures.
-    ThreeCrashes = 5,
+/// Parses the value of the given argument like `parse_arg`, exiting with a usage error if the
+/// value is invalid.
+fn arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    parse_arg(matches, name).unwrap_or_else(|e| e.exit())
 }
 
-impl Default for TestCase {
-    fn default() -> TestCase {
-        TestCase::NormalCase
-    }
+/// Parses a simulated latency of the form `NODE:MILLISECONDS` into the index of the node and the
+/// latency of datagrams sent to it.
+#[throws(io::Error)]
+fn parse_latency(latency: &str) -> (usize, Duration) {
+    let invalid = || io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid latency {}, expected NODE:MILLISECONDS", latency),
+    );
+    let mut parts = latency.splitn(2, ':');
+    let target = parts.next().and_then(|target| target.parse().ok()).ok_or_else(invalid)?;
+    let ms = parts.next().and_then(|ms| ms.parse().ok()).ok_or_else(invalid)?;
+    (target, Duration::from_millis(ms))
 }
 
-impl FromStr for TestCase {
-    type Err = fehler::Exception;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    #[throws]
-    fn from_str(s: &str) -> TestCase  {
-        match s.parse()? {
-            1u8 => TestCase::NormalCase,
-            2 => TestCase::FullRotation,
-            3 => TestCase::SingleCrash,
-            4 => TestCase::TwoCrashes,
-            5 => TestCase::ThreeCrashes,
-            _ => unreachable!(),
+    #[test]
+    fn timer_flags_default_when_absent_but_fail_when_invalid() {
+        let matches = cli().get_matches_from(vec!["paxos-vc", "-v", "2"]);
+        assert_eq!(parse_arg::<u64>(&matches, "progress_timer_length").unwrap(), None);
+        assert_eq!(parse_arg::<u64>(&matches, "vc_proof_timer_length").unwrap(), Some(2));
+
+        for invalid in &["3s", "three", "18446744073709551616"] {
+            let matches = cli().get_matches_from(vec!["paxos-vc", "-p", invalid]);
+            let err = parse_arg::<u64>(&matches, "progress_timer_length").unwrap_err();
+            assert_eq!(err.kind, clap::ErrorKind::ValueValidation, "{}", invalid);
+        }
+
+        // as do test cases that don't exist, rather than panicking
+        for invalid in &["0", "6", "7"] {
+            let matches = cli().get_matches_from(vec!["paxos-vc", "-t", invalid]);
+            let err = parse_arg::<TestCase>(&matches, "test_case").unwrap_err();
+            assert_eq!(err.kind, clap::ErrorKind::ValueValidation, "{}", invalid);
         }
     }
+
+    #[test]
+    fn latency_is_parsed_into_its_node_and_duration() {
+        assert_eq!(parse_latency("2:200").unwrap(), (2, Duration::from_millis(200)));
+        for invalid in &["2", "2:", ":200", "two:200", "2:-1"] {
+            let err = parse_latency(invalid).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", invalid);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "config")]
+    fn flags_override_the_config_file() {
+        use std::env;
+        use std::fs;
+
+        let path = env::temp_dir().join(format!("paxos-config-{}.toml", process::id()));
+        fs::write(&path, "\
+            hostname = \"columba\"\n\
+            hostfile = \"/etc/paxos/hosts\"\n\
+            test_case = 3\n\
+            progress_timer_length = 10\n\
+            vc_proof_timer_length = 2\n\
+            log_dir = \"log\"\n\
+        ").unwrap();
+        let file = FileConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let file = file.unwrap();
+        assert_eq!(file.hostname.as_ref().map(String::as_str), Some("columba"));
+        assert_eq!(file.hostfile, Some(PathBuf::from("/etc/paxos/hosts")));
+        assert_eq!(file.test_case, Some(TestCase::SingleCrash));
+        assert_eq!(file.progress_timer_length, Some(10));
+        assert_eq!(file.vc_proof_timer_length, Some(2));
+        assert_eq!(file.log_dir, Some(PathBuf::from("log")));
+
+        let flags = vec!["paxos-vc", "-n", "raphus", "-t", "4", "-p", "6"];
+        let settings = file.overridden_by(&cli().get_matches_from(flags));
+        assert_eq!(settings.hostname.as_ref().map(String::as_str), Some("raphus"));
+        assert_eq!(settings.hostfile, Some(PathBuf::from("/etc/paxos/hosts")));
+        assert_eq!(settings.test_case, Some(TestCase::TwoCrashes));
+        assert_eq!(settings.progress_timer_length, Some(6));
+        assert_eq!(settings.vc_proof_timer_length, Some(2));
+        assert_eq!(settings.log_dir, Some(PathBuf::from("log")));
+    }
 }
@@ -0,0 +1,314 @@
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use fehler::throws;
+use futures::future;
+use log::{info, trace, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::timer;
+
+use crate::bounded::BoundedMap;
+use crate::msg::Message;
+
+/// The kinds of messages whose traffic is counted, named as by `Message::kind`.
+const MESSAGE_KINDS: [&str; 9] = [
+    "view_change", "vc_proof", "ack", "view_query", "heartbeat", "nack", "reconfig", "ping", "pong",
+];
+
+/// The upper bounds in seconds of the buckets of view change latencies, short of the last bucket,
+/// which holds everything longer.
+const LATENCY_BUCKETS: [f64; 8] = [0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0];
+
+/// Counters of the messages of one kind sent or received, and the bytes they took up on the wire.
+#[derive(Debug, Default)]
+struct Traffic {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// The number of messages of one kind sent or received, and the bytes they took up on the wire.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrafficCount {
+    /// the number of messages
+    pub messages: u64,
+    /// the number of bytes, including any framing and checksums
+    pub bytes: u64,
+}
+
+/// Counters of protocol events, shared between the protocol and the metrics endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// the number of views installed
+    pub views_installed: AtomicU64,
+    /// the number of view changes started
+    pub view_changes_started: AtomicU64,
+    /// the number of vc proofs sent
+    pub proofs_sent: AtomicU64,
+    /// the number of stale messages dropped
+    pub stale_messages: AtomicU64,
+    /// the number of messages rejected for coming from unknown nodes
+    pub rejected_messages: AtomicU64,
+    /// the number of messages rejected for failing authentication
+    pub unauthenticated_messages: AtomicU64,
+    /// the smoothed round trip time to each node that has answered a ping, by index, for the nodes
+    /// that answered most recently
+    rtts: Mutex<BoundedMap<usize, Duration>>,
+    /// the traffic sent of each kind of message, in the order of `MESSAGE_KINDS`
+    sent: [Traffic; 9],
+    /// the traffic received of each kind of message, in the order of `MESSAGE_KINDS`
+    received: [Traffic; 9],
+    /// the number of view change latencies within each bucket (and none before it) of
+    /// `LATENCY_BUCKETS`, followed by the number longer than every bucket
+    install_latencies: [AtomicU64; 9],
+    /// the total of every view change latency recorded, in nanoseconds
+    install_latency_ns: AtomicU64,
+}
+
+impl Metrics {
+    /// Creates counters that keep the round trip times to at most the given number of nodes,
+    /// forgetting the ones that answered least recently.
+    pub fn with_rtt_capacity(capacity: usize) -> Metrics {
+        Metrics { rtts: Mutex::new(BoundedMap::new(capacity)), ..Metrics::default() }
+    }
+
+    /// Increments the given counter by one.
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Folds a round trip time sample to the node with the given index into its smoothed average,
+    /// weighting the new sample by 1/8 like TCP's smoothed round trip time.
+    pub fn record_rtt(&self, node: usize, sample: Duration) {
+        let mut rtts = self.rtts.lock().unwrap();
+        let rtt = rtts.get(&node).cloned().unwrap_or(sample);
+        rtts.insert(node, (rtt * 7 + sample) / 8);
+    }
+
+    /// Gets the smoothed round trip time to the node with the given index, if it has answered a
+    /// ping.
+    pub fn rtt(&self, node: usize) -> Option<Duration> {
+        self.rtts.lock().unwrap().get(&node).cloned()
+    }
+
+    /// Records the time a view change took from being proposed to a quorum installing its view.
+    pub fn record_install_latency(&self, latency: Duration) {
+        let secs = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+        let idx = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        Metrics::incr(&self.install_latencies[idx]);
+        self.install_latency_ns.fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Gets the number of view change latencies recorded.
+    pub fn install_latencies(&self) -> u64 {
+        self.install_latencies.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Counts a message sent to a single node, which took up the given number of bytes.
+    pub fn count_sent(&self, msg: &Message, bytes: usize) {
+        Metrics::count(&self.sent, msg, bytes);
+    }
+
+    /// Counts a message received, which took up the given number of bytes.
+    pub fn count_received(&self, msg: &Message, bytes: usize) {
+        Metrics::count(&self.received, msg, bytes);
+    }
+
+    /// Gets the traffic sent of the given kind of message (e.g. `view_change`).
+    pub fn sent(&self, kind: &str) -> TrafficCount {
+        Metrics::total(&self.sent, kind)
+    }
+
+    /// Gets the traffic received of the given kind of message (e.g. `view_change`).
+    pub fn received(&self, kind: &str) -> TrafficCount {
+        Metrics::total(&self.received, kind)
+    }
+
+    /// Adds a message of the given size to the traffic of its kind.
+    fn count(traffic: &[Traffic], msg: &Message, bytes: usize) {
+        if let Some(idx) = MESSAGE_KINDS.iter().position(|kind| *kind == msg.kind()) {
+            Metrics::incr(&traffic[idx].messages);
+            traffic[idx].bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Gets the traffic of the given kind of message, which is none for unknown kinds.
+    fn total(traffic: &[Traffic], kind: &str) -> TrafficCount {
+        match MESSAGE_KINDS.iter().position(|known| *known == kind) {
+            Some(idx) => TrafficCount {
+                messages: traffic[idx].messages.load(Ordering::Relaxed),
+                bytes: traffic[idx].bytes.load(Ordering::Relaxed),
+            },
+            None => TrafficCount::default(),
+        }
+    }
+
+    /// Renders every counter, along with the traffic of each kind of message, the round trip time
+    /// gauges, and the view change latency histogram, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = [
+            ("paxos_views_installed_total", "Views installed.", &self.views_installed),
+            ("paxos_view_changes_started_total", "View changes started.",
+             &self.view_changes_started),
+            ("paxos_proofs_sent_total", "VC proofs sent.", &self.proofs_sent),
+            ("paxos_stale_messages_total", "Stale messages dropped.", &self.stale_messages),
+            ("paxos_rejected_messages_total", "Messages rejected from unknown nodes.",
+             &self.rejected_messages),
+            ("paxos_unauthenticated_messages_total", "Messages failing authentication.",
+             &self.unauthenticated_messages),
+        ];
+
+        let mut out = String::new();
+        for (name, help, counter) in counters.iter() {
+            // writing to a string cannot fail
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        let traffic = [
+            ("paxos_messages_sent_total", "Messages sent, by kind.", &self.sent, false),
+            ("paxos_bytes_sent_total", "Bytes sent, by kind of message.", &self.sent, true),
+            ("paxos_messages_received_total", "Messages received, by kind.", &self.received, false),
+            ("paxos_bytes_received_total", "Bytes received, by kind of message.", &self.received,
+             true),
+        ];
+        for (name, help, counters, bytes) in traffic.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (kind, counter) in MESSAGE_KINDS.iter().zip(counters.iter()) {
+                let count = if *bytes { &counter.bytes } else { &counter.messages };
+                let _ = writeln!(out, "{}{{kind=\"{}\"}} {}", name, kind,
+                                 count.load(Ordering::Relaxed));
+            }
+        }
+        let _ = writeln!(out, "# HELP paxos_rtt_seconds Smoothed round trip time to each node.");
+        let _ = writeln!(out, "# TYPE paxos_rtt_seconds gauge");
+        let mut rtts: Vec<_> = self.rtts.lock().unwrap().iter()
+            .map(|(node, rtt)| (*node, *rtt))
+            .collect();
+        rtts.sort();
+        for (node, rtt) in rtts {
+            let secs = rtt.as_secs() as f64 + f64::from(rtt.subsec_nanos()) / 1e9;
+            let _ = writeln!(out, "paxos_rtt_seconds{{node=\"{}\"}} {}", node, secs);
+        }
+        let name = "paxos_install_latency_seconds";
+        let _ = writeln!(out, "# HELP {} Time from proposing a view change to a quorum installing \
+                               it.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        // histogram buckets are cumulative, each counting everything up to its bound
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.install_latencies.iter()) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.install_latencies();
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.install_latency_ns.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+        out
+    }
+}
+
+/// How long a client of the metrics endpoint gets to send its request before we hang up on it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before accepting connections again after failing to, such as when we've run
+/// out of file descriptors for them.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Serves the given metrics over HTTP on the given port, answering every request with all of them.
+/// Only fails if the port cannot be bound.
+#[throws(io::Error)]
+pub async fn serve(metrics: Arc<Metrics>, port: u16) -> () {
+    let mut listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    info!("serving metrics on port {}", port);
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("failed to accept metrics connection: {}", e);
+                timer::delay_for(ACCEPT_RETRY_DELAY).await;
+                continue
+            },
+        };
+        trace!("metrics requested by {}", addr);
+        // every connection gets its own task, so that a slow client can't hold up the rest
+        tokio::spawn(answer(metrics.clone(), stream, addr));
+    }
+}
+
+/// Answers the metrics request on the given connection from the given address with all of the
+/// given metrics.
+async fn answer(metrics: Arc<Metrics>, mut stream: TcpStream, addr: SocketAddr) {
+    // we answer every request the same way, so we only read it to be polite to the client
+    let mut request = [0; 1024];
+    let read = Box::pin(stream.read(&mut request));
+    match future::select(read, timer::delay_for(REQUEST_TIMEOUT)).await {
+        future::Either::Left((Ok(_), _)) => (),
+        future::Either::Left((Err(e), _)) => {
+            warn!("failed to read metrics request from {}: {}", addr, e);
+            return
+        },
+        future::Either::Right(_) => {
+            warn!("gave up on metrics request from {} after {:?}", addr, REQUEST_TIMEOUT);
+            return
+        },
+    }
+
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(), body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        warn!("failed to send metrics to {}: {}", addr, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener as StdTcpListener};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn endpoint_serves_the_rendered_metrics() {
+        let metrics = Arc::new(Metrics::default());
+        Metrics::incr(&metrics.views_installed);
+        Metrics::incr(&metrics.views_installed);
+        // borrow a free port from the OS for the endpoint to bind in turn
+        let port = StdTcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()
+            .local_addr().unwrap().port();
+        let served = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(served, port).await {
+                panic!("metrics endpoint failed: {}", e);
+            }
+        });
+
+        // the endpoint binds its port in the background, so we keep trying until it's up
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let _stalled = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => timer::delay_for(Duration::from_millis(10)).await,
+            }
+        };
+        // a client that never sends its request doesn't keep the endpoint from answering others
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&metrics.render()));
+        assert!(response.lines().any(|line| line == "paxos_views_installed_total 2"));
+    }
+}
@@ -1,11 +1,25 @@
-use std::io;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
-use bytes::{Buf, BufMut, BytesMut, IntoBuf};
+use bytes::{Buf, BufMut, BytesMut};
 use fehler::{throw, throws};
-use log::trace;
+use hmac::{Hmac, Mac};
+use log::{info, trace, warn};
+use sha2::{Digest, Sha256};
 use tokio::codec::{Decoder, Encoder};
 
-#[derive(Clone, Copy, Debug)]
+use crate::bounded::BoundedMap;
+
+/// A message in the view change protocol. The binary `MessageCodec` is the canonical wire format,
+/// but with the `serde` feature, messages can also be (de)serialized for tooling, e.g. to dump and
+/// replay traces as JSON.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     /// A message indicating that the given node is attempting to change to the given view.
     ViewChange {
@@ -21,10 +35,653 @@ pub enum Message {
         server_id: u32,
         /// the view installed by the node
         installed: u32,
+        /// the id of the leader of the installed view, recording where the view came from
+        proposer: u32,
+    },
+
+    /// An acknowledgement that the given node received a view change to the given view.
+    Ack {
+        /// the id of the node acknowledging the view change
+        server_id: u32,
+        /// the view of the view change being acknowledged
+        acking: u32,
     },
+
+    /// A request for the receiver to reply with a proof of its installed view, allowing a node
+    /// that is behind (e.g. after joining late or recovering) to catch up immediately.
+    ViewQuery {
+        /// the id of the node asking for the installed view
+        server_id: u32,
+    },
+
+    /// A sign of life from the leader of the given view, keeping its followers from timing out.
+    Heartbeat {
+        /// the id of the leader sending the heartbeat
+        server_id: u32,
+        /// the view the sender leads
+        view: u32,
+    },
+
+    /// A rejection of a view change to a view older than the one the given node has installed,
+    /// letting a sender that fell behind catch up to it.
+    Nack {
+        /// the id of the node rejecting the view change
+        server_id: u32,
+        /// the view installed by the node
+        current_view: u32,
+    },
+
+    /// A vote by the given node to reconfigure the system to consist of the first `members` nodes
+    /// of the hostfile, starting from the given epoch.
+    Reconfig {
+        /// the id of the node voting for the reconfiguration
+        server_id: u32,
+        /// the epoch the reconfiguration would begin
+        epoch: u32,
+        /// the number of nodes that would be members after the reconfiguration
+        members: u32,
+    },
+
+    /// A request for the receiver to reply with a `Pong` right away, measuring the round trip time.
+    Ping {
+        /// the id of the node measuring the round trip time
+        server_id: u32,
+        /// the value identifying this ping, echoed back in the reply
+        nonce: u32,
+    },
+
+    /// A reply to a `Ping` with the same nonce.
+    Pong {
+        /// the id of the node replying
+        server_id: u32,
+        /// the nonce of the ping being replied to
+        nonce: u32,
+    },
+}
+
+impl Message {
+    /// Gets the tag identifying the variant of the message on the wire.
+    fn tag(&self) -> u32 {
+        match self {
+            Message::ViewChange { .. } => 2,
+            Message::VCProof { .. } => 3,
+            Message::Ack { .. } => 4,
+            Message::ViewQuery { .. } => 5,
+            Message::Heartbeat { .. } => 6,
+            Message::Nack { .. } => 7,
+            Message::Reconfig { .. } => 8,
+            Message::Ping { .. } => 9,
+            Message::Pong { .. } => 10,
+        }
+    }
+
+    /// Gets the id of the node that sent the message, which every kind of message carries.
+    pub fn server_id(&self) -> u32 {
+        match *self {
+            Message::ViewChange { server_id, .. } | Message::VCProof { server_id, .. }
+                | Message::Ack { server_id, .. } | Message::ViewQuery { server_id }
+                | Message::Heartbeat { server_id, .. } | Message::Nack { server_id, .. }
+                | Message::Reconfig { server_id, .. } | Message::Ping { server_id, .. }
+                | Message::Pong { server_id, .. } => server_id,
+        }
+    }
+
+    /// Gets the name of the kind of the message, as it's labeled in the metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::ViewChange { .. } => "view_change",
+            Message::VCProof { .. } => "vc_proof",
+            Message::Ack { .. } => "ack",
+            Message::ViewQuery { .. } => "view_query",
+            Message::Heartbeat { .. } => "heartbeat",
+            Message::Nack { .. } => "nack",
+            Message::Reconfig { .. } => "reconfig",
+            Message::Ping { .. } => "ping",
+            Message::Pong { .. } => "pong",
+        }
+    }
+}
+
+/// Messages are displayed compactly (e.g. `VC srv=1 att=2`) for reading alongside raw datagrams.
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::ViewChange { server_id, attempted } =>
+                write!(f, "VC srv={} att={}", server_id, attempted),
+            Message::VCProof { server_id, installed, proposer } =>
+                write!(f, "PROOF srv={} inst={} prop={}", server_id, installed, proposer),
+            Message::Ack { server_id, acking } => write!(f, "ACK srv={} ack={}", server_id, acking),
+            Message::ViewQuery { server_id } => write!(f, "QUERY srv={}", server_id),
+            Message::Heartbeat { server_id, view } =>
+                write!(f, "HB srv={} view={}", server_id, view),
+            Message::Nack { server_id, current_view } =>
+                write!(f, "NACK srv={} view={}", server_id, current_view),
+            Message::Reconfig { server_id, epoch, members } =>
+                write!(f, "RECONFIG srv={} epoch={} members={}", server_id, epoch, members),
+            Message::Ping { server_id, nonce } =>
+                write!(f, "PING srv={} nonce={}", server_id, nonce),
+            Message::Pong { server_id, nonce } =>
+                write!(f, "PONG srv={} nonce={}", server_id, nonce),
+        }
+    }
+}
+
+/// The version of the wire format, which prefixes every message. Messages from peers speaking any
+/// other version are skipped.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// The error payload for a message that arrived whole but was rejected by the codec (e.g. for a
+/// bad checksum). Unlike other decoding errors, these only concern the one message in question,
+/// which has already been consumed, and so the stream can safely continue past them.
+#[derive(Debug)]
+pub struct Rejected(pub &'static str);
+
+impl Rejected {
+    /// Determines whether or not the given error is a rejected message.
+    pub fn is_rejected(err: &io::Error) -> bool {
+        err.get_ref().map_or(false, |inner| inner.is::<Rejected>())
+    }
+
+    /// Determines whether or not the given error is a message rejected for failing authentication.
+    pub fn is_unauthenticated(err: &io::Error) -> bool {
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<Rejected>())
+            .map_or(false, |rejected| rejected.0 == UNAUTHENTICATED)
+    }
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "message rejected: {}", self.0)
+    }
+}
+
+impl Error for Rejected {}
+
+/// Creates an error for a rejected message.
+fn rejected(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Rejected(reason))
+}
+
+/// The reason messages that fail authentication are rejected.
+const UNAUTHENTICATED: &str = "authentication failed";
+
+/// The length of the HMAC-SHA256 tag following every message when authentication is enabled.
+const AUTH_TAG_LEN: usize = 32;
+
+/// A secret shared by every node for authenticating messages. The secret is kept as its SHA-256
+/// digest, so that the key is a fixed size (and the codec holding it stays `Copy`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AuthKey([u8; 32]);
+
+impl AuthKey {
+    /// Creates a key from the given secret.
+    pub fn new(secret: &[u8]) -> AuthKey {
+        let mut key = [0; 32];
+        key.copy_from_slice(&Sha256::digest(secret));
+        AuthKey(key)
+    }
+
+    /// Loads a key from the secret in the given file, ignoring trailing whitespace (e.g. the
+    /// newline ending the file). Fails with `InvalidData` if the file holds no secret.
+    #[throws(io::Error)]
+    pub fn load<P: AsRef<Path>>(path: P) -> AuthKey {
+        let mut secret = fs::read(path)?;
+        while secret.last().map_or(false, |byte| byte.is_ascii_whitespace()) {
+            secret.pop();
+        }
+        if secret.is_empty() {
+            throw!(io::Error::new(io::ErrorKind::InvalidData, "the auth key file is empty"))
+        }
+        AuthKey::new(&secret)
+    }
+
+    /// Computes the HMAC-SHA256 of the given bytes under this key.
+    fn mac(&self, bytes: &[u8]) -> Hmac<Sha256> {
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.0).expect("HMAC rejected its key");
+        mac.input(bytes);
+        mac
+    }
 }
 
-pub struct MessageCodec;
+/// The key is a secret, and so it's left out of debugging output (e.g. of the codec).
+impl fmt::Debug for AuthKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AuthKey(..)")
+    }
+}
+
+/// The sequence numbers stamped on the messages a node sends, and those seen on the messages it
+/// receives, for detecting when the network reorders or loses messages between a pair of nodes.
+/// This is only for observation: messages are processed the same whatever their sequence numbers.
+#[derive(Debug, Default)]
+pub struct Sequencing {
+    /// the sequence number of the next message sent
+    next: AtomicU32,
+    /// the highest sequence number received from each node, by server id, for the nodes heard from
+    /// most recently
+    highest: Mutex<BoundedMap<u32, u32>>,
+    /// the number of messages received with a sequence number no higher than one already seen
+    /// from the same node, i.e. that arrived late (or twice)
+    pub out_of_order: AtomicU64,
+    /// the number of sequence numbers skipped over by messages received, i.e. of messages that
+    /// were lost (or have yet to arrive late)
+    pub missing: AtomicU64,
+}
+
+impl Sequencing {
+    /// Creates sequencing that tracks the sequence numbers received from at most the given number
+    /// of nodes, forgetting the ones heard from least recently.
+    pub fn with_capacity(capacity: usize) -> Sequencing {
+        Sequencing { highest: Mutex::new(BoundedMap::new(capacity)), ..Sequencing::default() }
+    }
+
+    /// Takes the sequence number of the next message sent.
+    fn stamp(&self) -> u32 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Notes a message received from the given node with the given sequence number, counting it
+    /// if it arrived out of order and counting the sequence numbers it skipped over if any.
+    ///
+    /// Sequence numbers are compared as serial numbers, so that they keep counting up as they wrap
+    /// past `u32::MAX`, and a jump back further than `REORDER_WINDOW` is taken as the sender having
+    /// restarted its numbering, which we then follow from wherever it restarted.
+    fn observe(&self, sender: u32, seq: u32) {
+        let mut highest = self.highest.lock().unwrap();
+        match highest.get(&sender).cloned() {
+            Some(prev) if (seq.wrapping_sub(prev) as i32) < -(REORDER_WINDOW as i32) => {
+                info!("sequence numbers from {} restarted at {} after {}", sender, seq, prev);
+                highest.insert(sender, seq);
+            },
+            Some(prev) if (seq.wrapping_sub(prev) as i32) <= 0 => {
+                warn!("message {} from {} arrived out of order after {}", seq, sender, prev);
+                self.out_of_order.fetch_add(1, Ordering::Relaxed);
+            },
+            Some(prev) => {
+                let skipped = seq.wrapping_sub(prev) - 1;
+                if skipped > 0 {
+                    warn!("{} messages from {} missing before {}", skipped, sender, seq);
+                    self.missing.fetch_add(u64::from(skipped), Ordering::Relaxed);
+                }
+                highest.insert(sender, seq);
+            },
+            None => {
+                highest.insert(sender, seq);
+            },
+        }
+    }
+}
+
+/// How far back a sequence number may be from the highest one seen from its sender for its message
+/// to count as merely arriving out of order, rather than the sender having restarted.
+const REORDER_WINDOW: u32 = 1024;
+
+/// The longest frame accepted over stream transports, which is as much as a single datagram could
+/// ever hold, so that a corrupt length prefix can't make us buffer without bound.
+const MAX_FRAME_LEN: usize = 65_535;
+
+/// A codec for the binary wire format of `Message`s.
+///
+/// Over datagram transports like UDP, every datagram holds whole messages back to back. Over
+/// stream transports like TCP, reads may split or coalesce messages arbitrarily, and so each
+/// message is instead prefixed by a `u32` holding the length of the message that follows it, which
+/// is at most `MAX_FRAME_LEN`.
+///
+/// Every message begins with a single byte holding the `PROTOCOL_VERSION` it was encoded with,
+/// then (after its sequence number, if any) a `u32` tag identifying its variant and its fields.
+/// Fields are `u32`s, unless varints are enabled, in which case each is LEB128 encoded into one to
+/// five bytes, shrinking the small views and ids of small clusters. Both ends must agree on the
+/// setting.
+///
+/// When checksums are enabled, every message is followed by a `u32` CRC32 of its bytes, and
+/// messages that fail verification are rejected. Both ends must agree on the setting.
+///
+/// When authentication is enabled, every message (and its checksum, if any) is followed by an
+/// HMAC-SHA256 of its bytes under a shared `AuthKey`, and messages that fail verification are
+/// rejected, so that only nodes holding the key can take part in the protocol.
+///
+/// When sequencing is enabled, the version is followed by a `u32` sequence number, counting up
+/// from zero across every message the node sends, which receivers use to detect reordering and
+/// loss. Clones of a codec share the same `Sequencing`. Both ends must agree on the setting.
+///
+/// When groups are enabled, the version is followed (before any sequence number) by the `u32` id
+/// of the group the message belongs to, so that several independent groups can share a socket.
+/// Messages from any other group are rejected, except by a `GroupedCodec`, which decodes every
+/// group's messages along with their ids. Both ends must agree on the setting.
+#[derive(Clone, Debug, Default)]
+pub struct MessageCodec {
+    /// whether or not every message is prefixed by its length
+    pub length_delimited: bool,
+    /// whether or not every message is followed by a checksum
+    pub checksum: bool,
+    /// the key to authenticate every message with, if any
+    pub auth_key: Option<AuthKey>,
+    /// the sequence numbers of messages sent and received, if every message carries one
+    pub sequencing: Option<Arc<Sequencing>>,
+    /// whether or not the fields of every message are varints, rather than fixed `u32`s
+    pub varint: bool,
+    /// the group every message is sent in and must belong to, if groups share the socket
+    pub group_id: Option<u32>,
+}
+
+impl MessageCodec {
+    /// Creates a codec for datagram transports.
+    pub fn datagram() -> MessageCodec {
+        MessageCodec { length_delimited: false, ..MessageCodec::default() }
+    }
+
+    /// Creates a codec for stream transports, where every message is length-prefixed.
+    pub fn length_delimited() -> MessageCodec {
+        MessageCodec { length_delimited: true, ..MessageCodec::default() }
+    }
+
+    /// Computes the number of bytes the given message takes up on the wire with this codec,
+    /// including its length prefix, group id, sequence number, checksum, and authentication tag, if
+    /// enabled.
+    pub fn encoded_len(&self, msg: Message) -> usize {
+        let mut buf = BytesMut::new();
+        // measuring doesn't take up a sequence number, since every one takes up the same space
+        self.encode_stamped(msg, self.sequencing.as_ref().map(|_| 0), &mut buf);
+        buf.len()
+    }
+
+    /// Describes the messages in the given raw bytes (e.g. from a packet capture) as this codec
+    /// would decode them, giving the tag and fields of each, along with any error or leftover bytes
+    /// that stopped the decoding.
+    pub fn describe_bytes(&self, bytes: &[u8]) -> String {
+        // describing bytes shouldn't count towards the sequence numbers actually received
+        let mut codec = MessageCodec {
+            sequencing: self.sequencing.as_ref().map(|_| Arc::default()),
+            ..self.clone()
+        };
+        let mut src = BytesMut::from(bytes);
+        let mut parts = Vec::new();
+        loop {
+            match codec.decode(&mut src) {
+                Ok(Some(msg)) => parts.push(format!("tag={} {}", msg.tag(), msg)),
+                Ok(None) => break,
+                Err(e) => {
+                    parts.push(format!("<{}>", e));
+                    // rejected messages are consumed, but other errors leave the bytes in place
+                    if !Rejected::is_rejected(&e) { break }
+                },
+            }
+        }
+        if !src.is_empty() {
+            parts.push(format!("<{} trailing bytes>", src.len()));
+        }
+        parts.join("; ")
+    }
+
+    /// Decodes a single message, verifying its checksum and authenticating it if enabled, or `None`
+    /// if it is incomplete. A message failing verification is still consumed, but is produced as
+    /// an error. A verified message's sequence number is observed, if sequencing is enabled, and
+    /// it is produced along with its group id, if it carries one.
+    #[throws(io::Error)]
+    fn decode_verified(
+        &self, buf: &mut Cursor<&[u8]>, grouped: bool
+    ) -> Option<io::Result<(Option<u32>, Message)>> {
+        let start = buf.position() as usize;
+        if buf.remaining() < 1 { return None }
+        let version = buf.get_u8();
+        if version != PROTOCOL_VERSION {
+            warn!("skipping message with protocol version {} (expected {})",
+                  version, PROTOCOL_VERSION);
+            // we can't know how the rest of the buffer is laid out, so we skip all of it
+            let end = buf.get_ref().len() as u64;
+            buf.set_position(end);
+            return Some(Err(rejected("protocol version mismatch")))
+        }
+        let group_id = if grouped {
+            if buf.remaining() < 4 { return None }
+            Some(buf.get_u32_be())
+        } else {
+            None
+        };
+        let seq = match self.sequencing {
+            Some(_) => {
+                if buf.remaining() < 4 { return None }
+                Some(buf.get_u32_be())
+            },
+            None => None,
+        };
+        let msg = match self.decode_message(buf)? {
+            Some(msg) => msg,
+            None => return None,
+        };
+        if self.checksum {
+            if buf.remaining() < 4 { return None }
+            let expected = crc32fast::hash(&buf.get_ref()[start..buf.position() as usize]);
+            if buf.get_u32_be() != expected {
+                warn!("dropping message with bad checksum: {:?}", msg);
+                return Some(Err(rejected("checksum mismatch")))
+            }
+        }
+        if let Some(ref key) = self.auth_key {
+            if buf.remaining() < AUTH_TAG_LEN { return None }
+            let (bytes, end) = (*buf.get_ref(), buf.position() as usize);
+            buf.advance(AUTH_TAG_LEN);
+            if key.mac(&bytes[start..end]).verify(&bytes[end..end + AUTH_TAG_LEN]).is_err() {
+                warn!("dropping unauthenticated message: {:?}", msg);
+                return Some(Err(rejected(UNAUTHENTICATED)))
+            }
+        }
+        if let (Some(ref sequencing), Some(seq)) = (&self.sequencing, seq) {
+            sequencing.observe(msg.server_id(), seq);
+        }
+        Some(Ok((group_id, msg)))
+    }
+
+    /// Encodes a single message with the given sequence number (if sequencing is enabled), prefixed
+    /// by its length if length-delimited, onto the end of the buffer.
+    fn encode_stamped(&self, msg: Message, seq: Option<u32>, dst: &mut BytesMut) {
+        if self.length_delimited {
+            let mut body = BytesMut::new();
+            self.encode_verified(msg, seq, &mut body);
+            dst.reserve(4 + body.len());
+            dst.put_u32_be(body.len() as u32);
+            dst.extend_from_slice(&body);
+        } else {
+            self.encode_verified(msg, seq, dst);
+        }
+    }
+
+    /// Encodes a single message, preceded by its group id if enabled and its sequence number if
+    /// given, and followed by its checksum and authentication tag if enabled, onto the end of the
+    /// buffer.
+    fn encode_verified(&self, msg: Message, seq: Option<u32>, dst: &mut BytesMut) {
+        let start = dst.len();
+        dst.reserve(5);
+        dst.put_u8(PROTOCOL_VERSION);
+        if let Some(group_id) = self.group_id {
+            dst.reserve(4);
+            dst.put_u32_be(group_id);
+        }
+        if let Some(seq) = seq {
+            dst.put_u32_be(seq);
+        }
+        self.encode_message(msg, dst);
+        if self.checksum {
+            let checksum = crc32fast::hash(&dst[start..]);
+            dst.reserve(4);
+            dst.put_u32_be(checksum);
+        }
+        if let Some(ref key) = self.auth_key {
+            let tag = key.mac(&dst[start..]).result().code();
+            dst.reserve(AUTH_TAG_LEN);
+            dst.put_slice(&tag);
+        }
+    }
+
+    /// Decodes a single message from the front of the buffer, or `None` if it is incomplete.
+    #[throws(io::Error)]
+    fn decode_message(&self, buf: &mut Cursor<&[u8]>) -> Option<Message> {
+        if buf.remaining() < 4 { return None }
+        let tag = buf.get_u32_be();
+        let arity = match tag {
+            // ViewQuery
+            5 => 1,
+            // ViewChange, Ack, Heartbeat, Nack, Ping, and Pong
+            2 | 4 | 6 | 7 | 9 | 10 => 2,
+            // VCProof and Reconfig
+            3 | 8 => 3,
+            // default case: unknown message type
+            n => throw!(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown message type: {}", n),
+            )),
+        };
+        let mut fields = [0; 3];
+        for field in fields.iter_mut().take(arity) {
+            *field = match self.get_field(buf)? {
+                Some(field) => field,
+                None => return None,
+            };
+        }
+        let [server_id, second, third] = fields;
+        Some(match tag {
+            2 => Message::ViewChange { server_id, attempted: second },
+            3 => Message::VCProof { server_id, installed: second, proposer: third },
+            4 => Message::Ack { server_id, acking: second },
+            5 => Message::ViewQuery { server_id },
+            6 => Message::Heartbeat { server_id, view: second },
+            7 => Message::Nack { server_id, current_view: second },
+            8 => Message::Reconfig { server_id, epoch: second, members: third },
+            9 => Message::Ping { server_id, nonce: second },
+            10 => Message::Pong { server_id, nonce: second },
+            _ => unreachable!("unknown tags were rejected above"),
+        })
+    }
+
+    /// Decodes a single field of a message from the front of the buffer, or `None` if it is
+    /// incomplete. Fails with `InvalidData` if a varint doesn't fit in a `u32`.
+    #[throws(io::Error)]
+    fn get_field(&self, buf: &mut Cursor<&[u8]>) -> Option<u32> {
+        if !self.varint {
+            if buf.remaining() < 4 { return None }
+            return Some(buf.get_u32_be())
+        }
+        let mut value = 0;
+        for shift in (0..32).step_by(7) {
+            if !buf.has_remaining() { return None }
+            let byte = buf.get_u8();
+            // the fifth byte holds the top four bits, with no room left for a continuation
+            if shift == 28 && byte > 0x0f {
+                throw!(io::Error::new(io::ErrorKind::InvalidData, "varint overflows a u32"))
+            }
+            value |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 { return Some(value) }
+        }
+        unreachable!("the fifth byte of a varint always ends it")
+    }
+
+    /// Encodes a single message onto the end of the buffer.
+    fn encode_message(&self, msg: Message, dst: &mut BytesMut) {
+        dst.reserve(4);
+        dst.put_u32_be(msg.tag());
+        match msg {
+            Message::ViewChange { server_id, attempted } => {
+                self.put_field(server_id, dst);
+                self.put_field(attempted, dst);
+            },
+            Message::VCProof { server_id, installed, proposer } => {
+                self.put_field(server_id, dst);
+                self.put_field(installed, dst);
+                self.put_field(proposer, dst);
+            },
+            Message::Ack { server_id, acking } => {
+                self.put_field(server_id, dst);
+                self.put_field(acking, dst);
+            },
+            Message::ViewQuery { server_id } => {
+                self.put_field(server_id, dst);
+            },
+            Message::Heartbeat { server_id, view } => {
+                self.put_field(server_id, dst);
+                self.put_field(view, dst);
+            },
+            Message::Nack { server_id, current_view } => {
+                self.put_field(server_id, dst);
+                self.put_field(current_view, dst);
+            },
+            Message::Reconfig { server_id, epoch, members } => {
+                self.put_field(server_id, dst);
+                self.put_field(epoch, dst);
+                self.put_field(members, dst);
+            },
+            Message::Ping { server_id, nonce } | Message::Pong { server_id, nonce } => {
+                self.put_field(server_id, dst);
+                self.put_field(nonce, dst);
+            },
+        }
+    }
+
+    /// Encodes a single field of a message onto the end of the buffer, as a varint (seven bits to
+    /// a byte, least significant first, with the high bit set on every byte but the last) if
+    /// enabled.
+    fn put_field(&self, value: u32, dst: &mut BytesMut) {
+        if !self.varint {
+            dst.reserve(4);
+            dst.put_u32_be(value);
+            return
+        }
+        dst.reserve(5);
+        let mut value = value;
+        while value >= 0x80 {
+            dst.put_u8(value as u8 | 0x80);
+            value >>= 7;
+        }
+        dst.put_u8(value as u8);
+    }
+
+    /// Decodes a single message along with its group id, which it carries only if grouped, or
+    /// `None` if it is incomplete.
+    #[throws(io::Error)]
+    fn decode_grouped(
+        &self, src: &mut BytesMut, grouped: bool
+    ) -> Option<(Option<u32>, Message)> {
+        let mut buf = Cursor::new(&src[..]);
+        trace!("received buffer: {:?}", buf);
+        let (result, consumed) = if self.length_delimited {
+            if buf.remaining() < 4 { return None }
+            let len = buf.get_u32_be() as usize;
+            if len > MAX_FRAME_LEN {
+                throw!(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame of length {} exceeds {}", len, MAX_FRAME_LEN),
+                ))
+            }
+            // wait until the whole frame has been buffered before parsing any of it
+            if buf.remaining() < len { return None }
+            let mut frame = Cursor::new(&src[4..4 + len]);
+            let result = match self.decode_verified(&mut frame, grouped)? {
+                Some(result) => result,
+                None => throw!(io::Error::new(
+                    io::ErrorKind::InvalidData, format!("truncated frame of length {}", len)
+                )),
+            };
+            // a frame holds exactly one message, so anything after it means the length is wrong
+            if frame.remaining() > 0 {
+                throw!(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} trailing bytes in frame of length {}", frame.remaining(), len),
+                ))
+            }
+            (result, 4 + len)
+        } else {
+            match self.decode_verified(&mut buf, grouped)? {
+                Some(result) => (result, buf.position() as usize),
+                None => return None,
+            }
+        };
+
+        // drop the bytes we just consumed so that the next call starts at the following message
+        src.advance(consumed);
+        Some(result?)
+    }
+}
 
 impl Decoder for MessageCodec {
     type Item = Message;
@@ -32,31 +689,32 @@ impl Decoder for MessageCodec {
 
     #[throws(io::Error)]
     fn decode(&mut self, src: &mut BytesMut) -> Option<Message> {
-        let mut buf = src.clone().into_buf();
-        trace!("received buffer: {:?}", buf);
-        if buf.remaining() < 4 { return None }
-        match buf.get_u32_be() {
-            // ViewChange
-            2 => {
-                if buf.remaining() < 8 { return None }
-                Some(Message::ViewChange {
-                    server_id: buf.get_u32_be(),
-                    attempted: buf.get_u32_be(),
-                })
-            },
-            // VCProof
-            3 => {
-                if buf.remaining() < 8 { return None }
-                Some(Message::VCProof {
-                    server_id: buf.get_u32_be(),
-                    installed: buf.get_u32_be(),
-                })
-            },
-            // default case: unknown message type
-            n => {
-                eprintln!("unknown message type: {}", n);
-                throw!(io::ErrorKind::InvalidData)
+        match self.decode_grouped(src, self.group_id.is_some())? {
+            Some((group_id, msg)) if group_id != self.group_id => {
+                trace!("ignoring message from group {:?}: {:?}", group_id, msg);
+                throw!(rejected("message from another group"))
             },
+            decoded => decoded.map(|(_, msg)| msg),
+        }
+    }
+}
+
+/// A codec for the messages of every group sharing a socket, which decodes each message along with
+/// the id of the group it belongs to, so that it can be routed to the protocol running that group.
+/// Every message must carry a group id, whatever the group id of the underlying codec.
+#[derive(Clone, Debug, Default)]
+pub struct GroupedCodec(pub MessageCodec);
+
+impl Decoder for GroupedCodec {
+    type Item = (u32, Message);
+    type Error = io::Error;
+
+    #[throws(io::Error)]
+    fn decode(&mut self, src: &mut BytesMut) -> Option<(u32, Message)> {
+        match self.0.decode_grouped(src, true)? {
+            Some((Some(group_id), msg)) => Some((group_id, msg)),
+            Some((None, _)) => unreachable!("grouped messages always carry a group id"),
+            None => None,
         }
     }
 }
@@ -68,17 +726,378 @@ impl Encoder for MessageCodec {
     #[throws(io::Error)]
     fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> () {
         trace!("encoding: {:?}", msg);
-        match msg {
-            Message::ViewChange { server_id, attempted } => {
-                dst.put_u32_be(2);
-                dst.put_u32_be(server_id);
-                dst.put_u32_be(attempted);
-            },
-            Message::VCProof { server_id, installed } => {
-                dst.put_u32_be(3);
-                dst.put_u32_be(server_id);
-                dst.put_u32_be(installed);
-            },
+        let seq = self.sequencing.as_ref().map(|sequencing| sequencing.stamp());
+        self.encode_stamped(msg, seq, dst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Encodes the given messages back to back with the given codec.
+    fn encode_all(mut codec: MessageCodec, msgs: &[Message]) -> BytesMut {
+        let mut bytes = BytesMut::new();
+        for msg in msgs {
+            codec.encode(*msg, &mut bytes).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn described_bytes_show_the_tag_and_fields_of_each_message() {
+        let msg = Message::ViewChange { server_id: 1, attempted: 2 };
+        let bytes = encode_all(MessageCodec::datagram(), &[msg]);
+        assert_eq!(MessageCodec::datagram().describe_bytes(&bytes), "tag=2 VC srv=1 att=2");
+
+        // a truncated message is left over after the whole ones
+        let bytes = encode_all(MessageCodec::datagram(), &[msg, msg]);
+        assert_eq!(MessageCodec::datagram().describe_bytes(&bytes[..bytes.len() - 1]),
+                   "tag=2 VC srv=1 att=2; <12 trailing bytes>");
+    }
+
+    #[test]
+    fn concatenated_messages_decode_one_after_another() {
+        let msgs = [
+            Message::ViewChange { server_id: 1, attempted: 2 },
+            Message::VCProof { server_id: 3, installed: 4, proposer: 5 },
+            Message::Heartbeat { server_id: 5, view: 6 },
+            Message::Nack { server_id: 7, current_view: 8 },
+            Message::Reconfig { server_id: 9, epoch: 10, members: 11 },
+            Message::Ping { server_id: 12, nonce: 13 },
+            Message::Pong { server_id: 14, nonce: 15 },
+        ];
+        let mut src = encode_all(MessageCodec::datagram(), &msgs);
+        let mut codec = MessageCodec::datagram();
+        for msg in &msgs {
+            assert_eq!(codec.decode(&mut src).unwrap(), Some(*msg));
+        }
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn frames_delivered_a_byte_at_a_time_wait_until_whole() {
+        let msgs = [
+            Message::ViewChange { server_id: 0, attempted: 5 },
+            Message::VCProof { server_id: 1, installed: 2, proposer: 2 },
+        ];
+        let bytes = encode_all(MessageCodec::length_delimited(), &msgs);
+        let first_len = encode_all(MessageCodec::length_delimited(), &msgs[..1]).len();
+        let mut codec = MessageCodec::length_delimited();
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for (count, byte) in bytes.iter().enumerate() {
+            src.extend_from_slice(&[*byte]);
+            if let Some(msg) = codec.decode(&mut src).unwrap() {
+                decoded.push((count + 1, msg));
+            }
+        }
+        // each message comes out with the last byte of its frame, and not a byte sooner
+        let ends = vec![first_len, bytes.len()];
+        assert_eq!(decoded, ends.into_iter().zip(msgs.iter().cloned()).collect::<Vec<_>>());
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected_before_it_is_buffered() {
+        let mut src = BytesMut::new();
+        src.put_u32_be(MAX_FRAME_LEN as u32 + 1);
+        let err = MessageCodec::length_delimited().decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn frame_with_trailing_bytes_is_rejected() {
+        let msg = Message::ViewChange { server_id: 1, attempted: 2 };
+        let body = encode_all(MessageCodec::datagram(), &[msg]);
+        // the length prefix also covers two bytes following the message
+        let mut src = BytesMut::new();
+        src.put_u32_be(body.len() as u32 + 2);
+        src.extend_from_slice(&body);
+        src.extend_from_slice(&[0, 0]);
+        let err = MessageCodec::length_delimited().decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn corrupted_message_fails_its_checksum() {
+        let codec = MessageCodec { checksum: true, ..MessageCodec::datagram() };
+        let msg = Message::ViewChange { server_id: 1, attempted: 2 };
+        let bytes = encode_all(codec.clone(), &[msg]);
+        // every bit of the fields and of the checksum itself is covered
+        for byte in 5..bytes.len() {
+            for bit in 0..8 {
+                let mut src = bytes.clone();
+                src[byte] ^= 1 << bit;
+                let mut decoder = codec.clone();
+                let err = decoder.decode(&mut src).unwrap_err();
+                assert_eq!(err.to_string(), "message rejected: checksum mismatch");
+                assert!(src.is_empty());
+            }
+        }
+    }
+
+    /// Creates a datagram codec authenticating messages with a key made from the given secret.
+    fn signing(secret: &[u8]) -> MessageCodec {
+        MessageCodec { auth_key: Some(AuthKey::new(secret)), ..MessageCodec::datagram() }
+    }
+
+    #[test]
+    fn message_signed_with_the_shared_key_is_authenticated() {
+        let codec = signing(b"secret");
+        let msg = Message::ViewChange { server_id: 1, attempted: 2 };
+        let mut src = encode_all(codec.clone(), &[msg]);
+        assert_eq!(src.len(), 13 + AUTH_TAG_LEN);
+        let mut decoder = codec;
+        assert_eq!(decoder.decode(&mut src).unwrap(), Some(msg));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn message_signed_with_another_key_is_rejected() {
+        let msg = Message::ViewChange { server_id: 1, attempted: 2 };
+        let mut src = encode_all(signing(b"wrong"), &[msg]);
+        let mut decoder = signing(b"secret");
+        let err = decoder.decode(&mut src).unwrap_err();
+        assert_eq!(err.to_string(), "message rejected: authentication failed");
+        assert!(Rejected::is_unauthenticated(&err));
+        assert!(src.is_empty());
+
+        // nor is a message that isn't signed at all
+        let mut src = encode_all(MessageCodec::datagram(), &[Message::ViewQuery { server_id: 1 }]);
+        src.extend_from_slice(&[0; AUTH_TAG_LEN]);
+        assert!(Rejected::is_unauthenticated(&decoder.decode(&mut src).unwrap_err()));
+    }
+
+    #[test]
+    fn varints_round_trip_and_shrink_small_fields() {
+        let varint = MessageCodec { varint: true, ..MessageCodec::datagram() };
+        let msgs = [
+            Message::ViewChange { server_id: 1, attempted: 2 },
+            Message::VCProof { server_id: 127, installed: 128, proposer: 1 },
+            Message::Reconfig { server_id: 16_383, epoch: 16_384, members: 3 },
+            Message::Ping { server_id: 0, nonce: u32::max_value() },
+            Message::ViewQuery { server_id: 300 },
+        ];
+        for codec in &[MessageCodec::datagram(), varint.clone()] {
+            let mut src = encode_all(codec.clone(), &msgs);
+            let mut decoder = codec.clone();
+            for msg in &msgs {
+                assert_eq!(decoder.decode(&mut src).unwrap(), Some(*msg), "{:?}", codec);
+            }
+            assert!(src.is_empty());
+        }
+
+        // single digit fields take a byte each rather than four, and the largest take five
+        assert_eq!(varint.encoded_len(msgs[0]), 1 + 4 + 2);
+        assert_eq!(MessageCodec::datagram().encoded_len(msgs[0]), 1 + 4 + 8);
+        assert_eq!(varint.encoded_len(msgs[1]), 1 + 4 + 1 + 2 + 1);
+        assert_eq!(varint.encoded_len(msgs[3]), 1 + 4 + 1 + 5);
+
+        // a varint running past the bits of a u32 is rejected rather than wrapping around
+        let overflowing = [PROTOCOL_VERSION, 0, 0, 0, 5, 0xff, 0xff, 0xff, 0xff, 0x1f];
+        let mut src = BytesMut::from(&overflowing[..]);
+        let err = varint.clone().decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn mismatched_version_is_skipped_without_ending_the_stream() {
+        let msgs = [
+            Message::ViewChange { server_id: 1, attempted: 2 },
+            Message::ViewChange { server_id: 2, attempted: 2 },
+        ];
+        let mut src = encode_all(MessageCodec::length_delimited(), &msgs);
+        // the version follows the length of the first frame
+        src[4] = PROTOCOL_VERSION + 1;
+        let mut codec = MessageCodec::length_delimited();
+        let err = codec.decode(&mut src).unwrap_err();
+        assert!(Rejected::is_rejected(&err), "{}", err);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(msgs[1]));
+        assert!(src.is_empty());
+    }
+
+    /// Creates a datagram codec stamping and observing sequence numbers.
+    fn sequenced() -> MessageCodec {
+        MessageCodec { sequencing: Some(Arc::default()), ..MessageCodec::datagram() }
+    }
+
+    #[test]
+    fn reordered_and_missing_sequence_numbers_are_counted() {
+        let sender = sequenced();
+        let datagrams: Vec<_> = (0..5)
+            .map(|view| encode_all(sender.clone(), &[Message::Heartbeat { server_id: 1, view }]))
+            .collect();
+        // the sequence number follows the version, and is the only thing the frame grows by
+        assert_eq!(&datagrams[3][1..5], &[0, 0, 0, 3]);
+        assert_eq!(datagrams[3].len(), 13 + 4);
+        assert_eq!(sender.encoded_len(Message::Heartbeat { server_id: 1, view: 0 }), 13 + 4);
+
+        // 0 and 2 arrive in order (with 1 missing), then 1 arrives late, then 4 (with 3 missing)
+        let receiver = sequenced();
+        let mut decoder = receiver.clone();
+        for idx in &[0, 2, 1, 4] {
+            let mut src = datagrams[*idx].clone();
+            let expected = Message::Heartbeat { server_id: 1, view: *idx as u32 };
+            assert_eq!(decoder.decode(&mut src).unwrap(), Some(expected));
+        }
+        let sequencing = receiver.sequencing.unwrap();
+        assert_eq!(sequencing.out_of_order.load(Ordering::Relaxed), 1);
+        assert_eq!(sequencing.missing.load(Ordering::Relaxed), 2);
+
+        // and every sender is tracked separately, starting from wherever it's first seen
+        let mut src = encode_all(sequenced(), &[Message::ViewQuery { server_id: 2 }]);
+        decoder.decode(&mut src).unwrap();
+        assert_eq!(sequencing.out_of_order.load(Ordering::Relaxed), 1);
+        assert_eq!(sequencing.missing.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn sequence_numbers_are_followed_across_restarts_and_wrapping() {
+        let sequencing = Sequencing::default();
+        // a sender that restarts numbers its messages from 0 again, which isn't a late arrival
+        sequencing.observe(1, 5000);
+        for seq in 0..3 {
+            sequencing.observe(1, seq);
+        }
+        assert_eq!(sequencing.out_of_order.load(Ordering::Relaxed), 0);
+        assert_eq!(sequencing.missing.load(Ordering::Relaxed), 0);
+        // though a message from shortly before the restart still counts as one
+        sequencing.observe(1, 1);
+        assert_eq!(sequencing.out_of_order.load(Ordering::Relaxed), 1);
+
+        // while the message stamped right after u32::MAX follows on from it, unlike one before it
+        sequencing.observe(2, u32::MAX - 1);
+        sequencing.observe(2, u32::MAX);
+        sequencing.observe(2, 0);
+        sequencing.observe(2, 2);
+        sequencing.observe(2, u32::MAX);
+        assert_eq!(sequencing.out_of_order.load(Ordering::Relaxed), 2);
+        assert_eq!(sequencing.missing.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn messages_from_another_group_are_rejected_unless_decoding_every_group() {
+        let in_group = |group_id| {
+            MessageCodec { group_id: Some(group_id), ..MessageCodec::datagram() }
+        };
+        let msgs = [Message::ViewChange { server_id: 1, attempted: 2 }];
+        let mut src = encode_all(in_group(7), &msgs);
+        // the group id follows the version
+        assert_eq!(&src[1..5], &[0, 0, 0, 7]);
+
+        let err = in_group(8).decode(&mut src.clone()).unwrap_err();
+        assert!(Rejected::is_rejected(&err));
+        assert_eq!(in_group(7).decode(&mut src.clone()).unwrap(), Some(msgs[0]));
+        assert_eq!(GroupedCodec(in_group(8)).decode(&mut src).unwrap(), Some((7, msgs[0])));
+        assert!(src.is_empty());
+    }
+
+    /// Generates arbitrary messages of every variant.
+    fn any_message() -> impl Strategy<Value = Message> {
+        let pair = || (any::<u32>(), any::<u32>());
+        prop_oneof![
+            pair().prop_map(|(server_id, attempted)| Message::ViewChange { server_id, attempted }),
+            (any::<u32>(), any::<u32>(), any::<u32>()).prop_map(|(server_id, installed, proposer)| {
+                Message::VCProof { server_id, installed, proposer }
+            }),
+            pair().prop_map(|(server_id, acking)| Message::Ack { server_id, acking }),
+            any::<u32>().prop_map(|server_id| Message::ViewQuery { server_id }),
+            pair().prop_map(|(server_id, view)| Message::Heartbeat { server_id, view }),
+            pair().prop_map(|(server_id, current_view)| Message::Nack { server_id, current_view }),
+            (any::<u32>(), any::<u32>(), any::<u32>()).prop_map(|(server_id, epoch, members)| {
+                Message::Reconfig { server_id, epoch, members }
+            }),
+            pair().prop_map(|(server_id, nonce)| Message::Ping { server_id, nonce }),
+            pair().prop_map(|(server_id, nonce)| Message::Pong { server_id, nonce }),
+        ]
+    }
+
+    /// Generates codecs with every combination of settings, authenticating with a fixed key.
+    fn any_codec() -> impl Strategy<Value = MessageCodec> {
+        let flags = any::<[bool; 5]>();
+        (flags, any::<Option<u32>>()).prop_map(|(flags, group_id)| {
+            let [length_delimited, checksum, auth, sequenced, varint] = flags;
+            MessageCodec {
+                length_delimited, checksum, varint, group_id,
+                auth_key: if auth { Some(AuthKey::new(b"secret")) } else { None },
+                sequencing: if sequenced { Some(Arc::default()) } else { None },
+            }
+        })
+    }
+
+    /// Decodes messages from the given bytes until they run out or fail to decode, checking that
+    /// decoding only ever fails with `InvalidData`.
+    fn decode_all(mut codec: MessageCodec, bytes: &[u8]) -> Result<(), TestCaseError> {
+        let mut src = BytesMut::from(bytes);
+        loop {
+            match codec.decode(&mut src) {
+                // every message decoded consumes at least its version, so this ends
+                Ok(Some(_)) => continue,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    prop_assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    return Ok(())
+                },
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn every_message_survives_a_round_trip(
+            codec in any_codec(), msgs in collection::vec(any_message(), 1..8)
+        ) {
+            let mut src = encode_all(codec.clone(), &msgs);
+            let mut decoder = codec;
+            for msg in &msgs {
+                prop_assert_eq!(decoder.decode(&mut src).unwrap(), Some(*msg));
+            }
+            prop_assert!(src.is_empty());
+        }
+
+        #[test]
+        fn decoding_arbitrary_bytes_never_panics(
+            codec in any_codec(), bytes in collection::vec(any::<u8>(), 0..64)
+        ) {
+            decode_all(codec, &bytes)?;
+        }
+
+        #[test]
+        fn decoding_truncated_or_corrupted_messages_never_panics(
+            codec in any_codec(), msg in any_message(), cut in any::<usize>(),
+            flip in any::<(usize, u8)>(),
+        ) {
+            let mut bytes = encode_all(codec.clone(), &[msg]);
+            let (idx, mask) = flip;
+            let len = bytes.len();
+            bytes[idx % len] ^= mask;
+            decode_all(codec, &bytes[..cut % (len + 1)])?;
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn every_variant_survives_a_json_round_trip() {
+        let msgs = vec![
+            Message::ViewChange { server_id: 1, attempted: 2 },
+            Message::VCProof { server_id: 1, installed: 2, proposer: 2 },
+            Message::Ack { server_id: 1, acking: 2 },
+            Message::ViewQuery { server_id: 1 },
+            Message::Heartbeat { server_id: 1, view: 2 },
+            Message::Nack { server_id: 1, current_view: 2 },
+            Message::Reconfig { server_id: 1, epoch: 2, members: 3 },
+            Message::Ping { server_id: 1, nonce: 2 },
+            Message::Pong { server_id: 1, nonce: 2 },
+        ];
+        for msg in &msgs {
+            let json = serde_json::to_string(msg).unwrap();
+            assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), *msg, "{}", json);
         }
+        let json = serde_json::to_string(&msgs).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Message>>(&json).unwrap(), msgs);
     }
 }
@@ -1,139 +1,1281 @@
-use std::io;
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+#[cfg(unix)]
+use std::fs;
+use std::fs::File;
+#[cfg(unix)]
+use std::future::Future;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::task::{Context, Poll};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use bytes::{Bytes, BytesMut};
 use fehler::{throw, throws};
-use futures::select;
-use futures::stream::StreamExt;
+use futures::{future, select};
+use futures::channel::oneshot;
+use futures::future::{FusedFuture, FutureExt};
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{self, Stream, StreamExt};
 use log::{trace, info, warn, error};
-use tokio::net::{UdpFramed, UdpSocket};
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use tokio::codec::{BytesCodec, Encoder};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::UdpSocket;
+use tokio::net::driver::Handle;
+use tokio::signal;
+#[cfg(unix)]
+use tokio::signal::unix::{Signal, SignalKind};
+use tokio::timer;
+use tokio::sync::mpsc::{self, Receiver, Sender};
 
-use crate::TestCase;
-use crate::msg::{Message, MessageCodec};
+#[cfg(unix)]
+use crate::admin;
+use crate::channel::{DatagramChannel, DatagramFramed};
+#[cfg(feature = "capture")]
+use crate::capture::{self, BinaryCapture, Capture, Captured, CapturedDatagram};
+use crate::metrics::{self, Metrics};
+use crate::msg::{GroupedCodec, Message, MessageCodec, Rejected};
 use crate::paxos::{Paxos, PaxosConfig};
 
-pub type ProtocolSocket = UdpFramed<MessageCodec>;
+pub type ProtocolSocket = DatagramFramed<MessageCodec>;
 
+/// A future serving the admin socket for as long as the protocol runs.
+#[cfg(unix)]
+type AdminFuture = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+/// A socket sending datagrams that were already encoded by a `MessageCodec`.
+pub type OutgoingSocket = DatagramFramed<BytesCodec>;
+
+/// A sink taking the place of the outgoing socket, sending datagrams that were already encoded by a
+/// `MessageCodec` to their destinations (e.g. capturing them in a test).
+pub type OutgoingSink = Pin<Box<dyn Sink<(Bytes, SocketAddr), Error = io::Error> + Send>>;
+
+/// A handle injecting messages into a running system as if they arrived from the given addresses.
+#[cfg(any(test, feature = "inject"))]
+pub type Injector = futures::channel::mpsc::UnboundedSender<(Message, SocketAddr)>;
+
+/// The default base port: incoming messages are received on it, and outgoing ones sent from the
+/// port immediately after it.
 pub const PORT_NUMBER: u16 = 42069;
 
+/// The default number of messages that can be queued for the outgoing socket at once.
+pub const OUTGOING_CAPACITY: usize = 1024;
+
+/// The default number of times a datagram is re-sent after the outgoing socket fails transiently.
+pub const SEND_RETRIES: u32 = 3;
+
+/// The default number of consecutive failed interactions with a node before it's suspected dead.
+pub const FAILURE_THRESHOLD: u32 = 3;
+
+/// How long to wait before re-sending a datagram after the outgoing socket fails transiently.
+const SEND_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// How long shutting down waits for the outgoing socket to send the messages still enqueued.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default time to keep trying to resolve each host for at startup before giving up.
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait before trying to resolve a hostname again after failing to.
+const RESOLVE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How long to wait to connect to the server when fetching the hostfile from a URL, in
+/// milliseconds.
+#[cfg(feature = "http-hosts")]
+const HOSTFILE_FETCH_TIMEOUT_MS: u64 = 10_000;
+
+/// The default address to bind sockets to, i.e. every IPv4 interface.
+pub const BIND_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// The default time to keep trying to bind a socket for while its port is in use (e.g. by the
+/// previous process of a node that just restarted) before giving up.
+pub const BIND_WINDOW: Duration = Duration::from_secs(10);
+
+/// How long to wait before trying to bind a socket again after its port was in use, which doubles
+/// on every attempt up to `BIND_RETRY_MAX_DELAY`.
+const BIND_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// The longest to wait between attempts to bind a socket.
+const BIND_RETRY_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Creates a socket on the given port, retrying with a backoff for up to the given window for as
+/// long as the port is in use. Fails with `AddrInUse` if it's still in use once the window passes.
 #[throws(io::Error)]
-async fn make_proc_socket(port: u16) -> ProtocolSocket {
-    trace!("creating local socket on port {}", port);
-    UdpFramed::new(UdpSocket::bind(format!("0.0.0.0:{}", port)).await?, MessageCodec)
+async fn make_proc_socket<C>(bind: IpAddr, port: u16, codec: C, window: Duration)
+    -> DatagramFramed<C>
+{
+    trace!("creating local socket on {}, port {}", bind, port);
+    let deadline = Instant::now() + window;
+    let mut delay = BIND_RETRY_DELAY;
+    let socket = loop {
+        match bind_socket(SocketAddr::new(bind, port)) {
+            Ok(socket) => break socket,
+            Err(ref e) if e.kind() == io::ErrorKind::AddrInUse && Instant::now() < deadline => {
+                let delay_now = delay.min(deadline - Instant::now());
+                warn!("port {} is in use, trying to bind it again in {:?}", port, delay_now);
+                timer::delay_for(delay_now).await;
+                delay = (delay * 2).min(BIND_RETRY_MAX_DELAY);
+            },
+            Err(e) => throw!(e),
+        }
+    };
+    DatagramFramed::new(socket, codec)
+}
+
+/// Binds a UDP socket to the given address exclusively, without `SO_REUSEADDR`, which for UDP
+/// would let a restarted node share its port with a previous process still holding it (splitting
+/// the datagrams between them) rather than fail with `AddrInUse` until the port is let go of.
+#[throws(io::Error)]
+fn bind_socket(addr: SocketAddr) -> UdpSocket {
+    UdpSocket::from_std(std::net::UdpSocket::bind(addr)?, &Handle::default())?
 }
 
 #[throws(io::Error)]
-pub async fn incoming_socket() -> ProtocolSocket {
-    make_proc_socket(PORT_NUMBER).await?
+pub async fn incoming_socket(bind: IpAddr, port: u16, codec: MessageCodec, window: Duration)
+    -> ProtocolSocket
+{
+    make_proc_socket(bind, port, codec, window).await?
 }
 
 #[throws(io::Error)]
-pub async fn outgoing_socket() -> ProtocolSocket {
-    make_proc_socket(PORT_NUMBER + 1).await?
+pub async fn outgoing_socket(bind: IpAddr, port: u16, window: Duration) -> OutgoingSocket {
+    make_proc_socket(bind, port + 1, BytesCodec::new(), window).await?
 }
 
+/// Picks the first of the given addresses in the same family (IPv4 or IPv6) as the given bind
+/// address, since a socket bound to it can only reach those, or else the first address of all.
+fn pick_addr<I: Iterator<Item = SocketAddr>>(addrs: I, bind: IpAddr) -> Option<SocketAddr> {
+    let addrs: Vec<_> = addrs.collect();
+    addrs.iter().find(|addr| addr.is_ipv6() == bind.is_ipv6()).or_else(|| addrs.first()).cloned()
+}
+
+/// The capacity that messages are encoded into, which is more than `Bytes` would store inline, so
+/// that every copy of an encoded message shares the one buffer rather than copying it.
+const ENCODING_CAPACITY: usize = 64;
+
 struct Node {
-    addr: SocketAddr,
+    /// the hostname the node was resolved from, kept around for re-resolving it
+    hostname: String,
+    /// the port the node receives on
+    port: u16,
+    /// the local bind address, whose address family is preferred when resolving the hostname
+    bind: IpAddr,
+    /// the most recently resolved address of the node
+    addr: Mutex<SocketAddr>,
+    /// the number of votes the node carries towards a quorum
+    weight: u32,
 }
 
 impl Node {
-    /// Attempt to resolve the given hostname repeatedly until success.
+    /// Attempt to resolve the given hostname on the given port repeatedly until success, preferring
+    /// addresses in the same family as the given bind address. Resolving blocks, so every attempt
+    /// runs on a thread of its own while the runtime carries on. Fails with `TimedOut` once the
+    /// given timeout passes without success, though an attempt underway at the time carries on in
+    /// the background until the resolver itself gives up on it.
     #[throws(io::Error)]
-    fn resolve_from_hostname<S: AsRef<str>>(hostname: S) -> Node {
-        info!("attempting to resolve hostname: {}", hostname.as_ref());
-        let mut attempts = 0;
-        while let Err(e) = format!("{}:{}", hostname.as_ref(), PORT_NUMBER).to_socket_addrs() {
-            attempts += 1;
-            warn!("{}", e);
-            thread::sleep(Duration::from_millis(500));
+    async fn resolve_from_hostname(
+        hostname: &str, port: u16, bind: IpAddr, timeout: Duration
+    ) -> Node {
+        info!("attempting to resolve hostname: {}", hostname);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let (tx, rx) = oneshot::channel();
+            let owned = hostname.to_owned();
+            thread::spawn(move || {
+                // the attempt may outlive the deadline, by which time nobody is waiting on it
+                let _ = tx.send(Node::resolve(owned, port, bind));
+            });
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let err = match future::select(rx, timer::delay_for(remaining)).await {
+                future::Either::Left((Ok(Ok(node)), _)) => return node,
+                future::Either::Left((Ok(Err(e)), _)) => e,
+                future::Either::Left((Err(_), _)) => {
+                    io::Error::new(io::ErrorKind::Other, "resolver thread panicked")
+                },
+                future::Either::Right(_) => {
+                    let reason = format!("resolution still underway after {:?}", timeout);
+                    io::Error::new(io::ErrorKind::TimedOut, reason)
+                },
+            };
+            warn!("failed to resolve hostname {}: {}", hostname, err);
 
-            // if it takes longer than five minutes to resolve the hostname, we'll just give up
-            if attempts > 2 * 60 * 5 {
-                error!("{}", e);
-                throw!(e)
+            // if we can't try again before the deadline, we'll just give up
+            if Instant::now() + RESOLVE_RETRY_DELAY >= deadline {
+                error!("giving up on resolving hostname {} after {:?}", hostname, timeout);
+                let reason = format!("timed out resolving hostname {} after {:?}: {}",
+                                     hostname, timeout, err);
+                throw!(io::Error::new(io::ErrorKind::TimedOut, reason))
             }
+            timer::delay_for(RESOLVE_RETRY_DELAY).await;
         }
+    }
 
-        let addr =
-            format!("{}:{}", hostname.as_ref(), PORT_NUMBER).to_socket_addrs()?.next().unwrap();
+    /// Attempts to resolve the given hostname on the given port once, preferring addresses in the
+    /// same family as the given bind address.
+    #[throws(io::Error)]
+    fn resolve<S: AsRef<str>>(hostname: S, port: u16, bind: IpAddr) -> Node {
+        let addr = match pick_addr((hostname.as_ref(), port).to_socket_addrs()?, bind) {
+            Some(addr) => addr,
+            None => throw!(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("hostname {} resolved to no addresses", hostname.as_ref()),
+            )),
+        };
         info!("hostname {} resolved to {:?}", hostname.as_ref(), addr);
-        Node { addr }
+        let hostname = hostname.as_ref().to_owned();
+        Node { hostname, port, bind, addr: Mutex::new(addr), weight: 1 }
+    }
+
+    /// Gets the most recently resolved address of the node.
+    fn addr(&self) -> SocketAddr {
+        *self.addr.lock().unwrap()
+    }
+
+    /// Attempts to resolve the hostname of the node once more, updating its address on success and
+    /// keeping the old one otherwise.
+    fn re_resolve(&self) {
+        let res = (self.hostname.as_str(), self.port).to_socket_addrs()
+            .map(|addrs| pick_addr(addrs, self.bind));
+        match res {
+            Ok(Some(addr)) => {
+                let mut cached = self.addr.lock().unwrap();
+                if *cached != addr {
+                    info!("hostname {} now resolves to {:?}", self.hostname, addr);
+                    *cached = addr;
+                }
+            },
+            Ok(None) => warn!("hostname {} resolved to no addresses", self.hostname),
+            Err(e) => warn!("failed to re-resolve hostname {}: {}", self.hostname, e),
+        }
+    }
+}
+
+/// The nodes taking part in the system, which change as the system is reconfigured.
+struct Membership {
+    /// the epoch of the reconfiguration that installed these members, starting from 0
+    epoch: u32,
+    /// every known node in order of pid, of which only the first `len` are members
+    hosts: Vec<Node>,
+    /// the number of known nodes that are currently members
+    len: usize,
+}
+
+/// A reliably multicast message that some nodes have yet to acknowledge.
+#[derive(Clone)]
+struct Unacked {
+    /// the message to retransmit
+    msg: Message,
+    /// the message as it was encoded for its first transmission
+    datagram: Bytes,
+    /// the indices of the nodes that have not yet acknowledged the message
+    pending: HashSet<usize>,
+    /// the number of times the message has been retransmitted so far
+    retries: u32,
+}
+
+/// A simulation of an unreliable network, dropping outgoing datagrams at random.
+#[derive(Clone)]
+struct Loss {
+    /// the probability of dropping each datagram
+    rate: f64,
+    /// the source of randomness deciding which datagrams are dropped
+    rng: StdRng,
+}
+
+/// What to do with a multicast sent faster than the multicast rate limit allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// drop the multicast, leaving the protocol to recover as it would from any lost datagram
+    Drop,
+    /// hold the multicast back until the bucket has room for it again
+    Queue,
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RateLimitPolicy, String> {
+        match s {
+            "drop" => Ok(RateLimitPolicy::Drop),
+            "queue" => Ok(RateLimitPolicy::Queue),
+            _ => Err(format!("unknown rate limit policy: {}", s)),
+        }
+    }
+}
+
+/// A token bucket limiting the rate of outgoing multicasts, holding up to a second's worth of them
+/// (and at least one) so that short bursts still go out at once.
+#[derive(Clone, Debug)]
+struct RateLimit {
+    /// the number of multicasts allowed per second
+    rate: f64,
+    /// the multicasts that can go out right now, which is negative while queued ones are pending,
+    /// but never by more than a burst
+    tokens: f64,
+    /// when the tokens were last topped up
+    refilled: Instant,
+    /// what to do with the multicasts that go past the limit
+    policy: RateLimitPolicy,
+}
+
+impl RateLimit {
+    /// Takes a token for a multicast, returning how long it has to be held back for (i.e. none if
+    /// it can go out now), or `None` if it should be dropped. At most a burst of multicasts are
+    /// held back at once, so past that, even the queue policy drops them.
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let burst = self.rate.max(1.0);
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(burst);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Some(Duration::from_millis(0))
+        }
+        match self.policy {
+            RateLimitPolicy::Drop => None,
+            RateLimitPolicy::Queue if self.tokens - 1.0 < -burst => {
+                warn!("multicast queue is full, with a burst of {} waiting", burst);
+                None
+            },
+            RateLimitPolicy::Queue => {
+                // the multicast goes out once the tokens it's short of have accrued
+                self.tokens -= 1.0;
+                Some(Duration::from_secs_f64(-self.tokens / self.rate))
+            },
+        }
     }
 }
 
+/// All the nodes in the system, along with a channel for sending messages to them.
+///
+/// The members of the system are the first however many of the nodes from the hostfile, which is
+/// every one of them until the system is reconfigured. Every handle shares the same membership.
+///
+/// Messages are encoded as they're sent, once no matter how many nodes they're sent to, and the
+/// outgoing socket sends the encoded datagrams along as they are.
+///
+/// Multicasts are never delivered back to the local node, and so the protocol is responsible for
+/// accounting for its own participation directly (e.g. recording its own view change vote).
+///
+/// In reliable mode, multicast view changes are retransmitted to every node that has not yet
+/// acknowledged them each time `retransmit` is called, until a maximum number of retries.
+///
+/// The outgoing channel is bounded, so sends fail with `WouldBlock` while it is full rather than
+/// queueing without limit. `poll_ready` waits for room in it, which `Paxos` relies on to stop
+/// accepting incoming messages (and so stop generating replies) until the outgoing socket catches
+/// up. A multicast can still run out of room partway through, since it needs one slot per node.
+///
+/// Multicasts can also be rate limited by a token bucket, which each handle keeps on its own.
 #[derive(Clone)]
-pub struct Nodes(UnboundedSender<(Message, SocketAddr)>, Arc<Vec<Node>>);
+pub struct Nodes {
+    /// the index of the local node
+    pid: usize,
+    /// the codec to encode outgoing messages with
+    codec: MessageCodec,
+    /// the outgoing channel of encoded messages, drained by the outgoing socket
+    tx: Sender<(Bytes, SocketAddr)>,
+    /// every node known to the system, including the local node, and which of them are members
+    membership: Arc<RwLock<Membership>>,
+    /// the maximum number of retransmissions of a view change, if in reliable mode
+    max_retransmits: Option<u32>,
+    /// unacknowledged view changes, keyed by their server id and attempted view
+    unacked: HashMap<(u32, u32), Unacked>,
+    /// the simulated loss of outgoing datagrams, if any
+    loss: Option<Loss>,
+    /// the simulated latency of datagrams sent to each node, by index, for nodes that have any
+    latency: HashMap<usize, Duration>,
+    /// the counters to record the traffic sent in, if counting it
+    metrics: Option<Arc<Metrics>>,
+    /// the number of consecutive failed interactions with each node, by index, for nodes that have
+    /// any
+    failures: HashMap<usize, u32>,
+    /// the number of consecutive failed interactions with a node before it's suspected dead
+    failure_threshold: u32,
+    /// the limit on the rate of outgoing multicasts, if any
+    rate_limit: Option<RateLimit>,
+}
+
+/// A means for the protocol to send messages to the other nodes in the system.
+pub trait Transport {
+    /// Gets the number of nodes in the system, including the local node.
+    fn len(&self) -> usize;
+
+    /// Gets the number of votes the node with the given index carries towards a quorum. Every node
+    /// carries one unless the transport overrides this.
+    fn weight(&self, _idx: usize) -> u32 {
+        1
+    }
+
+    /// Gets the number of nodes known to the system, members or not, which is as many as a
+    /// reconfiguration can make members. Transports with a fixed set of nodes only know their
+    /// members.
+    fn known(&self) -> usize {
+        self.len()
+    }
+
+    /// Reconfigures the system to consist of the first `members` nodes as of the given epoch,
+    /// ignoring epochs at or before the current one. Transports with a fixed set of nodes fail with
+    /// `Other`.
+    fn reconfigure(&mut self, _epoch: u32, _members: usize) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "the nodes of this transport are fixed"))
+    }
+
+    /// Sends the given message to every node except the local one.
+    fn multicast_send(&mut self, msg: Message) -> io::Result<()>;
+
+    /// Sends the given message to the single node with the given index.
+    fn unicast_send(&mut self, msg: Message, target: usize) -> io::Result<()>;
+
+    /// Determines whether or not view changes are sent reliably, i.e. require acknowledgements.
+    /// Transports are unreliable unless they override this.
+    fn is_reliable(&self) -> bool {
+        false
+    }
+
+    /// Sets the maximum number of retransmissions of a view change, enabling reliable mode, or
+    /// disables reliable mode if `None`. Transports without a reliable mode ignore this.
+    fn set_max_retransmits(&mut self, _max_retransmits: Option<u32>) {}
+
+    /// Records that the node with the given index acknowledged the view change with the given key.
+    fn acknowledge(&mut self, _from: usize, _key: (u32, u32)) {}
+
+    /// Retransmits every unacknowledged view change to the nodes that have yet to acknowledge it.
+    fn retransmit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Waits until there is room to send at least one more message. Transports that never fill up
+    /// are always ready.
+    fn poll_ready(&mut self, _ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Records that an interaction with the node with the given index failed (e.g. it didn't
+    /// answer a ping or acknowledge a view change). Transports that don't track failures ignore
+    /// this.
+    fn record_failure(&mut self, _idx: usize) {}
+
+    /// Records that an interaction with the node with the given index succeeded (e.g. we heard
+    /// from it), clearing its failures. Transports that don't track failures ignore this.
+    fn record_success(&mut self, _idx: usize) {}
+
+    /// Gets the indices of the nodes suspected dead, having failed too many interactions in a row.
+    /// Transports that don't track failures suspect no one.
+    fn suspected_dead(&self) -> HashSet<usize> {
+        HashSet::new()
+    }
+}
 
 impl Nodes {
-    pub fn len(&self) -> usize {
-        self.1.len()
+    /// Gets the most recently resolved address of the node with the given index, if it's still
+    /// known (since reloading the hostfile can drop nodes).
+    fn addr(&self, idx: usize) -> Option<SocketAddr> {
+        self.membership.read().unwrap().hosts.get(idx).map(Node::addr)
+    }
+
+    /// Encodes the given message once, so that it can be enqueued for any number of nodes.
+    #[throws(io::Error)]
+    fn encode(&self, msg: Message) -> Bytes {
+        let mut buf = BytesMut::with_capacity(ENCODING_CAPACITY);
+        let mut codec = self.codec.clone();
+        codec.encode(msg, &mut buf)?;
+        buf.freeze()
+    }
+
+    /// Enqueues the given message, already encoded, to be sent to the node with the given index by
+    /// the outgoing socket, after its simulated latency if it has any (and after being held back
+    /// for the given time). Fails with `WouldBlock` if the outgoing channel is full, or with
+    /// `BrokenPipe` if the outgoing socket has gone away (e.g. during shutdown).
+    #[throws(io::Error)]
+    fn enqueue(&mut self, msg: Message, datagram: Bytes, target: usize, held: Duration) -> () {
+        let addr = match self.addr(target) {
+            Some(addr) => addr,
+            None => {
+                warn!("dropping datagram to node {}, which is no longer known", target);
+                return
+            }
+        };
+        if let Some(ref mut loss) = self.loss {
+            if loss.rng.gen_bool(loss.rate) {
+                info!("simulating loss of datagram to {:?}: {:?}", addr, datagram);
+                return
+            }
+        }
+        let bytes = datagram.len();
+        let latency = self.latency.get(&target).cloned().unwrap_or_default() + held;
+        if latency > Duration::from_millis(0) {
+            trace!("send to {:?} in {:?}: {:?}", addr, latency, datagram);
+            let (mut tx, metrics) = (self.tx.clone(), self.metrics.clone());
+            // the delayed send holds on to the channel, so shutdown still waits to flush it, and
+            // only counts the datagram once it's actually queued for the outgoing socket
+            tokio::spawn(async move {
+                timer::delay_for(latency).await;
+                match tx.send((datagram, addr)).await {
+                    Ok(()) => if let Some(metrics) = metrics { metrics.count_sent(&msg, bytes) },
+                    Err(_) => warn!("outgoing socket stopped before a delayed send to {:?}", addr),
+                }
+            });
+            return
+        }
+        trace!("send to {:?}: {:?}", addr, datagram);
+        if let Err(e) = self.tx.try_send((datagram, addr)) {
+            let kind =
+                if e.is_full() { io::ErrorKind::WouldBlock } else { io::ErrorKind::BrokenPipe };
+            throw!(io::Error::new(kind, e.to_string()))
+        }
+        self.count_sent(&msg, bytes);
+    }
+
+    /// Counts the given message as sent, if counting traffic.
+    fn count_sent(&self, msg: &Message, bytes: usize) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.count_sent(msg, bytes);
+        }
+    }
+}
+
+impl Transport for Nodes {
+    fn len(&self) -> usize {
+        self.membership.read().unwrap().len
+    }
+
+    fn weight(&self, idx: usize) -> u32 {
+        self.membership.read().unwrap().hosts.get(idx).map_or(0, |node| node.weight)
+    }
+
+    fn known(&self) -> usize {
+        self.membership.read().unwrap().hosts.len()
+    }
+
+    #[throws(io::Error)]
+    fn reconfigure(&mut self, epoch: u32, members: usize) -> () {
+        let mut membership = self.membership.write().unwrap();
+        if members == 0 || members > membership.hosts.len() {
+            throw!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot have {} of the {} known nodes as members",
+                        members, membership.hosts.len()),
+            ))
+        }
+        // every handle shares the membership, so another one may have gotten here first
+        if epoch <= membership.epoch { return }
+        info!("reconfiguring from {} to {} nodes in epoch {}", membership.len, members, epoch);
+        membership.epoch = epoch;
+        membership.len = members;
     }
 
     #[throws(io::Error)]
-    pub fn multicast_send(&mut self, msg: Message) -> () {
+    fn multicast_send(&mut self, msg: Message) -> () {
         info!("multicasting {:?}", msg);
-        for node in self.1.iter() {
-            trace!("send to {:?}: {:?}", node.addr, msg);
-            self.0.try_send((msg, node.addr)).unwrap();
+        let len = self.len();
+        let datagram = self.encode(msg)?;
+
+        // in reliable mode, keep track of view changes until everyone acknowledges them, starting
+        // before they're sent so that the ones we can't enqueue are still retransmitted later
+        if self.is_reliable() {
+            if let Message::ViewChange { server_id, attempted } = msg {
+                let pending = (0..len).filter(|idx| *idx != self.pid).collect();
+                let unacked = Unacked { msg, datagram: datagram.clone(), pending, retries: 0 };
+                self.unacked.insert((server_id, attempted), unacked);
+            }
+        }
+
+        let held = match self.rate_limit.as_mut().map(RateLimit::take) {
+            Some(None) => {
+                warn!("dropping multicast of {:?} past the rate limit", msg);
+                return
+            },
+            Some(Some(held)) => held,
+            None => Duration::from_millis(0),
+        };
+        for idx in 0..len {
+            if idx == self.pid { continue }
+            self.enqueue(msg, datagram.clone(), idx, held)?;
+        }
+    }
+
+    #[throws(io::Error)]
+    fn unicast_send(&mut self, msg: Message, target: usize) -> () {
+        if target >= self.len() {
+            throw!(io::Error::new(
+                io::ErrorKind::InvalidInput, format!("no node with index {}", target)
+            ))
+        }
+        info!("unicasting {:?} to {}", msg, target);
+        let datagram = self.encode(msg)?;
+        self.enqueue(msg, datagram, target, Duration::from_millis(0))?;
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.max_retransmits.is_some()
+    }
+
+    fn set_max_retransmits(&mut self, max_retransmits: Option<u32>) {
+        self.max_retransmits = max_retransmits;
+        if max_retransmits.is_none() {
+            self.unacked.clear();
+        }
+    }
+
+    fn acknowledge(&mut self, from: usize, key: (u32, u32)) {
+        self.record_success(from);
+        if let Some(unacked) = self.unacked.get_mut(&key) {
+            unacked.pending.remove(&from);
+            if unacked.pending.is_empty() {
+                trace!("view change {:?} acknowledged by everyone", key);
+                self.unacked.remove(&key);
+            }
+        }
+    }
+
+    /// Retransmits every unacknowledged view change to the nodes that have yet to acknowledge it,
+    /// giving up on the ones that have exhausted their retransmissions.
+    #[throws(io::Error)]
+    fn retransmit(&mut self) -> () {
+        let max_retransmits = match self.max_retransmits {
+            Some(max_retransmits) => max_retransmits,
+            None => return,
+        };
+
+        let mut resend = Vec::new();
+        let mut unresponsive = Vec::new();
+        self.unacked.retain(|key, unacked| {
+            if unacked.retries >= max_retransmits {
+                warn!("giving up on view change {:?}, unacknowledged by {:?}",
+                      key, unacked.pending);
+                return false
+            }
+            unacked.retries += 1;
+            info!("retransmitting {:?}", unacked.msg);
+            for idx in unacked.pending.iter() {
+                resend.push((unacked.msg, unacked.datagram.clone(), *idx));
+                unresponsive.push(*idx);
+            }
+            true
+        });
+
+        // every node we have to retransmit to failed to acknowledge in time
+        for idx in unresponsive {
+            self.record_failure(idx);
+        }
+
+        for (msg, datagram, idx) in resend {
+            self.enqueue(msg, datagram, idx, Duration::from_millis(0))?;
+        }
+    }
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.tx.poll_ready(ctx).map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))
+    }
+
+    fn record_failure(&mut self, idx: usize) {
+        let failures = self.failures.entry(idx).or_insert(0);
+        *failures = failures.saturating_add(1);
+        if *failures == self.failure_threshold {
+            warn!("suspecting node {} is dead after {} failures in a row", idx, failures);
+        }
+    }
+
+    fn record_success(&mut self, idx: usize) {
+        if let Some(failures) = self.failures.remove(&idx) {
+            if failures >= self.failure_threshold {
+                info!("no longer suspecting node {} is dead", idx);
+            }
+        }
+    }
+
+    fn suspected_dead(&self) -> HashSet<usize> {
+        self.failures.iter()
+            .filter(|(_, failures)| **failures >= self.failure_threshold)
+            .map(|(idx, _)| *idx)
+            .collect()
+    }
+}
+
+/// Whether or not each peer of the local node answered a query during `System::check`.
+#[derive(Clone, Debug)]
+pub struct Reachability {
+    /// the hostname and address of every peer in order of pid, along with whether it answered
+    peers: Vec<(String, SocketAddr, bool)>,
+}
+
+impl Reachability {
+    /// Determines whether or not every peer answered.
+    pub fn all_reachable(&self) -> bool {
+        self.peers.iter().all(|(_, _, reachable)| *reachable)
+    }
+
+    /// Gets the hostnames of the peers that did not answer.
+    pub fn unreachable(&self) -> Vec<&str> {
+        self.peers.iter()
+            .filter(|(_, _, reachable)| !*reachable)
+            .map(|(hostname, _, _)| hostname.as_str())
+            .collect()
+    }
+
+    /// Gets the exit code for a check with this outcome, which is non-zero unless every peer
+    /// answered.
+    pub fn exit_code(&self) -> i32 {
+        if self.all_reachable() { 0 } else { 1 }
+    }
+}
+
+/// The report lists one peer per line, e.g. `node2 (10.0.0.2:42069): reachable`.
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (hostname, addr, reachable) in self.peers.iter() {
+            let status = if *reachable { "reachable" } else { "UNREACHABLE" };
+            writeln!(f, "{} ({}): {}", hostname, addr, status)?;
+        }
+        Ok(())
+    }
+}
+
+/// The nodes of a system as resolved from its hosts, along with the pid of the local node.
+#[derive(Clone, Debug)]
+pub struct Topology {
+    /// the index of the local node
+    pub pid: usize,
+    /// the hostname and resolved address of every node in order of pid
+    pub nodes: Vec<(String, SocketAddr)>,
+}
+
+/// The topology lists the local pid, then one node per line, e.g. `1: node1 (10.0.0.1:42069)`,
+/// with the local node marked.
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "pid: {}", self.pid)?;
+        for (idx, (hostname, addr)) in self.nodes.iter().enumerate() {
+            let local = if idx == self.pid { " (local)" } else { "" };
+            writeln!(f, "{}: {} ({}){}", idx, hostname, addr, local)?;
         }
+        Ok(())
     }
 }
 
 pub struct System {
     pid: usize,
+    /// the local address the sockets are bound to
+    bind: IpAddr,
+    port: u16,
+    /// how long to keep trying to bind the outgoing socket for while its port is in use
+    bind_window: Duration,
     incoming: ProtocolSocket,
-    opt_rx: Option<UnboundedReceiver<(Message, SocketAddr)>>,
+    opt_rx: Option<Receiver<(Bytes, SocketAddr)>>,
     nodes: Nodes,
+    /// the number of times to re-send a datagram after the outgoing socket fails transiently
+    send_retries: u32,
+    /// the sink to send outgoing datagrams through in place of the outgoing socket, if any
+    outgoing_sink: Option<OutgoingSink>,
+    /// the path to answer admin commands on, if any
+    #[cfg(unix)]
+    admin_path: Option<PathBuf>,
+    /// the capture to record received messages in, if recording
+    #[cfg(feature = "capture")]
+    capture: Option<Capture>,
+    /// the captured messages to replay in place of received ones, if replaying
+    #[cfg(feature = "capture")]
+    replay: Option<Vec<Captured>>,
+    /// the binary capture to record received messages in, if recording one
+    #[cfg(feature = "capture")]
+    binary_capture: Option<BinaryCapture>,
+    /// the datagrams from a binary capture to replay in place of received ones, if replaying one
+    #[cfg(feature = "capture")]
+    binary_replay: Option<Vec<CapturedDatagram>>,
+    /// the messages injected alongside the ones received, if there's a handle to inject them
+    #[cfg(any(test, feature = "inject"))]
+    injected: Option<futures::channel::mpsc::UnboundedReceiver<(Message, SocketAddr)>>,
 }
 
 impl System {
+    /// Creates a system of the given hosts, with the local node being the one with the given
+    /// hostname, bound to every IPv4 interface. Fails with `NotFound` if the local hostname is not
+    /// among the hosts.
+    #[throws(io::Error)]
+    pub async fn from_hosts(
+        hosts: Vec<String>, hostname: &str, port: u16, codec: MessageCodec
+    ) -> System {
+        System::from_hosts_on(hosts, hostname, BIND_ADDRESS, port, codec).await?
+    }
+
+    /// Creates a system like `from_hosts`, but with its sockets bound to the given local address,
+    /// whose address family (IPv4 or IPv6) is also preferred when resolving the hosts.
+    #[throws(io::Error)]
+    pub async fn from_hosts_on(
+        hosts: Vec<String>, hostname: &str, bind: IpAddr, port: u16, codec: MessageCodec
+    ) -> System {
+        System::from_hosts_within(hosts, hostname, bind, port, codec, RESOLVE_TIMEOUT, BIND_WINDOW)
+            .await?
+    }
+
+    /// Creates a system like `from_hosts_on`, but giving up on resolving each host once the given
+    /// timeout passes, rather than after `RESOLVE_TIMEOUT`, and on binding each socket while its
+    /// port is in use once the given window passes, rather than after `BIND_WINDOW`.
+    #[throws(io::Error)]
+    pub async fn from_hosts_within(
+        hosts: Vec<String>, hostname: &str, bind: IpAddr, port: u16, codec: MessageCodec,
+        resolve_timeout: Duration, bind_window: Duration,
+    ) -> System {
+        let (pid, nodes) =
+            System::resolve_hosts(&hosts, hostname, bind, port, resolve_timeout).await?;
+        let incoming = incoming_socket(bind, port, codec.clone(), bind_window).await?;
+        let mut system = System::assemble(pid, bind, port, nodes, incoming, codec);
+        system.bind_window = bind_window;
+        system
+    }
+
+    /// Creates a system like `from_hosts`, but exchanging datagrams over the given channels rather
+    /// than binding sockets of its own, receiving over the first and sending over the second. This
+    /// allows simulating many nodes within one process, e.g. by attaching each to a `MemoryMesh`
+    /// at the addresses its hostname resolves to (receiving on the port and sending from the one
+    /// after it, as the sockets would).
+    #[throws(io::Error)]
+    pub async fn from_hosts_over<I, O>(
+        hosts: Vec<String>, hostname: &str, port: u16, codec: MessageCodec, incoming: I, outgoing: O
+    ) -> System
+    where
+        I: DatagramChannel + 'static,
+        O: DatagramChannel + 'static,
+    {
+        let (pid, nodes) =
+            System::resolve_hosts(&hosts, hostname, BIND_ADDRESS, port, RESOLVE_TIMEOUT).await?;
+        let incoming = DatagramFramed::new(incoming, codec.clone());
+        let mut system = System::assemble(pid, BIND_ADDRESS, port, nodes, incoming, codec);
+        system.send_through(DatagramFramed::new(outgoing, BytesCodec::new()));
+        system
+    }
+
+    /// Resolves every one of the given hosts the way creating a system of them would, with the
+    /// local node being the one with the given hostname, but without binding any sockets. Fails
+    /// like `from_hosts_within`.
     #[throws(io::Error)]
-    pub async fn from_hosts(hosts: Vec<String>, hostname: &str) -> System {
-        let pid = hosts.iter().take_while(|curr_host| curr_host != &hostname).count();
-        let nodes: io::Result<Vec<_>> = hosts.iter().map(Node::resolve_from_hostname).collect();
-        let incoming = incoming_socket().await?;
-        let (tx, rx) = mpsc::unbounded_channel();
+    pub async fn topology(
+        hosts: Vec<String>, hostname: &str, bind: IpAddr, port: u16, resolve_timeout: Duration,
+    ) -> Topology {
+        let (pid, nodes) =
+            System::resolve_hosts(&hosts, hostname, bind, port, resolve_timeout).await?;
+        let nodes = nodes.iter().map(|node| (node.hostname.clone(), node.addr())).collect();
+        Topology { pid, nodes }
+    }
+
+    /// Resolves every one of the given hosts, each of which may carry a weight, returning the
+    /// index of the one with the given hostname along with all the nodes. Fails with `NotFound`
+    /// if the local hostname is not among the hosts.
+    #[throws(io::Error)]
+    async fn resolve_hosts(
+        hosts: &[String], hostname: &str, bind: IpAddr, port: u16, resolve_timeout: Duration,
+    ) -> (usize, Vec<Node>) {
+        let weighted = hosts.iter()
+            .map(|host| split_weight(host))
+            .collect::<io::Result<Vec<_>>>()?;
+        let pid = match weighted.iter().position(|(curr_host, _)| *curr_host == hostname) {
+            Some(pid) => pid,
+            None => throw!(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("local host {} is not one of the hosts {:?}", hostname, hosts),
+            )),
+        };
+        let mut nodes = Vec::with_capacity(hosts.len());
+        for (host, weight) in weighted.iter() {
+            let node = Node::resolve_from_hostname(host, port, bind, resolve_timeout).await?;
+            nodes.push(Node { weight: *weight, ..node });
+        }
+        (pid, nodes)
+    }
+
+    /// Puts together a system of the given resolved nodes, receiving over the given socket.
+    fn assemble(
+        pid: usize, bind: IpAddr, port: u16, nodes: Vec<Node>, incoming: ProtocolSocket,
+        codec: MessageCodec,
+    ) -> System {
+        let len = nodes.len();
+        let (tx, rx) = mpsc::channel(OUTGOING_CAPACITY);
         System {
-            pid, incoming,
+            pid, bind, port, incoming,
+            bind_window: BIND_WINDOW,
             opt_rx: Some(rx),
-            nodes: Nodes(tx, Arc::new(nodes?))
+            nodes: Nodes {
+                pid, codec, tx,
+                membership: Arc::new(RwLock::new(Membership { epoch: 0, hosts: nodes, len })),
+                max_retransmits: None,
+                unacked: HashMap::new(),
+                loss: None,
+                latency: HashMap::new(),
+                metrics: None,
+                failures: HashMap::new(),
+                failure_threshold: FAILURE_THRESHOLD,
+                rate_limit: None,
+            },
+            send_retries: SEND_RETRIES,
+            outgoing_sink: None,
+            #[cfg(unix)]
+            admin_path: None,
+            #[cfg(feature = "capture")]
+            capture: None,
+            #[cfg(feature = "capture")]
+            replay: None,
+            #[cfg(feature = "capture")]
+            binary_capture: None,
+            #[cfg(feature = "capture")]
+            binary_replay: None,
+            #[cfg(any(test, feature = "inject"))]
+            injected: None,
+        }
+    }
+
+    /// Gets the process id of the local node.
+    pub fn pid(&self) -> usize {
+        self.pid
+    }
+
+    /// Gets all the nodes in the system.
+    pub fn nodes(&self) -> Nodes {
+        self.nodes.clone()
+    }
+
+    /// Gets all the nodes in the system, sending every message in the given group, for running one
+    /// of several groups sharing this system's sockets with `paxos_groups`.
+    pub fn group_nodes(&self, group_id: u32) -> Nodes {
+        let mut nodes = self.nodes.clone();
+        nodes.codec.group_id = Some(group_id);
+        nodes
+    }
+
+    /// Sets the number of messages that can be queued for the outgoing socket at once, replacing
+    /// the outgoing channel. Handles to the nodes taken before this keep sending into the old one.
+    pub fn set_outgoing_capacity(&mut self, capacity: usize) {
+        let (tx, rx) = mpsc::channel(capacity);
+        self.nodes.tx = tx;
+        self.opt_rx = Some(rx);
+    }
+
+    /// Sets the number of times a datagram is re-sent after the outgoing socket fails to send it
+    /// with a transient error (e.g. a full send buffer), before giving up and stopping the node.
+    pub fn set_send_retries(&mut self, retries: u32) {
+        self.send_retries = retries;
+    }
+
+    /// Sets the number of consecutive failed interactions with a node (e.g. unanswered pings or
+    /// unacknowledged view changes) before it's suspected dead, which only covers the handles to
+    /// the nodes taken from now on.
+    pub fn set_failure_threshold(&mut self, threshold: u32) {
+        self.nodes.failure_threshold = threshold;
+    }
+
+    /// Sends every outgoing datagram through the given sink rather than binding the outgoing
+    /// socket, e.g. to capture exactly what the node puts on the wire and where in a test.
+    pub fn send_through<S>(&mut self, sink: S)
+    where S: Sink<(Bytes, SocketAddr), Error = io::Error> + Send + 'static {
+        self.outgoing_sink = Some(Box::pin(sink));
+    }
+
+    /// Drops each outgoing datagram with the given probability, simulating an unreliable network.
+    /// Drops are decided by a generator seeded with the given seed (combined with the pid, so that
+    /// nodes sharing a seed still differ), or randomly if `None`. Fails with `InvalidInput` unless
+    /// the probability is between 0 and 1.
+    #[throws(io::Error)]
+    pub fn drop_outgoing(&mut self, rate: f64, seed: Option<u64>) -> () {
+        if !(0.0..=1.0).contains(&rate) {
+            throw!(io::Error::new(
+                io::ErrorKind::InvalidInput, format!("drop rate {} is not a probability", rate)
+            ))
+        }
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(self.pid as u64)),
+            None => StdRng::from_entropy(),
+        };
+        info!("dropping outgoing datagrams with probability {}", rate);
+        self.nodes.loss = Some(Loss { rate, rng });
+    }
+
+    /// Limits outgoing multicasts to the given number per second, dropping or queueing the ones
+    /// past the limit according to the given policy. Only covers the handles to the nodes taken
+    /// from now on. Fails with `InvalidInput` unless the rate is positive.
+    #[throws(io::Error)]
+    pub fn limit_multicast_rate(&mut self, rate: f64, policy: RateLimitPolicy) -> () {
+        if !rate.is_finite() || rate <= 0.0 {
+            throw!(io::Error::new(
+                io::ErrorKind::InvalidInput, format!("multicast rate {} is not positive", rate)
+            ))
+        }
+        info!("limiting multicasts to {} per second, {:?} past it", rate, policy);
+        let tokens = rate.max(1.0);
+        self.nodes.rate_limit = Some(RateLimit { rate, tokens, refilled: Instant::now(), policy });
+    }
+
+    /// Delays every datagram sent to the node with the given index by the given latency, simulating
+    /// a slow link to it. Fails with `InvalidInput` if there is no such node.
+    #[throws(io::Error)]
+    pub fn delay_outgoing(&mut self, target: usize, latency: Duration) -> () {
+        if target >= self.nodes.len() {
+            throw!(io::Error::new(
+                io::ErrorKind::InvalidInput, format!("no node with index {}", target)
+            ))
+        }
+        info!("delaying datagrams to node {} by {:?}", target, latency);
+        self.nodes.latency.insert(target, latency);
+    }
+
+    /// Spawns a thread re-resolving the hostname of every node on the given interval, so that
+    /// peers that come back at a new address (e.g. after a container restart) stay reachable. The
+    /// thread stops once every handle to the nodes has been dropped.
+    pub fn re_resolve_every(&self, interval: Duration) {
+        let membership = Arc::downgrade(&self.nodes.membership);
+        thread::spawn(move || re_resolve_loop(membership, interval));
+    }
+
+    /// Spawns a task reloading the hosts from the hostfile at the given path each time the process
+    /// receives SIGHUP, without disturbing the protocol. The reloads themselves run on a thread of
+    /// their own, since loading the hostfile and resolving new hosts block. If the hostfile is
+    /// invalid, the current hosts are kept and the error is logged. Hangups are ignored once every
+    /// handle to the nodes has been dropped.
+    #[cfg(unix)]
+    #[throws(io::Error)]
+    pub fn reload_on_hangup<P: Into<PathBuf>>(&self, path: P) -> () {
+        let mut hangups = Signal::new(SignalKind::hangup())?;
+        let membership = Arc::downgrade(&self.nodes.membership);
+        let (pid, path) = (self.pid, path.into());
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || reload_loop(membership, pid, path, rx));
+        tokio::spawn(async move {
+            while let Some(()) = hangups.next().await {
+                // the reloading thread stops once the nodes are gone
+                if tx.send(()).is_err() { break }
+            }
+        });
+    }
+
+    /// Counts the traffic of every message sent or received in the given metrics, which only
+    /// covers the handles to the nodes taken from now on.
+    pub fn count_traffic(&mut self, metrics: Arc<Metrics>) {
+        self.nodes.metrics = Some(metrics);
+    }
+
+    /// Spawns a task serving the given metrics over HTTP on the given port in the background.
+    pub fn serve_metrics(&self, metrics: Arc<Metrics>, port: u16) {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics, port).await {
+                error!("metrics endpoint failed: {}", e);
+            }
+        });
+    }
+
+    /// Answers admin commands on a Unix socket at the given path while the protocol runs, replacing
+    /// any socket left there by an earlier run.
+    #[cfg(unix)]
+    pub fn serve_admin_on<P: Into<PathBuf>>(&mut self, path: P) {
+        self.admin_path = Some(path.into());
+    }
+
+    /// Records every message received from now on, along with its sender and arrival time, in a
+    /// capture file at the given path.
+    #[cfg(feature = "capture")]
+    #[throws(io::Error)]
+    pub fn record_to<P: AsRef<Path>>(&mut self, path: P) -> () {
+        self.capture = Some(Capture::create(path)?);
+    }
+
+    /// Replays the messages recorded in the capture file at the given path, with their original
+    /// inter-arrival delays, in place of the messages received over the network.
+    #[cfg(feature = "capture")]
+    #[throws(io::Error)]
+    pub fn replay_from<P: AsRef<Path>>(&mut self, path: P) -> () {
+        self.replay = Some(capture::load(path)?);
+    }
+
+    /// Records every message received from now on, along with its arrival time, in a binary
+    /// capture file at the given path, encoded by this system's codec.
+    #[cfg(feature = "capture")]
+    #[throws(io::Error)]
+    pub fn capture_to<P: AsRef<Path>>(&mut self, path: P) -> () {
+        self.binary_capture = Some(BinaryCapture::create(path, self.nodes.codec.clone())?);
+    }
+
+    /// Replays the messages recorded in the binary capture file at the given path, decoded by this
+    /// system's codec with their original timing, in place of the messages received over the
+    /// network.
+    #[cfg(feature = "capture")]
+    #[throws(io::Error)]
+    pub fn replay_binary_from<P: AsRef<Path>>(&mut self, path: P) -> () {
+        self.binary_replay = Some(capture::load_binary(path)?);
+    }
+
+    /// Creates a handle injecting messages into the protocol once it runs, as if received over the
+    /// incoming socket from the given addresses, e.g. to drive it deterministically in a test. Only
+    /// the latest handle created is listened to.
+    #[cfg(any(test, feature = "inject"))]
+    pub fn injector(&mut self) -> Injector {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        self.injected = Some(rx);
+        tx
+    }
+
+    /// Takes the sink to send outgoing datagrams through, which is a newly bound outgoing socket
+    /// unless another sink was given to send through.
+    #[throws(io::Error)]
+    async fn take_outgoing_sink(&mut self) -> OutgoingSink {
+        match self.outgoing_sink.take() {
+            Some(sink) => sink,
+            None => Box::pin(outgoing_socket(self.bind, self.port, self.bind_window).await?),
         }
     }
 
     /// gets the outgoing receiver from this system, fails on subsequent attempts
-    fn take_outgoing(&mut self) -> UnboundedReceiver<(Message, SocketAddr)> {
+    fn take_outgoing(&mut self) -> Receiver<(Bytes, SocketAddr)> {
         self.opt_rx.take().unwrap()
     }
 
+    /// Binds the admin socket, if there is one, and hooks it up to the given protocol, returning a
+    /// future serving it. Without an admin socket, the future never completes.
+    #[cfg(unix)]
+    #[throws(io::Error)]
+    fn admin_future(&self, paxos: &mut Paxos) -> AdminFuture {
+        let path = match self.admin_path {
+            Some(ref path) => path,
+            None => return Box::pin(future::pending()),
+        };
+        // a socket left behind by a run that didn't clean up would keep us from binding
+        match fs::symlink_metadata(path) {
+            Ok(ref meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+            _ => (),
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("serving admin commands on {}", path.display());
+        let (tx, rx) = mpsc::channel(1);
+        paxos.serve_admin(rx);
+        Box::pin(admin::serve(listener, tx))
+    }
+
+    /// Checks that every peer is reachable, rather than running the protocol, by sending each a
+    /// view query and waiting up to the given timeout for their proofs in reply.
+    #[throws(io::Error)]
+    pub async fn check(mut self, timeout: Duration) -> Reachability {
+        let outgoing_socket = self.take_outgoing_sink().await?;
+        let outgoing = self.take_outgoing();
+        let peers: Vec<_> = {
+            let membership = self.nodes.membership.read().unwrap();
+            (0..membership.len)
+                .filter(|idx| *idx != self.pid)
+                .map(|idx| {
+                    let node = &membership.hosts[idx];
+                    (idx, node.hostname.clone(), node.addr())
+                })
+                .collect()
+        };
+        let mut pending: HashSet<_> = peers.iter().map(|(idx, _, _)| *idx).collect();
+        for idx in pending.iter() {
+            self.nodes.unicast_send(Message::ViewQuery { server_id: self.pid as u32 }, *idx)?;
+        }
+
+        // drop our handle to the nodes so that the outgoing channel closes once it's all sent
+        drop(self.nodes);
+        send_outgoing(outgoing, outgoing_socket, self.send_retries).await?;
+
+        let mut incoming = self.incoming.fuse();
+        let mut deadline = timer::delay_for(timeout).fuse();
+        while !pending.is_empty() {
+            select! {
+                opt_res = incoming.next() => match opt_res {
+                    Some(Ok((Message::VCProof { server_id, .. }, addr))) => {
+                        trace!("proof from {} at {:?}", server_id, addr);
+                        pending.remove(&(server_id as usize));
+                    },
+                    Some(Ok(_)) => (),
+                    Some(Err(ref e)) if is_malformed(e) => {
+                        warn!("skipping incoming datagram: {}", e);
+                    },
+                    Some(Err(e)) => throw!(e),
+                    None => break,
+                },
+                _ = deadline => break,
+            }
+        }
+
+        let peers = peers.into_iter()
+            .map(|(idx, hostname, addr)| (hostname, addr, !pending.contains(&idx)))
+            .collect();
+        Reachability { peers }
+    }
+
+    /// Runs the protocol until it finishes, then flushes any outgoing messages (for up to
+    /// `DRAIN_TIMEOUT`) before returning.
     #[throws]
-    #[allow(unreachable_code)]
-    pub async fn paxos(
-        mut self, test_case: TestCase, progress_timer_length: u64, vc_proof_timer_length: u64
-    ) -> ! {
-        // create an outgoing socket to actually forward sent messages along
-        let outgoing_socket = outgoing_socket().await?;
-        let mut outgoing_future = self.take_outgoing().map(|m| Ok(m)).forward(outgoing_socket);
+    pub async fn paxos(mut self, config: PaxosConfig) -> () {
+        // create an outgoing socket to actually forward sent messages (already encoded) along
+        let outgoing_socket = self.take_outgoing_sink().await?;
+        let outgoing = self.take_outgoing();
+        let mut outgoing_future = send_outgoing(outgoing, outgoing_socket, self.send_retries)
+            .boxed()
+            .fuse();
 
         // create a new instance of the Paxos protocol
-        let paxos = Paxos::new(PaxosConfig {
-            pid: self.pid,
-            nodes: self.nodes.clone(),
-            test_case, progress_timer_length, vc_proof_timer_length
-        })?;
+        let mut paxos = Paxos::new(config)?;
+
+        // answer admin commands alongside the protocol, if asked to
+        #[cfg(unix)]
+        let mut admin_future = self.admin_future(&mut paxos)?.fuse();
+        #[cfg(not(unix))]
+        let mut admin_future = future::pending::<io::Result<()>>().fuse();
+
+        // drop our own handle to the nodes, so that the outgoing channel closes with paxos, keeping
+        // just what we need to count the traffic received
+        let (codec, metrics) = (self.nodes.codec.clone(), self.nodes.metrics.clone());
+        drop(self.nodes);
 
         // split paxos into a separate sink and stream
         let (paxos_inc, paxos_out) = paxos.split();
 
-        // forward received messages to the protocol implementation
-        let mut incoming_future = self.incoming
-            .map(|result| result.map(|msg_with_addr| msg_with_addr.0))
-            .forward(paxos_inc);
+        // forward received messages to the protocol implementation, skipping malformed datagrams
+        // so that one bad datagram can't stop the node, while socket errors still stop it
+        let skipped_metrics = metrics.clone();
+        let received = self.incoming
+            .filter_map(move |result| future::ready(match result {
+                Err(ref e) if is_malformed(e) => {
+                    warn!("skipping incoming datagram: {}", e);
+                    match skipped_metrics {
+                        Some(ref metrics) if Rejected::is_unauthenticated(e) =>
+                            Metrics::incr(&metrics.unauthenticated_messages),
+                        _ => (),
+                    }
+                    None
+                },
+                result => Some(result),
+            }));
+        #[cfg(any(test, feature = "inject"))]
+        let received = stream::select(
+            received, stream::iter(self.injected.take()).flatten().map(Ok),
+        );
+        #[cfg(feature = "capture")]
+        let received = capture::wrap(received, self.capture.take(), self.replay.take());
+        #[cfg(feature = "capture")]
+        let received = {
+            let replay = self.binary_replay.take().map(|captured| (captured, codec.clone()));
+            capture::wrap_binary(received, self.binary_capture.take(), replay)
+        };
+        let received = received.inspect(move |res| match (res, &metrics) {
+            (Ok((msg, _)), Some(metrics)) => metrics.count_received(msg, codec.encoded_len(*msg)),
+            _ => (),
+        });
+        let mut incoming_future = received.map(|res| res.map(|(msg, _)| msg)).forward(paxos_inc);
 
         let mut paxos_out = paxos_out.fuse();
 
+        // stop gracefully when asked to by the user or an orchestrator, rather than mid-send
+        let mut shutdown = shutdown_signals()?.fuse();
+
         loop {
             select! {
                 res = outgoing_future => {
@@ -148,10 +1290,1337 @@ impl System {
                     trace!("selected paxos stream: {:?}", opt_res);
                     match opt_res {
                         Some(res) => res?,
-                        None => (),
+                        None => break,
+                    }
+                },
+                res = admin_future => {
+                    trace!("selected admin future: {:?}", res);
+                    if let Err(e) = res {
+                        error!("admin socket failed: {}", e);
+                    }
+                },
+                _ = shutdown.next() => {
+                    info!("received shutdown signal, stopping");
+                    break
+                },
+            }
+        }
+
+        // the protocol has finished (or been stopped), so drop it (closing the outgoing channel)
+        // and wait for the outgoing socket to send everything that was enqueued before then
+        info!("paxos finished, flushing outgoing messages");
+        drop(incoming_future);
+        drop(paxos_out);
+        drop(admin_future);
+        #[cfg(unix)]
+        {
+            if let Some(ref path) = self.admin_path {
+                fs::remove_file(path)?;
+            }
+        }
+        drain_outgoing(outgoing_future).await?;
+    }
+
+    /// Runs a protocol for each of the given groups over this system's sockets, until every one
+    /// finishes or the process is asked to shut down, then flushes any outgoing messages before
+    /// returning. Each group's config must use the nodes from `group_nodes` for its id, so that its
+    /// messages carry that id, and every message received is routed to the protocol for the group
+    /// it carries, so that one group never sees the view changes of another. Messages for groups
+    /// not running here are ignored.
+    #[throws]
+    pub async fn paxos_groups(mut self, configs: Vec<(u32, PaxosConfig)>) -> () {
+        let outgoing_socket = self.take_outgoing_sink().await?;
+        let outgoing = self.take_outgoing();
+        let mut outgoing_future = send_outgoing(outgoing, outgoing_socket, self.send_retries)
+            .boxed()
+            .fuse();
+
+        // split every group's protocol into a sink for its own messages and a stream of its timers
+        let mut groups = HashMap::new();
+        let mut timers = Vec::new();
+        for (group_id, config) in configs {
+            let (paxos_inc, paxos_out) = Paxos::new(config)?.split();
+            groups.insert(group_id, paxos_inc);
+            timers.push(paxos_out);
+        }
+        let mut paxos_out = stream::select_all(timers);
+
+        // decode the messages of every group on the one incoming socket, rather than just our own
+        let codec = GroupedCodec(self.nodes.codec.clone());
+        drop(self.nodes);
+        let mut incoming = self.incoming.with_codec(codec).fuse();
+
+        let mut shutdown = shutdown_signals()?.fuse();
+
+        loop {
+            select! {
+                res = outgoing_future => {
+                    trace!("selected outgoing future: {:?}", res);
+                    res?
+                },
+                opt_res = incoming.next() => match opt_res {
+                    Some(Ok(((group_id, msg), _))) => match groups.get_mut(&group_id) {
+                        Some(paxos_inc) => paxos_inc.send(msg).await?,
+                        None => trace!("ignoring message for group {}: {:?}", group_id, msg),
+                    },
+                    Some(Err(ref e)) if is_malformed(e) => {
+                        warn!("skipping incoming datagram: {}", e);
+                    },
+                    Some(Err(e)) => throw!(e),
+                    None => break,
+                },
+                opt_res = paxos_out.next() => {
+                    trace!("selected paxos streams: {:?}", opt_res);
+                    match opt_res {
+                        Some(res) => res?,
+                        // every group has finished
+                        None => break,
                     }
                 },
+                _ = shutdown.next() => {
+                    info!("received shutdown signal, stopping");
+                    break
+                },
+            }
+        }
+
+        info!("paxos groups finished, flushing outgoing messages");
+        drop(groups);
+        drop(paxos_out);
+        drain_outgoing(outgoing_future).await?;
+    }
+}
+
+/// Waits for the given outgoing future to send every datagram enqueued before shutting down, which
+/// it finishes doing once every handle to the nodes is dropped. Gives up on whatever is left after
+/// `DRAIN_TIMEOUT`, so that a stuck socket (or a handle kept elsewhere) can't hang shutdown.
+#[throws(io::Error)]
+async fn drain_outgoing<F>(outgoing_future: F) -> ()
+    where F: FusedFuture<Output = io::Result<()>> + Unpin
+{
+    if outgoing_future.is_terminated() { return }
+    match future::select(outgoing_future, timer::delay_for(DRAIN_TIMEOUT)).await {
+        future::Either::Left((res, _)) => res?,
+        future::Either::Right(_) => {
+            warn!("gave up flushing outgoing messages after {:?}", DRAIN_TIMEOUT);
+        },
+    }
+}
+
+/// Sends every datagram from the given outgoing channel over the given socket until the channel
+/// closes, re-sending each one up to the given number of times if the socket fails transiently.
+#[throws(io::Error)]
+async fn send_outgoing<S>(
+    mut outgoing: Receiver<(Bytes, SocketAddr)>, mut socket: S, retries: u32
+) -> () where S: Sink<(Bytes, SocketAddr), Error = io::Error> + Unpin
+{
+    while let Some(datagram) = outgoing.next().await {
+        let mut res = socket.send(datagram).await;
+        let mut attempts = 0;
+        while let Err(e) = res {
+            if !is_transient(&e) || attempts == retries {
+                throw!(e)
+            }
+            attempts += 1;
+            warn!("failed to send datagram (retry {} of {}): {}", attempts, retries, e);
+            timer::delay_for(SEND_RETRY_DELAY).await;
+            // a socket that fails to flush keeps the datagram buffered, so flushing re-sends it
+            res = socket.flush().await;
+        }
+    }
+    socket.close().await?;
+}
+
+/// Determines whether or not the given error from the outgoing socket is likely to go away by
+/// itself, such that sending again shortly is worthwhile. Anything else is taken to be fatal.
+fn is_transient(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => true,
+        _ => false,
+    }
+}
+
+/// Determines whether or not the given error from the incoming socket came from decoding a
+/// malformed datagram (e.g. with an unknown tag or a bad checksum), rather than from the socket
+/// itself. The codec fails with `InvalidData` for every malformed message, rejected or not, and
+/// since the socket decodes each datagram on its own, it can go on receiving past one.
+fn is_malformed(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::InvalidData
+}
+
+/// Loads the hosts listed in the hostfile at the given path, as by `read_hostfile`. The path may
+/// also be `-` to read the hostfile from stdin, or (with the `http-hosts` feature) an `http://` or
+/// `https://` URL to fetch it from.
+#[throws(io::Error)]
+pub fn load_hostfile<P: AsRef<Path>>(path: P) -> Vec<String> {
+    let path = path.as_ref();
+    if path == Path::new("-") {
+        let stdin = io::stdin();
+        let hosts = read_hostfile(stdin.lock(), "from stdin")?;
+        return hosts
+    }
+    #[cfg(feature = "http-hosts")]
+    {
+        if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+            return read_hostfile(fetch_hostfile(url)?.as_bytes(), url)?
+        }
+    }
+    read_hostfile(File::open(path)?, &path.display().to_string())?
+}
+
+/// Determines whether or not the given hostfile path is an HTTP(S) URL to fetch it from.
+#[cfg(feature = "http-hosts")]
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Fetches the contents of the hostfile at the given URL, failing with `Other` unless the server
+/// answers with a success.
+#[cfg(feature = "http-hosts")]
+#[throws(io::Error)]
+fn fetch_hostfile(url: &str) -> String {
+    let response = ureq::get(url).timeout_connect(HOSTFILE_FETCH_TIMEOUT_MS).call();
+    if let Some(e) = response.synthetic_error() {
+        throw!(io::Error::new(io::ErrorKind::Other, format!("failed to fetch {}: {}", url, e)))
+    }
+    if !response.ok() {
+        throw!(io::Error::new(
+            io::ErrorKind::Other, format!("failed to fetch {}: {}", url, response.status_line())
+        ))
+    }
+    response.into_string()?
+}
+
+/// Reads the hosts listed one per line in a hostfile from the given reader, ignoring surrounding
+/// whitespace and blank lines, with the hostfile named as given in errors. Each host may be
+/// followed by its voting weight, as in `hostname=weight`. Fails if the list is empty, lists a
+/// host more than once (which would double count it in every majority), or gives a host an invalid
+/// weight.
+#[throws(io::Error)]
+pub fn read_hostfile<R: Read>(mut reader: R, name: &str) -> Vec<String> {
+    let mut buffer = String::new();
+    reader.read_to_string(&mut buffer)?;
+    let hosts: Vec<String> = buffer.lines()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect();
+
+    let invalid = |reason: String| io::Error::new(
+        io::ErrorKind::InvalidData, format!("invalid hostfile {}: {}", name, reason)
+    );
+    if hosts.is_empty() {
+        throw!(invalid("no hosts listed".to_owned()))
+    }
+    let mut seen = HashSet::new();
+    for host in hosts.iter() {
+        let (hostname, _) = split_weight(host).map_err(|e| invalid(e.to_string()))?;
+        if !seen.insert(hostname) {
+            throw!(invalid(format!("host {} listed more than once", hostname)))
+        }
+    }
+    hosts
+}
+
+/// Splits a host as listed in a hostfile into its hostname and its voting weight, which is one
+/// unless given as in `hostname=weight`. Fails with `InvalidData` unless the weight is positive.
+#[throws(io::Error)]
+fn split_weight(host: &str) -> (&str, u32) {
+    let mut parts = host.splitn(2, '=');
+    // splitting always yields at least one part, even of an empty string
+    let hostname = parts.next().unwrap_or(host).trim();
+    match parts.next() {
+        None => (hostname, 1),
+        Some(weight) => match weight.trim().parse() {
+            Ok(weight) if weight > 0 => (hostname, weight),
+            _ => throw!(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid weight {} for host {}", weight, hostname),
+            )),
+        },
+    }
+}
+
+/// Reloads the hosts from the hostfile at the given path each time a hangup is received, until
+/// the hangups stop or every handle to the nodes has been dropped.
+#[cfg(unix)]
+fn reload_loop(membership: Weak<RwLock<Membership>>, pid: usize, path: PathBuf,
+               hangups: std::sync::mpsc::Receiver<()>) {
+    while let Ok(()) = hangups.recv() {
+        let membership = match membership.upgrade() {
+            Some(membership) => membership,
+            None => break,
+        };
+        info!("received hangup, reloading hostfile {}", path.display());
+        if let Err(e) = reload_hosts(&membership, pid, &path) {
+            error!("keeping the current hosts: {}", e);
+        }
+    }
+}
+
+/// Replaces the known nodes with the hosts listed in the hostfile at the given path. Nodes that
+/// are still listed keep their addresses, new ones are resolved, and removed ones are dropped, but
+/// the members (and the epoch that installed them) stay as they are, so new hosts only become
+/// members by reconfiguring. Since every node's pid is its index, fails, leaving the nodes as they
+/// were, if any node still listed would move to another index or any member would be dropped or
+/// change its weight, as well as if the hostfile is invalid or a new host cannot be resolved.
+#[throws(io::Error)]
+fn reload_hosts(membership: &RwLock<Membership>, pid: usize, path: &Path) -> () {
+    let entries = load_hostfile(path)?;
+    let hosts = entries.iter().map(|host| split_weight(host)).collect::<io::Result<Vec<_>>>()?;
+    let (port, bind, known) = {
+        let membership = membership.read().unwrap();
+        let local = &membership.hosts[pid];
+        let known: HashSet<_> =
+            membership.hosts.iter().map(|node| node.hostname.clone()).collect();
+        for (idx, node) in membership.hosts.iter().enumerate() {
+            match hosts.iter().position(|(hostname, _)| *hostname == node.hostname) {
+                Some(moved) if moved != idx => throw!(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("host {} must stay at index {} of {}, not move to {}",
+                            node.hostname, idx, path.display(), moved),
+                )),
+                None if idx == pid => throw!(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("local host {} must stay at index {} of {}",
+                            node.hostname, pid, path.display()),
+                )),
+                None if idx < membership.len => throw!(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("member {} must stay in {} until reconfigured away",
+                            node.hostname, path.display()),
+                )),
+                // every member has to weigh the same on every node, or their quorums could miss
+                Some(_) if idx < membership.len && hosts[idx].1 != node.weight => throw!(
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("member {} must keep its weight of {} in {}",
+                                node.hostname, node.weight, path.display()),
+                    )
+                ),
+                _ => (),
+            }
+        }
+        (local.port, local.bind, known)
+    };
+
+    // resolve the new hosts up front, so that a failure leaves the nodes untouched
+    let mut added = HashMap::new();
+    for (hostname, _) in hosts.iter().filter(|(hostname, _)| !known.contains(*hostname)) {
+        added.insert(hostname.to_string(), Node::resolve(hostname, port, bind)?);
+    }
+
+    let mut membership = membership.write().unwrap();
+    let mut kept: HashMap<_, _> = membership.hosts.drain(..)
+        .map(|node| (node.hostname.clone(), node))
+        .collect();
+    info!("reloaded hostfile {}, adding {:?} and removing {:?}", path.display(),
+          added.keys().collect::<Vec<_>>(),
+          kept.keys()
+              .filter(|kept| !hosts.iter().any(|(hostname, _)| kept.as_str() == *hostname))
+              .collect::<Vec<_>>());
+    membership.hosts = hosts.iter()
+        .map(|(hostname, weight)| {
+            let node = match kept.remove(*hostname) {
+                Some(node) => node,
+                None => added.remove(*hostname).expect("every new host was resolved"),
+            };
+            // only nodes that aren't members yet can have changed their weight
+            Node { weight: *weight, ..node }
+        })
+        .collect();
+}
+
+/// Creates a stream of the signals asking the process to shut down, i.e. SIGINT and SIGTERM.
+#[throws(io::Error)]
+fn shutdown_signals() -> impl Stream<Item = ()> + Unpin {
+    let signals = signal::ctrl_c()?;
+    #[cfg(unix)]
+    let signals = stream::select(signals, Signal::new(SignalKind::terminate())?);
+    signals
+}
+
+/// Re-resolves the hostname of every known node on the given interval for as long as the nodes
+/// exist.
+fn re_resolve_loop(membership: Weak<RwLock<Membership>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match membership.upgrade() {
+            Some(membership) => membership.read().unwrap().hosts.iter().for_each(Node::re_resolve),
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::net::{Ipv6Addr, UdpSocket as StdUdpSocket};
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use std::process;
+
+    use futures::task::noop_waker_ref;
+    use tokio::codec::Decoder;
+
+    use crate::metrics::TrafficCount;
+    use crate::paxos::ViewInstalled;
+    use crate::TestCase;
+
+    use super::*;
+
+    /// Creates the given number of nodes on localhost, the first of them local, along with the
+    /// receiving end of the queue of messages sent to them.
+    fn local_nodes(len: u16) -> (Nodes, Receiver<(Bytes, SocketAddr)>) {
+        bounded_nodes(len, OUTGOING_CAPACITY)
+    }
+
+    /// Creates the given number of nodes on localhost like `local_nodes`, but with room for only
+    /// the given number of queued messages.
+    fn bounded_nodes(len: u16, capacity: usize) -> (Nodes, Receiver<(Bytes, SocketAddr)>) {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let hosts: Vec<_> = (0..len)
+            .map(|idx| {
+                let port = PORT_NUMBER + 2 * idx;
+                let addr = Mutex::new(SocketAddr::new(localhost, port));
+                let hostname = localhost.to_string();
+                Node { hostname, port, bind: BIND_ADDRESS, addr, weight: 1 }
+            })
+            .collect();
+        let (tx, rx) = mpsc::channel(capacity);
+        let nodes = Nodes {
+            pid: 0, tx,
+            codec: MessageCodec::datagram(),
+            membership: Arc::new(RwLock::new(Membership { epoch: 0, len: hosts.len(), hosts })),
+            max_retransmits: None,
+            unacked: HashMap::new(),
+            loss: None,
+            latency: HashMap::new(),
+            metrics: None,
+            failures: HashMap::new(),
+            failure_threshold: FAILURE_THRESHOLD,
+            rate_limit: None,
+        };
+        (nodes, rx)
+    }
+
+    /// Takes the encoded messages queued for the outgoing socket so far, along with their
+    /// destinations.
+    fn queued_datagrams(rx: &mut Receiver<(Bytes, SocketAddr)>) -> Vec<(Bytes, SocketAddr)> {
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let mut queued = Vec::new();
+        while let Poll::Ready(Some(item)) = rx.poll_next_unpin(&mut ctx) {
+            queued.push(item);
+        }
+        queued
+    }
+
+    /// Takes the messages queued for the outgoing socket so far, along with their destinations.
+    fn queued(rx: &mut Receiver<(Bytes, SocketAddr)>) -> Vec<(Message, SocketAddr)> {
+        queued_datagrams(rx).into_iter()
+            .map(|(datagram, addr)| {
+                let mut src = BytesMut::from(&datagram[..]);
+                (MessageCodec::datagram().decode(&mut src).unwrap().unwrap(), addr)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unicast_enqueues_one_datagram_for_its_target() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        let msg = Message::VCProof { server_id: 0, installed: 1, proposer: 1 };
+        nodes.unicast_send(msg, 2).unwrap();
+        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), PORT_NUMBER + 4);
+        assert_eq!(queued(&mut rx), vec![(msg, target)]);
+
+        let err = nodes.unicast_send(msg, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(queued(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn multicast_after_the_outgoing_socket_stops_is_an_error() {
+        let (mut nodes, rx) = local_nodes(3);
+        drop(rx);
+        let err = nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 })
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn full_outgoing_queue_pushes_back_until_drained() {
+        let (mut nodes, mut rx) = bounded_nodes(4, 2);
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        // three datagrams don't fit in a queue of two, so the last one is refused
+        let err = nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 })
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(nodes.poll_ready(&mut ctx).is_pending());
+
+        assert_eq!(queued(&mut rx).len(), 2);
+        assert!(match nodes.poll_ready(&mut ctx) { Poll::Ready(Ok(())) => true, _ => false });
+        nodes.unicast_send(Message::ViewQuery { server_id: 0 }, 1).unwrap();
+        assert_eq!(queued(&mut rx).len(), 1);
+    }
+
+    /// Sends a multicast and a unicast from nodes losing datagrams at the given rate, returning how
+    /// many of the three datagrams made it into the outgoing queue.
+    fn delivered_at_drop_rate(rate: f64) -> usize {
+        let (mut nodes, mut rx) = local_nodes(3);
+        nodes.loss = Some(Loss { rate, rng: StdRng::seed_from_u64(7) });
+        nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+        nodes.unicast_send(Message::ViewQuery { server_id: 0 }, 1).unwrap();
+        queued(&mut rx).len()
+    }
+
+    #[test]
+    fn certain_loss_drops_every_datagram() {
+        assert_eq!(delivered_at_drop_rate(1.0), 0);
+    }
+
+    #[test]
+    fn no_loss_drops_no_datagrams() {
+        assert_eq!(delivered_at_drop_rate(0.0), 3);
+    }
+
+    #[tokio::test]
+    async fn datagrams_to_a_slow_node_arrive_after_its_latency() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        let latency = Duration::from_millis(200);
+        nodes.latency.insert(2, latency);
+        let start = Instant::now();
+        nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+
+        let mut arrivals = HashMap::new();
+        for _ in 0..2 {
+            let (_, addr) = rx.recv().await.unwrap();
+            arrivals.insert(addr.port(), start.elapsed());
+        }
+        assert!(arrivals[&(PORT_NUMBER + 2)] < latency, "{:?}", arrivals);
+        assert!(arrivals[&(PORT_NUMBER + 4)] >= latency, "{:?}", arrivals);
+    }
+
+    /// Limits the multicasts of the given nodes to two a second, with the given policy.
+    fn rate_limited(nodes: &mut Nodes, policy: RateLimitPolicy) {
+        let tokens = 2.0;
+        nodes.rate_limit = Some(RateLimit { rate: 2.0, tokens, refilled: Instant::now(), policy });
+    }
+
+    #[test]
+    fn multicasts_past_the_rate_limit_are_dropped_by_the_drop_policy() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        rate_limited(&mut nodes, RateLimitPolicy::Drop);
+        for attempted in 1..=3 {
+            nodes.multicast_send(Message::ViewChange { server_id: 0, attempted }).unwrap();
+        }
+        // the bucket holds two multicasts, each sent to both of the other nodes
+        let sent: Vec<_> = queued(&mut rx).into_iter().map(|(msg, _)| msg).collect();
+        assert_eq!(sent.len(), 4);
+        assert!(!sent.contains(&Message::ViewChange { server_id: 0, attempted: 3 }));
+
+        // unicasts aren't limited
+        nodes.unicast_send(Message::ViewQuery { server_id: 0 }, 1).unwrap();
+        assert_eq!(queued(&mut rx).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn multicasts_past_the_rate_limit_are_delayed_by_the_queue_policy() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        rate_limited(&mut nodes, RateLimitPolicy::Queue);
+        let start = Instant::now();
+        for attempted in 1..=3 {
+            nodes.multicast_send(Message::ViewChange { server_id: 0, attempted }).unwrap();
+        }
+        assert_eq!(queued(&mut rx).len(), 4);
+
+        // the third multicast waits for the bucket to refill by half a second's worth
+        for _ in 0..2 {
+            let (datagram, _) = rx.recv().await.unwrap();
+            let mut src = BytesMut::from(&datagram[..]);
+            let msg = MessageCodec::datagram().decode(&mut src).unwrap().unwrap();
+            assert_eq!(msg, Message::ViewChange { server_id: 0, attempted: 3 });
+        }
+        assert!(start.elapsed() >= Duration::from_millis(450), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn long_bursts_queue_at_most_a_burst_of_multicasts() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        rate_limited(&mut nodes, RateLimitPolicy::Queue);
+        for attempted in 1..=100 {
+            nodes.multicast_send(Message::ViewChange { server_id: 0, attempted }).unwrap();
+        }
+        // two go out at once and two more are held back, while the rest are dropped
+        assert_eq!(queued(&mut rx).len(), 4);
+        let tokens = nodes.rate_limit.as_ref().unwrap().tokens;
+        assert!(tokens >= -2.0, "{}", tokens);
+        timer::delay_for(Duration::from_millis(1100)).await;
+        let sent: Vec<_> = queued(&mut rx).into_iter().map(|(msg, _)| msg).collect();
+        assert_eq!(sent.len(), 4);
+        assert!(sent.iter().all(|msg| match msg {
+            Message::ViewChange { attempted, .. } => *attempted <= 4,
+            _ => false,
+        }), "{:?}", sent);
+    }
+
+    #[tokio::test]
+    async fn multicast_rate_must_be_positive() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let port = StdUdpSocket::bind((localhost, 0)).unwrap().local_addr().unwrap().port();
+        let hosts = vec!["127.0.0.1".to_string()];
+        let mut system =
+            System::from_hosts(hosts, "127.0.0.1", port, MessageCodec::datagram()).await.unwrap();
+        for rate in &[0.0, -1.0, std::f64::NAN] {
+            let err = system.limit_multicast_rate(*rate, RateLimitPolicy::Drop).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!("queue".parse(), Ok(RateLimitPolicy::Queue));
+        assert!("slow".parse::<RateLimitPolicy>().is_err());
+    }
+
+    #[test]
+    fn multicast_skips_the_local_node() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+        let addrs: Vec<_> = queued(&mut rx).into_iter().map(|(_, addr)| addr).collect();
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let others = [2, 4].iter().map(|offset| SocketAddr::new(localhost, PORT_NUMBER + offset));
+        assert_eq!(addrs, others.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn multicast_encodes_its_message_once_for_every_node() {
+        for len in &[3, 10] {
+            let (mut nodes, mut rx) = local_nodes(*len);
+            nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+            let datagrams = queued_datagrams(&mut rx);
+            assert_eq!(datagrams.len(), *len as usize - 1);
+            // every datagram is the very same buffer, rather than an encoding of its own
+            let first = datagrams[0].0.as_ptr();
+            assert!(datagrams.iter().all(|(datagram, _)| datagram.as_ptr() == first));
+        }
+    }
+
+    #[tokio::test]
+    async fn system_binds_and_resolves_on_a_custom_port() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        // borrow a free port from the OS, assuming the one after it is free too
+        let port = StdUdpSocket::bind((localhost, 0)).unwrap().local_addr().unwrap().port();
+        let hosts = vec!["127.0.0.1".to_string()];
+        let system =
+            System::from_hosts(hosts, "127.0.0.1", port, MessageCodec::datagram()).await.unwrap();
+
+        assert_eq!(system.nodes.addr(0).unwrap().port(), port);
+        let err = StdUdpSocket::bind((localhost, port)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let outgoing = outgoing_socket(BIND_ADDRESS, port, BIND_WINDOW).await.unwrap();
+        assert_eq!(outgoing.get_ref().local_addr().unwrap().port(), port + 1);
+    }
+
+    #[tokio::test]
+    async fn binding_an_occupied_port_succeeds_once_it_frees_up_within_the_window() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let occupant = StdUdpSocket::bind((localhost, 0)).unwrap();
+        let port = occupant.local_addr().unwrap().port();
+        // the previous process lets go of the port a little while after we first try to bind it
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(occupant);
+        });
+
+        let start = Instant::now();
+        let window = Duration::from_secs(5);
+        let incoming =
+            incoming_socket(localhost, port, MessageCodec::datagram(), window).await.unwrap();
+        assert_eq!(incoming.get_ref().local_addr().unwrap(), SocketAddr::new(localhost, port));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn binding_a_port_still_occupied_once_the_window_passes_fails() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let occupant = StdUdpSocket::bind((localhost, 0)).unwrap();
+        let port = occupant.local_addr().unwrap().port();
+        let codec = MessageCodec::datagram();
+        let err = incoming_socket(localhost, port, codec, Duration::from_millis(100)).await
+            .err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[tokio::test]
+    async fn port_held_by_another_node_socket_is_never_shared() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let port = StdUdpSocket::bind((localhost, 0)).unwrap().local_addr().unwrap().port();
+        let occupant = bind_socket(SocketAddr::new(localhost, port)).unwrap();
+        let err = bind_socket(SocketAddr::new(localhost, port)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let codec = MessageCodec::datagram();
+        let err = incoming_socket(localhost, port, codec, Duration::from_millis(100)).await
+            .err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        drop(occupant);
+    }
+
+    #[tokio::test]
+    async fn system_binds_to_ipv6_loopback_and_resolves_an_ipv6_peer() {
+        let localhost = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let port = StdUdpSocket::bind((localhost, 0)).unwrap().local_addr().unwrap().port();
+        let hosts = vec!["::1".to_string()];
+        let system = System::from_hosts_on(hosts, "::1", localhost, port, MessageCodec::datagram())
+            .await.unwrap();
+
+        let incoming = system.incoming.get_ref().local_addr().unwrap();
+        assert_eq!(incoming, SocketAddr::new(localhost, port));
+        assert_eq!(system.nodes.addr(0), Some(SocketAddr::new(localhost, port)));
+        let outgoing = outgoing_socket(localhost, port, BIND_WINDOW).await.unwrap();
+        assert_eq!(outgoing.get_ref().local_addr().unwrap(), SocketAddr::new(localhost, port + 1));
+    }
+
+    #[test]
+    fn resolution_prefers_the_family_of_the_bind_address() {
+        let v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), PORT_NUMBER);
+        let v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), PORT_NUMBER);
+        let bind_v6 = IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+        assert_eq!(pick_addr(vec![v4, v6].into_iter(), bind_v6), Some(v6));
+        assert_eq!(pick_addr(vec![v6, v4].into_iter(), BIND_ADDRESS), Some(v4));
+        // with nothing in the preferred family, any address will do
+        assert_eq!(pick_addr(vec![v6].into_iter(), BIND_ADDRESS), Some(v6));
+        assert_eq!(pick_addr(vec![].into_iter(), BIND_ADDRESS), None);
+    }
+
+    #[test]
+    fn view_change_is_retransmitted_until_acknowledged() {
+        let (mut nodes, mut rx) = local_nodes(3);
+        nodes.set_max_retransmits(Some(3));
+        let msg = Message::ViewChange { server_id: 0, attempted: 1 };
+        nodes.multicast_send(msg).unwrap();
+        assert_eq!(queued(&mut rx).len(), 2);
+
+        nodes.retransmit().unwrap();
+        let mut resent: Vec<_> = queued(&mut rx).into_iter().map(|(_, addr)| addr).collect();
+        resent.sort();
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let others = [2, 4].iter().map(|offset| SocketAddr::new(localhost, PORT_NUMBER + offset));
+        assert_eq!(resent, others.collect::<Vec<_>>());
+
+        nodes.acknowledge(1, (0, 1));
+        nodes.retransmit().unwrap();
+        assert_eq!(queued(&mut rx), vec![(msg, SocketAddr::new(localhost, PORT_NUMBER + 4))]);
+
+        nodes.acknowledge(2, (0, 1));
+        assert!(nodes.unacked.is_empty());
+        nodes.retransmit().unwrap();
+        assert!(queued(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn node_failing_repeatedly_is_suspected_dead_until_it_succeeds() {
+        let (mut nodes, _rx) = local_nodes(3);
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            nodes.record_failure(2);
+        }
+        assert!(nodes.suspected_dead().is_empty());
+
+        nodes.record_failure(2);
+        nodes.record_failure(2);
+        assert_eq!(nodes.suspected_dead(), [2].iter().cloned().collect());
+
+        // a single success clears every failure before it
+        nodes.record_success(2);
+        assert!(nodes.suspected_dead().is_empty());
+        nodes.record_failure(2);
+        assert!(nodes.suspected_dead().is_empty());
+    }
+
+    #[test]
+    fn node_never_acknowledging_view_changes_is_suspected_dead() {
+        let (mut nodes, _rx) = local_nodes(3);
+        nodes.set_max_retransmits(Some(FAILURE_THRESHOLD));
+        nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+        nodes.acknowledge(1, (0, 1));
+        for _ in 0..FAILURE_THRESHOLD {
+            nodes.retransmit().unwrap();
+        }
+        assert_eq!(nodes.suspected_dead(), [2].iter().cloned().collect());
+    }
+
+    #[test]
+    fn view_change_is_given_up_on_after_its_retransmissions() {
+        let (mut nodes, mut rx) = local_nodes(2);
+        nodes.set_max_retransmits(Some(1));
+        nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+        nodes.retransmit().unwrap();
+        assert_eq!(queued(&mut rx).len(), 2);
+
+        nodes.retransmit().unwrap();
+        assert!(nodes.unacked.is_empty());
+        assert!(queued(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn sent_traffic_is_counted_by_kind_of_message() {
+        let (mut nodes, _rx) = local_nodes(3);
+        let metrics = Arc::new(Metrics::default());
+        nodes.metrics = Some(metrics.clone());
+        nodes.multicast_send(Message::ViewChange { server_id: 0, attempted: 1 }).unwrap();
+        let proof = Message::VCProof { server_id: 0, installed: 1, proposer: 1 };
+        nodes.unicast_send(proof, 2).unwrap();
+        nodes.unicast_send(Message::ViewQuery { server_id: 0 }, 1).unwrap();
+
+        // a message is 12 bytes of tag and fields (one fewer field for a query and one more for a
+        // proof), plus the version
+        assert_eq!(metrics.sent("view_change"), TrafficCount { messages: 2, bytes: 2 * 13 });
+        assert_eq!(metrics.sent("vc_proof"), TrafficCount { messages: 1, bytes: 17 });
+        assert_eq!(metrics.sent("view_query"), TrafficCount { messages: 1, bytes: 9 });
+        assert_eq!(metrics.sent("heartbeat"), TrafficCount::default());
+
+        // received messages are counted by the same measure
+        let codec = MessageCodec::datagram();
+        assert_eq!(codec.encoded_len(Message::Ack { server_id: 1, acking: 1 }), 13);
+        assert_eq!(MessageCodec { checksum: true, ..codec }
+                       .encoded_len(Message::Ack { server_id: 1, acking: 1 }), 17);
+        assert!(metrics.render().lines().any(|line| {
+            line == "paxos_bytes_sent_total{kind=\"view_change\"} 26"
+        }));
+    }
+
+    #[test]
+    fn re_resolving_updates_a_stale_address() {
+        let (mut nodes, mut rx) = local_nodes(2);
+        let current = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), PORT_NUMBER + 2);
+        let stale = SocketAddr::new([10, 0, 0, 9].into(), PORT_NUMBER + 2);
+        // the hostname of the node is its IP, which resolves to itself, unlike its cached address
+        *nodes.membership.read().unwrap().hosts[1].addr.lock().unwrap() = stale;
+        let weak = Arc::downgrade(&nodes.membership);
+        thread::spawn(move || re_resolve_loop(weak, Duration::from_millis(10)));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while nodes.addr(1) != Some(current) {
+            assert!(Instant::now() < deadline, "address was never re-resolved");
+            thread::sleep(Duration::from_millis(10));
+        }
+        nodes.unicast_send(Message::ViewQuery { server_id: 0 }, 1).unwrap();
+        assert_eq!(queued(&mut rx), vec![(Message::ViewQuery { server_id: 0 }, current)]);
+    }
+
+    /// Writes the given hostfile to a path unique to the given test.
+    fn scratch_hostfile(test: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("paxos-hosts-{}-{}", process::id(), test));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Loads the given hostfile, cleaning it up afterwards.
+    fn load(test: &str, contents: &str) -> io::Result<Vec<String>> {
+        let path = scratch_hostfile(test, contents);
+        let res = load_hostfile(&path);
+        fs::remove_file(&path).unwrap();
+        res
+    }
+
+    #[test]
+    fn hostfile_lines_are_trimmed_and_blank_ones_skipped() {
+        let hosts = load("trimmed", "  alpha \n\n\tbeta\n   \n").unwrap();
+        assert_eq!(hosts, vec!["alpha", "beta"]);
+    }
+
+    #[test]
+    fn hostfile_is_read_from_a_buffer_as_from_stdin() {
+        let hosts = read_hostfile("alpha\n beta=2 \n\n".as_bytes(), "from stdin").unwrap();
+        assert_eq!(hosts, vec!["alpha", "beta=2"]);
+        let err = read_hostfile("\n".as_bytes(), "from stdin").unwrap_err();
+        assert_eq!(err.to_string(), "invalid hostfile from stdin: no hosts listed");
+    }
+
+    #[cfg(feature = "http-hosts")]
+    #[test]
+    fn hostfile_is_fetched_from_a_url() {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        // a server answering one request with a hostfile, however it was asked
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let body = "alpha\nbeta\n";
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   body.len(), body).unwrap();
+        });
+
+        let hosts = load_hostfile(format!("http://127.0.0.1:{}/hosts", port)).unwrap();
+        assert_eq!(hosts, vec!["alpha", "beta"]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn empty_hostfile_is_rejected() {
+        let err = load("empty", " \n\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(": no hosts listed"), "{}", err);
+    }
+
+    #[test]
+    fn hostfile_listing_a_host_twice_is_rejected() {
+        let err = load("twice", "alpha\nbeta\n alpha\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(": host alpha listed more than once"), "{}", err);
+    }
+
+    #[test]
+    fn hostfile_weights_are_kept_and_must_be_positive() {
+        let hosts = load("weighted", "alpha=3\nbeta\n").unwrap();
+        assert_eq!(hosts, vec!["alpha=3", "beta"]);
+        let err = load("weightless", "alpha=0\nbeta\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(": invalid weight 0 for host alpha"), "{}", err);
+        let err = load("weighted-twice", "alpha=3\nalpha=1\n").unwrap_err();
+        assert!(err.to_string().ends_with(": host alpha listed more than once"), "{}", err);
+    }
+
+    /// Gets the hostnames of every known node, in order of pid.
+    fn hostnames(nodes: &Nodes) -> Vec<String> {
+        nodes.membership.read().unwrap().hosts.iter().map(|node| node.hostname.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn reloading_the_hostfile_swaps_in_its_hosts() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts = vec!["127.0.0.1".to_string(), "127.0.0.2".to_string(), "127.0.0.3".to_string()];
+        let system =
+            System::from_hosts(hosts, "127.0.0.1", port, MessageCodec::datagram()).await.unwrap();
+        let path = env::temp_dir().join(format!("paxos-reload-{}", process::id()));
+
+        // new hosts are known from then on, but only become members by reconfiguring
+        fs::write(&path, "127.0.0.1\n127.0.0.2\n127.0.0.3\n127.0.0.4\n").unwrap();
+        reload_hosts(&system.nodes.membership, system.pid, &path).unwrap();
+        let all = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3", "127.0.0.4"];
+        assert_eq!(hostnames(&system.nodes), all);
+        assert_eq!(system.nodes.len(), 3);
+        assert_eq!(system.nodes.addr(3), Some(SocketAddr::new([127, 0, 0, 4].into(), port)));
+
+        // an invalid hostfile leaves the hosts as they were
+        fs::write(&path, "\n").unwrap();
+        let err = reload_hosts(&system.nodes.membership, system.pid, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(hostnames(&system.nodes), all);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reloading_never_moves_hosts_or_drops_members() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts = vec!["127.0.0.1".to_string(), "127.0.0.2".to_string(), "127.0.0.3".to_string()];
+        let system =
+            System::from_hosts(hosts, "127.0.0.1", port, MessageCodec::datagram()).await.unwrap();
+        let path = env::temp_dir().join(format!("paxos-reload-{}-shift", process::id()));
+
+        // dropping a node from the middle would shift the pids of the ones after it
+        for hostfile in &["127.0.0.1\n127.0.0.3\n", "127.0.0.1\n127.0.0.3\n127.0.0.2\n"] {
+            fs::write(&path, hostfile).unwrap();
+            let err = reload_hosts(&system.nodes.membership, system.pid, &path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // and so would dropping a member from the end, until it's reconfigured away
+        fs::write(&path, "127.0.0.1\n127.0.0.2\n").unwrap();
+        let err = reload_hosts(&system.nodes.membership, system.pid, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(hostnames(&system.nodes), vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+
+        // while reweighing a member would have this node count quorums unlike its peers
+        fs::write(&path, "127.0.0.1\n127.0.0.2=3\n127.0.0.3\n").unwrap();
+        let err = reload_hosts(&system.nodes.membership, system.pid, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(system.nodes.weight(1), 1);
+
+        // once it is, reloading keeps the members and the epoch that installed them
+        let mut nodes = system.nodes.clone();
+        nodes.reconfigure(1, 2).unwrap();
+        reload_hosts(&system.nodes.membership, system.pid, &path).unwrap();
+        assert_eq!(hostnames(&system.nodes), vec!["127.0.0.1", "127.0.0.2"]);
+        assert_eq!(system.nodes.len(), 2);
+        assert_eq!(system.nodes.membership.read().unwrap().epoch, 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_flags_the_peer_that_never_answers() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"].into_iter()
+            .map(String::from)
+            .collect();
+        // every node binds to its own address, so that they can all share the port
+        let bound = |host: &str| {
+            let hosts = hosts.clone();
+            let bind = host.parse().unwrap();
+            let host = host.to_owned();
+            async move {
+                System::from_hosts_on(hosts, &host, bind, port, MessageCodec::datagram()).await
+                    .unwrap()
+            }
+        };
+        let checker = bound("127.0.0.1").await;
+        let peer = bound("127.0.0.2").await;
+        let config = PaxosConfig::builder(peer.pid(), peer.nodes()).build().unwrap();
+
+        // the peer answers while the protocol runs, and nothing answers for the third host
+        let check = Box::pin(checker.check(Duration::from_millis(500)));
+        let report = match future::select(Box::pin(peer.paxos(config)), check).await {
+            future::Either::Left((_, check)) => check.await,
+            future::Either::Right((report, _)) => report,
+        }.unwrap();
+        assert_eq!(report.unreachable(), vec!["127.0.0.3"]);
+        assert!(!report.all_reachable());
+        assert_ne!(report.exit_code(), 0);
+    }
+
+    #[tokio::test]
+    async fn malformed_datagram_is_skipped_rather_than_stopping_the_node() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2"].into_iter().map(String::from).collect();
+        let checker = System::from_hosts_on(
+            hosts.clone(), "127.0.0.1", "127.0.0.1".parse().unwrap(), port, MessageCodec::datagram()
+        ).await.unwrap();
+        let peer = System::from_hosts_on(
+            hosts, "127.0.0.2", "127.0.0.2".parse().unwrap(), port, MessageCodec::datagram()
+        ).await.unwrap();
+        let config = PaxosConfig::builder(peer.pid(), peer.nodes()).build().unwrap();
+
+        // a datagram with an unknown tag arrives ahead of the view query
+        let mut unknown = vec![crate::msg::PROTOCOL_VERSION];
+        unknown.extend_from_slice(&[0, 0, 0, 99, 0, 0, 0, 1, 0, 0, 0, 2]);
+        let sender = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&unknown, ("127.0.0.2", port)).unwrap();
+
+        let check = Box::pin(checker.check(Duration::from_millis(500)));
+        let report = match future::select(Box::pin(peer.paxos(config)), check).await {
+            future::Either::Left((res, check)) => {
+                res.unwrap();
+                check.await
+            },
+            future::Either::Right((report, _)) => report,
+        }.unwrap();
+        assert!(report.all_reachable(), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn groups_sharing_a_socket_only_see_their_own_view_changes() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"].into_iter()
+            .map(String::from)
+            .collect();
+        let system = System::from_hosts_on(
+            hosts, "127.0.0.1", "127.0.0.1".parse().unwrap(), port, MessageCodec::datagram()
+        ).await.unwrap();
+        let (tx1, mut rx1) = mpsc::channel(4);
+        let (tx2, mut rx2) = mpsc::channel(4);
+        let configs = vec![(1, tx1), (2, tx2)].into_iter()
+            .map(|(group_id, tx)| {
+                let config = PaxosConfig::builder(system.pid(), system.group_nodes(group_id))
+                    .view_installed(Some(tx))
+                    .build()
+                    .unwrap();
+                (group_id, config)
+            })
+            .collect();
+
+        // both peers change to view 1 in group 1 alone, before the groups are even running, since
+        // the incoming socket is already bound to queue the datagrams up
+        let sender = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        for server_id in 1..3 {
+            let datagram = system.group_nodes(1)
+                .encode(Message::ViewChange { server_id, attempted: 1 })
+                .unwrap();
+            sender.send_to(&datagram, ("127.0.0.1", port)).unwrap();
+        }
+
+        let running = Box::pin(system.paxos_groups(configs));
+        let observed = Box::pin(async {
+            let installed = rx1.recv().await;
+            let quiet = timer::delay_for(Duration::from_millis(200));
+            let other = match future::select(Box::pin(rx2.recv()), quiet).await {
+                future::Either::Left((other, _)) => other,
+                future::Either::Right(_) => None,
+            };
+            (installed, other)
+        });
+        let (installed, other) = match future::select(running, observed).await {
+            future::Either::Left((res, _)) => panic!("groups stopped early: {:?}", res),
+            future::Either::Right((observed, _)) => observed,
+        };
+        assert_eq!(installed, Some(ViewInstalled { view: 1, leader: 1 }));
+        assert_eq!(other, None);
+    }
+
+    #[tokio::test]
+    async fn view_change_fans_out_to_every_peer_through_the_injected_sink() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"].into_iter()
+            .map(String::from)
+            .collect();
+        let mut system = System::from_hosts_on(
+            hosts, "127.0.0.1", "127.0.0.1".parse().unwrap(), port, MessageCodec::datagram()
+        ).await.unwrap();
+        let (tx, mut rx) = futures::channel::mpsc::unbounded();
+        system.send_through(tx.sink_map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+        let config = PaxosConfig::builder(system.pid(), system.nodes()).build().unwrap();
+
+        // a peer attempting view 1 sets off our own view change
+        let datagram = system.nodes().encode(Message::ViewChange { server_id: 1, attempted: 1 })
+            .unwrap();
+        StdUdpSocket::bind("127.0.0.1:0").unwrap().send_to(&datagram, ("127.0.0.1", port)).unwrap();
+
+        let fanned_out = Box::pin(async {
+            let mut view_changes = Vec::new();
+            while view_changes.len() < 2 {
+                let (datagram, addr) = rx.next().await.unwrap();
+                let msg = MessageCodec::datagram().decode(&mut BytesMut::from(&datagram[..]))
+                    .unwrap()
+                    .unwrap();
+                if let Message::ViewChange { .. } = msg {
+                    view_changes.push((msg, addr));
+                }
+            }
+            view_changes
+        });
+        let running = Box::pin(system.paxos(config));
+        let mut view_changes = match future::select(running, fanned_out).await {
+            future::Either::Left((res, _)) => panic!("node stopped early: {:?}", res),
+            future::Either::Right((view_changes, _)) => view_changes,
+        };
+        view_changes.sort_by_key(|(_, addr)| addr.to_string());
+        let view_change = Message::ViewChange { server_id: 0, attempted: 1 };
+        assert_eq!(view_changes, vec![
+            (view_change, SocketAddr::from(([127, 0, 0, 2], port))),
+            (view_change, SocketAddr::from(([127, 0, 0, 3], port))),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn messages_still_enqueued_when_the_protocol_finishes_are_all_sent() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"].into_iter()
+            .map(String::from)
+            .collect();
+        let mut system = System::from_hosts_on(
+            hosts, "127.0.0.1", "127.0.0.1".parse().unwrap(), port, MessageCodec::datagram()
+        ).await.unwrap();
+        // a slow socket, so that the enqueued messages are still waiting by the time we finish
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        system.send_through(
+            tx.sink_map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                .with(|datagram| async move {
+                    timer::delay_for(Duration::from_millis(10)).await;
+                    Ok::<_, io::Error>(datagram)
+                })
+        );
+        let config = PaxosConfig::builder(system.pid(), system.nodes())
+            .schedule(TestCase::NormalCase)
+            .build()
+            .unwrap();
+
+        let mut nodes = system.nodes();
+        for nonce in 0..10 {
+            nodes.multicast_send(Message::Ping { server_id: 0, nonce }).unwrap();
+        }
+        // our handle would keep the outgoing channel open, so it must go before the protocol ends
+        drop(nodes);
+
+        // a peer attempting view 1 makes a quorum with us, and the test case finishes in view 1
+        let datagram = system.nodes().encode(Message::ViewChange { server_id: 1, attempted: 1 })
+            .unwrap();
+        StdUdpSocket::bind("127.0.0.1:0").unwrap().send_to(&datagram, ("127.0.0.1", port)).unwrap();
+        system.paxos(config).await.unwrap();
+
+        let pings: Vec<_> = rx.collect::<Vec<_>>().await.into_iter()
+            .filter_map(|(datagram, _)| {
+                match MessageCodec::datagram().decode(&mut BytesMut::from(&datagram[..])) {
+                    Ok(Some(Message::Ping { nonce, .. })) => Some(nonce),
+                    _ => None,
+                }
+            })
+            .collect();
+        // every ping went out to both peers, in the order they were enqueued
+        let expected: Vec<_> = (0..10).flat_map(|nonce| vec![nonce, nonce]).collect();
+        assert_eq!(pings, expected);
+    }
+
+    #[tokio::test]
+    async fn topology_lists_every_resolved_host_and_the_local_pid() {
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"].into_iter()
+            .map(String::from)
+            .collect();
+        let topology = System::topology(
+            hosts, "127.0.0.2", BIND_ADDRESS, PORT_NUMBER, RESOLVE_TIMEOUT
+        ).await.unwrap();
+        assert_eq!(topology.pid, 1);
+        let expected = format!(
+            "pid: 1\n0: 127.0.0.1 (127.0.0.1:{port})\n1: 127.0.0.2 (127.0.0.2:{port}) (local)\n\
+             2: 127.0.0.3 (127.0.0.3:{port})\n",
+            port = PORT_NUMBER,
+        );
+        assert_eq!(topology.to_string(), expected);
+    }
+
+    #[tokio::test]
+    async fn injected_quorum_of_view_changes_installs_the_view() {
+        let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let hosts: Vec<_> = vec!["127.0.0.1", "127.0.0.2", "127.0.0.3"].into_iter()
+            .map(String::from)
+            .collect();
+        let mut system = System::from_hosts_on(
+            hosts, "127.0.0.1", "127.0.0.1".parse().unwrap(), port, MessageCodec::datagram()
+        ).await.unwrap();
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        system.send_through(tx.sink_map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+        let injector = system.injector();
+        let config = PaxosConfig::builder(system.pid(), system.nodes())
+            // this test case finishes the protocol once view 1 is installed
+            .schedule(TestCase::NormalCase)
+            .build()
+            .unwrap();
+
+        for server_id in 1..3 {
+            let from = SocketAddr::new(format!("127.0.0.{}", server_id + 1).parse().unwrap(), port);
+            injector.unbounded_send((Message::ViewChange { server_id, attempted: 1 }, from))
+                .unwrap();
+        }
+        system.paxos(config).await.unwrap();
+
+        let sent: Vec<_> = rx.collect::<Vec<_>>().await.into_iter()
+            .filter_map(|(datagram, _)| {
+                MessageCodec::datagram().decode(&mut BytesMut::from(&datagram[..])).unwrap()
+            })
+            .collect();
+        // we joined the view change, and then proved to both peers that we installed its view
+        let proof = Message::VCProof { server_id: 0, installed: 1, proposer: 1 };
+        assert!(sent.contains(&Message::ViewChange { server_id: 0, attempted: 1 }), "{:?}", sent);
+        assert_eq!(sent.iter().filter(|msg| **msg == proof).count(), 2, "{:?}", sent);
+    }
+
+    /// A socket that fails to flush with the given error a number of times before sending, keeping
+    /// the datagram buffered in between like a real one.
+    struct FlakySocket {
+        failures: u32,
+        kind: io::ErrorKind,
+        buffered: Option<(Bytes, SocketAddr)>,
+        sent: Vec<(Bytes, SocketAddr)>,
+    }
+
+    impl Sink<(Bytes, SocketAddr)> for FlakySocket {
+        type Error = io::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
+            self.poll_flush(ctx)
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, datagram: (Bytes, SocketAddr)) -> io::Result<()> {
+            self.buffered = Some(datagram);
+            Ok(())
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            if self.buffered.is_none() {
+                return Poll::Ready(Ok(()))
+            }
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Poll::Ready(Err(io::Error::new(self.kind, "flaky socket")))
+            }
+            let datagram = self.buffered.take().unwrap();
+            self.sent.push(datagram);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
+            self.poll_flush(ctx)
+        }
+    }
+
+    /// Sends the given datagram through a flaky socket failing with the given error the given
+    /// number of times, allowing the given number of retries, and returns the socket afterwards.
+    async fn send_through_flaky(
+        datagram: (Bytes, SocketAddr), failures: u32, kind: io::ErrorKind, retries: u32
+    ) -> (io::Result<()>, FlakySocket) {
+        let mut socket = FlakySocket { failures, kind, buffered: None, sent: vec![] };
+        let (mut tx, rx) = mpsc::channel(1);
+        tx.send(datagram).await.unwrap();
+        drop(tx);
+        let res = send_outgoing(rx, &mut socket, retries).await;
+        (res, socket)
+    }
+
+    #[tokio::test]
+    async fn transient_send_failures_are_retried_up_to_the_limit() {
+        let datagram = (Bytes::from(&b"hello"[..]), SocketAddr::from((Ipv4Addr::LOCALHOST, 1)));
+
+        let kind = io::ErrorKind::WouldBlock;
+        let (res, socket) = send_through_flaky(datagram.clone(), 2, kind, 3).await;
+        res.unwrap();
+        assert_eq!(socket.sent, vec![datagram.clone()]);
+
+        let (res, socket) = send_through_flaky(datagram.clone(), 2, kind, 1).await;
+        assert_eq!(res.unwrap_err().kind(), kind);
+        assert!(socket.sent.is_empty());
+
+        let kind = io::ErrorKind::PermissionDenied;
+        let (res, socket) = send_through_flaky(datagram, 1, kind, 3).await;
+        assert_eq!(res.unwrap_err().kind(), kind);
+        assert!(socket.sent.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn terminate_signal_asks_to_shut_down() {
+        let mut shutdown = shutdown_signals().unwrap();
+        let pid = std::process::id().to_string();
+        let status = std::process::Command::new("kill").args(&["-TERM", &pid]).status().unwrap();
+        assert!(status.success());
+        assert_eq!(shutdown.next().await, Some(()));
+    }
+
+    #[tokio::test]
+    async fn unresolvable_host_times_out_without_blocking_the_runtime() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(1);
+        // the runtime keeps running other tasks while the host fails to resolve
+        let ticked = async {
+            timer::delay_for(Duration::from_millis(100)).await;
+            start.elapsed()
+        };
+        let resolved = async {
+            let res = Node::resolve_from_hostname("unresolvable.invalid", 1, BIND_ADDRESS, timeout)
+                .await;
+            (res, start.elapsed())
+        };
+        let (ticked, (res, resolved)) = future::join(ticked, resolved).await;
+
+        match res {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            Ok(node) => panic!("resolved an invalid host to {:?}", node.addr()),
+        }
+        assert!(resolved < timeout + Duration::from_millis(500), "gave up after {:?}", resolved);
+        assert!(ticked < Duration::from_millis(500), "runtime blocked for {:?}", ticked);
+    }
+
+    #[tokio::test]
+    async fn system_without_its_own_host_listed_fails_to_start() {
+        let hosts = vec!["127.0.0.1".to_string()];
+        let res = System::from_hosts(hosts, "elsewhere", 0, MessageCodec::datagram()).await;
+
+        match res {
+            Err(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::NotFound);
+                let reason = err.to_string();
+                assert!(reason.starts_with("local host elsewhere is not one of the hosts"));
             }
+            Ok(_) => panic!("started a system with no pid of its own"),
         }
     }
 }
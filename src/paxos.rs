@@ -1,117 +1,1031 @@
 use std::convert::TryFrom;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::future::Future;
-use std::io;
+use std::io::{self, Write};
+use std::path::PathBuf;
 use std::pin::Pin;
-use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use fehler::throws;
-use futures::{Poll, Sink, Stream};
-use futures::task::Context;
-use log::{trace, info, warn};
-use tokio::timer::{self, Delay, Interval};
+use fehler::{throw, throws};
+use futures::{future, Poll, Sink, Stream};
+use futures::task::{Context, Waker};
+use log::{trace, info, warn, error};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{event, trace_span, Level};
 
-use crate::TestCase;
+use crate::admin::{AdminCommand, AdminRequest};
+use crate::clock::{Clock, ClockDelay, Ticker, TokioClock};
+use crate::events::{Event, EventLog};
+use crate::metrics::Metrics;
 use crate::msg::Message;
-use crate::net::Nodes;
+use crate::net::{Nodes, Transport};
+use crate::roles::{total_weight, Acceptor, Judgement, Proposer};
+use crate::state::DurableViews;
 
-/// An internal entry for tracking received view changes.
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
-struct VC(u32, u32);
+/// A schedule of the points at which nodes crash or finish, used to exercise the protocol.
+pub trait FailureSchedule {
+    /// Determines whether or not the node with the given pid should crash upon finding a majority
+    /// attempting to install the given view, before installing it.
+    fn should_crash(&self, pid: u32, view: u32) -> bool;
+
+    /// Determines whether or not the node with the given pid should finish the protocol after
+    /// installing the given view, which is led by the given leader.
+    fn should_exit(&self, pid: u32, view: u32, leader: u32) -> bool;
+}
+
+/// A mapping from a view and the number of nodes to the id of the view's leader, which must be one
+/// of the nodes.
+pub type LeaderFn = Arc<dyn Fn(u32, usize) -> u32 + Send + Sync>;
+
+/// The format in which each newly installed leader is output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// a sentence per leader, e.g. `0: Server 1 is the new leader of view 1`
+    Human,
+    /// a JSON object per line, e.g. `{"pid":0,"leader":1,"view":1}`
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// A strategy for picking the view to propose when the progress timer expires.
+pub trait ViewStrategy {
+    /// Picks the view to propose after the last attempted view among the given number of nodes,
+    /// given the leader of each view and the nodes suspected dead, or `None` if the view counter
+    /// is exhausted.
+    fn next_view(&self, last_attempted: u32, num_nodes: usize, leader_of: &dyn Fn(u32) -> u32,
+                 suspected_dead: &BTreeSet<u32>) -> Option<u32>;
+}
+
+/// A strategy that always proposes the view right after the last attempted one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Successor;
+
+impl ViewStrategy for Successor {
+    fn next_view(&self, last_attempted: u32, _num_nodes: usize, _leader_of: &dyn Fn(u32) -> u32,
+                 _suspected_dead: &BTreeSet<u32>) -> Option<u32> {
+        last_attempted.checked_add(1)
+    }
+}
+
+/// A strategy that proposes the first view after the last attempted one whose leader isn't
+/// suspected dead, rather than wasting a round on a leader that can't take over. If every node
+/// is suspected, it falls back on the view right after the last attempted one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SkipSuspected;
+
+impl ViewStrategy for SkipSuspected {
+    fn next_view(&self, last_attempted: u32, num_nodes: usize, leader_of: &dyn Fn(u32) -> u32,
+                 suspected_dead: &BTreeSet<u32>) -> Option<u32> {
+        let next = last_attempted.checked_add(1)?;
+        // every leader has had its turn once we've looked as many views ahead as there are nodes
+        (0..num_nodes as u32)
+            .filter_map(|ahead| next.checked_add(ahead))
+            .find(|view| !suspected_dead.contains(&leader_of(*view)))
+            .or(Some(next))
+    }
+}
 
 /// A configuration for constructing a new instance of Paxos.
-pub struct PaxosConfig {
+pub struct PaxosConfig<T = Nodes> {
     /// the process id of the current node
     pub pid: usize,
     /// all the nodes in the system
-    pub nodes: Nodes,
-    /// the current test case being executed
-    pub test_case: TestCase,
-    /// the duration of the progress timer in seconds
+    pub nodes: T,
+    /// the schedule of crashes and exits to follow, e.g. the current test case being executed
+    pub schedule: Box<dyn FailureSchedule + Send>,
+    /// the base duration of the progress timer in seconds
     pub progress_timer_length: u64,
+    /// the factor the progress timer grows by each time it expires without a view installing
+    pub progress_backoff_multiplier: u32,
+    /// the maximum duration of the progress timer in seconds, no matter how much it backs off
+    pub progress_timer_cap: u64,
     /// the duration of the vc proof timer in seconds
     pub vc_proof_timer_length: u64,
+    /// the maximum number of retransmissions of unacknowledged view changes, if sent reliably
+    pub max_retransmits: Option<u32>,
+    /// the file to persist installed views into and to recover them from on startup, if any
+    pub state_path: Option<PathBuf>,
+    /// the upper bound (exclusive) on random jitter added to the progress timer in milliseconds
+    pub progress_jitter_ms: u64,
+    /// the span in milliseconds that the nodes' first progress timeouts are spread across, each
+    /// offset by its pid and the seed so that they don't all time out together after starting
+    pub initial_spread_ms: u64,
+    /// the seed for the jitter, combined with the pid so that nodes sharing a seed still differ
+    pub seed: Option<u64>,
+    /// the counters to record protocol events in
+    pub metrics: Arc<Metrics>,
+    /// the log to record protocol transitions in
+    pub events: EventLog,
+    /// the clock driving the timers
+    pub clock: Box<dyn Clock + Send>,
+    /// the total weight of the view changes needed to install a view, in place of a majority of
+    /// the total weight of the nodes, if any
+    pub quorum_size: Option<usize>,
+    /// whether or not to multicast a vc proof as soon as a view installs, rather than only on the
+    /// vc proof timer
+    pub eager_proof: bool,
+    /// whether or not only the leader of the installed view multicasts vc proofs on the vc proof
+    /// timer, rather than every node
+    pub leader_only_proofs: bool,
+    /// the interval in seconds between pings of every other node measuring round trip times, if
+    /// any
+    pub ping_interval: Option<u64>,
+    /// the largest view the progress timer may start a view change to, if any, past which the
+    /// node stops changing views of its own accord
+    pub max_view: Option<u32>,
+    /// the number of view changes in a row that may fail (i.e. have the progress timer expire on
+    /// them) without any view installing before a livelock is reported, if any
+    pub livelock_threshold: Option<u32>,
+    /// the number of failed nodes `f` to tolerate, if explicit, which requires a total weight of at
+    /// least `2f + 1` and makes the quorum `f + 1` in place of a majority
+    pub tolerated_failures: Option<u32>,
+    /// the channel to notify of every view installed, if any
+    pub view_installed: Option<Sender<ViewInstalled>>,
+    /// the view to start out having installed in place of view 0, if any, unless the views
+    /// recovered from the state file are further along
+    pub initial_view: Option<u32>,
+    /// whether or not to multicast a view query on the first poll, so that peers answer with the
+    /// views they installed rather than leaving a fresh node to wait for their next proofs
+    pub catch_up: bool,
+    /// the mapping from views to their leaders, if not round-robin by the view modulo the number
+    /// of nodes
+    pub leader_fn: Option<LeaderFn>,
+    /// whether or not to stop the progress timer once a full rotation of leaders failed to take
+    /// over, which means that more nodes failed than a quorum can do without
+    pub stop_when_blocked: bool,
+    /// the strategy for picking the view to propose when the progress timer expires
+    pub view_strategy: Box<dyn ViewStrategy + Send>,
+    /// the shortest that any timer may be, which longer ones are clamped to
+    pub min_timer_length: Duration,
+    /// how long after starting a view change that starting it again (or one to an earlier view)
+    /// is coalesced into the one underway, rather than starting over
+    pub view_change_cooldown: Duration,
+    /// the format in which each newly installed leader is output
+    pub output_format: OutputFormat,
+    /// where each newly installed leader is output
+    pub output: Box<dyn Write + Send>,
+}
+
+/// A notification that the node installed a view, for embedding applications to react to (e.g. by
+/// starting or stopping accepting writes as leadership moves).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewInstalled {
+    /// the view installed
+    pub view: u32,
+    /// the leader of the view
+    pub leader: u32,
+}
+
+/// A failure schedule in which nodes never crash or finish, running the protocol indefinitely.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NeverFail;
+
+impl FailureSchedule for NeverFail {
+    fn should_crash(&self, _pid: u32, _view: u32) -> bool {
+        false
+    }
+
+    fn should_exit(&self, _pid: u32, _view: u32, _leader: u32) -> bool {
+        false
+    }
+}
+
+/// A reason that a `PaxosConfig` was rejected when building it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// one of the timers was configured to be zero seconds long
+    ZeroTimer,
+    /// the vc proof timer was not shorter than the progress timer, so proofs could not help nodes
+    /// that missed a view installing catch up before they started yet another view change
+    ProofTimerTooLong,
+    /// the progress timer was configured to back off by a factor of zero
+    ZeroBackoff,
+    /// the progress timer was capped below its base length
+    CapBelowProgressTimer,
+    /// the quorum size was zero or larger than the total weight of the nodes, so no view could ever
+    /// install
+    QuorumOutOfRange,
+    /// the total weight of the nodes was less than `2f + 1` for the `f` failures to tolerate
+    TooFewNodes,
+    /// both a quorum size and a number of failures to tolerate were configured, which each decide
+    /// the quorum
+    ConflictingQuorums,
+    /// the initial view was past the maximum view, which the node could never leave
+    InitialViewPastMax,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            ConfigError::ZeroTimer => "timers must be at least one second long",
+            ConfigError::ProofTimerTooLong =>
+                "the vc proof timer must be shorter than the progress timer",
+            ConfigError::ZeroBackoff => "the progress backoff multiplier must be at least one",
+            ConfigError::CapBelowProgressTimer =>
+                "the progress timer cap must be at least the progress timer length",
+            ConfigError::QuorumOutOfRange =>
+                "the quorum size must be between one and the total weight of the nodes",
+            ConfigError::TooFewNodes =>
+                "tolerating f failures takes a total weight of at least 2f + 1 across the nodes",
+            ConfigError::ConflictingQuorums =>
+                "only one of the quorum size and the failures to tolerate may be set",
+            ConfigError::InitialViewPastMax => "the initial view must not be past the maximum view",
+        };
+        write!(f, "invalid paxos configuration: {}", reason)
+    }
+}
+
+impl Error for ConfigError {}
+
+impl<T> PaxosConfig<T> {
+    /// Starts building a configuration for the node with the given pid among the given nodes, with
+    /// the defaults given on each of the builder's setters.
+    pub fn builder(pid: usize, nodes: T) -> PaxosConfigBuilder<T> {
+        PaxosConfigBuilder {
+            config: PaxosConfig {
+                pid, nodes,
+                schedule: Box::new(NeverFail),
+                progress_timer_length: 3,
+                progress_backoff_multiplier: 1,
+                progress_timer_cap: 60,
+                vc_proof_timer_length: 1,
+                max_retransmits: None,
+                state_path: None,
+                progress_jitter_ms: 0,
+                initial_spread_ms: 0,
+                seed: None,
+                metrics: Arc::new(Metrics::default()),
+                events: EventLog::disabled(),
+                clock: Box::new(TokioClock),
+                quorum_size: None,
+                eager_proof: true,
+                leader_only_proofs: false,
+                ping_interval: None,
+                max_view: None,
+                livelock_threshold: None,
+                tolerated_failures: None,
+                view_installed: None,
+                initial_view: None,
+                catch_up: true,
+                leader_fn: None,
+                stop_when_blocked: false,
+                view_strategy: Box::new(Successor),
+                min_timer_length: MIN_TIMER_LENGTH,
+                view_change_cooldown: Duration::from_secs(0),
+                output_format: OutputFormat::Human,
+                output: Box::new(io::stdout()),
+            },
+        }
+    }
+}
+
+/// A builder for a `PaxosConfig` that validates it before handing it out.
+pub struct PaxosConfigBuilder<T = Nodes> {
+    /// the configuration built so far
+    config: PaxosConfig<T>,
+}
+
+impl<T> PaxosConfigBuilder<T> {
+    /// Sets the schedule of crashes and exits to follow. Defaults to a schedule in which nodes
+    /// never fail.
+    pub fn schedule<S: FailureSchedule + Send + 'static>(mut self, schedule: S) -> Self {
+        self.config.schedule = Box::new(schedule);
+        self
+    }
+
+    /// Sets the base duration of the progress timer in seconds. Defaults to 3 seconds.
+    pub fn progress_timer_length(mut self, secs: u64) -> Self {
+        self.config.progress_timer_length = secs;
+        self
+    }
+
+    /// Sets the factor the progress timer grows by each time it expires without a view installing.
+    /// Defaults to 1, i.e. no backoff.
+    pub fn progress_backoff_multiplier(mut self, multiplier: u32) -> Self {
+        self.config.progress_backoff_multiplier = multiplier;
+        self
+    }
+
+    /// Sets the maximum duration of the progress timer in seconds. Defaults to 60 seconds.
+    pub fn progress_timer_cap(mut self, secs: u64) -> Self {
+        self.config.progress_timer_cap = secs;
+        self
+    }
+
+    /// Sets the duration of the vc proof timer in seconds. Defaults to 1 second.
+    pub fn vc_proof_timer_length(mut self, secs: u64) -> Self {
+        self.config.vc_proof_timer_length = secs;
+        self
+    }
+
+    /// Sets the maximum number of retransmissions of unacknowledged view changes, sending them
+    /// reliably, or sends them unreliably if `None`. Defaults to `None`.
+    pub fn max_retransmits(mut self, max_retransmits: Option<u32>) -> Self {
+        self.config.max_retransmits = max_retransmits;
+        self
+    }
+
+    /// Sets the file to persist installed views into and to recover them from, if any. Defaults to
+    /// `None`.
+    pub fn state_path(mut self, state_path: Option<PathBuf>) -> Self {
+        self.config.state_path = state_path;
+        self
+    }
+
+    /// Sets the upper bound (exclusive) on random jitter added to the progress timer in
+    /// milliseconds. Defaults to 0, i.e. no jitter.
+    pub fn progress_jitter_ms(mut self, jitter_ms: u64) -> Self {
+        self.config.progress_jitter_ms = jitter_ms;
+        self
+    }
+
+    /// Sets the span in milliseconds that the nodes' first progress timeouts are spread across,
+    /// each node offset deterministically by its pid and the seed. Defaults to 0, i.e. no spread.
+    pub fn initial_spread_ms(mut self, spread_ms: u64) -> Self {
+        self.config.initial_spread_ms = spread_ms;
+        self
+    }
+
+    /// Sets the seed for the jitter, or seeds it randomly if `None`. Defaults to `None`.
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    /// Sets the counters to record protocol events in. Defaults to fresh counters.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.config.metrics = metrics;
+        self
+    }
+
+    /// Sets the log to record protocol transitions in. Defaults to a disabled log.
+    pub fn events(mut self, events: EventLog) -> Self {
+        self.config.events = events;
+        self
+    }
+
+    /// Sets the clock driving the timers. Defaults to the wall clock.
+    pub fn clock<C: Clock + Send + 'static>(mut self, clock: C) -> Self {
+        self.config.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets the total weight of the view changes needed to install a view, or requires a majority
+    /// of the total weight if `None`. Defaults to `None`.
+    pub fn quorum_size(mut self, quorum_size: Option<usize>) -> Self {
+        self.config.quorum_size = quorum_size;
+        self
+    }
+
+    /// Sets whether or not to multicast a vc proof as soon as a view installs, on top of the ones
+    /// sent on the vc proof timer. Defaults to `true`.
+    pub fn eager_proof(mut self, eager_proof: bool) -> Self {
+        self.config.eager_proof = eager_proof;
+        self
+    }
+
+    /// Sets whether or not only the leader of the installed view multicasts vc proofs on the vc
+    /// proof timer. Defaults to `false`.
+    pub fn leader_only_proofs(mut self, leader_only_proofs: bool) -> Self {
+        self.config.leader_only_proofs = leader_only_proofs;
+        self
+    }
+
+    /// Sets the interval in seconds between pings of every other node, or never pings if `None`.
+    /// Defaults to `None`.
+    pub fn ping_interval(mut self, secs: Option<u64>) -> Self {
+        self.config.ping_interval = secs;
+        self
+    }
+
+    /// Sets the largest view the progress timer may start a view change to, or lets views climb
+    /// without bound if `None`. Defaults to `None`.
+    pub fn max_view(mut self, max_view: Option<u32>) -> Self {
+        self.config.max_view = max_view;
+        self
+    }
+
+    /// Sets the number of view changes in a row that may fail without any view installing before a
+    /// livelock is reported, or never reports one if `None`. Defaults to `None`.
+    pub fn livelock_threshold(mut self, threshold: Option<u32>) -> Self {
+        self.config.livelock_threshold = threshold;
+        self
+    }
+
+    /// Sets the number of failed nodes to tolerate, deriving the quorum from it, or requires a
+    /// majority if `None`. Defaults to `None`.
+    pub fn tolerated_failures(mut self, failures: Option<u32>) -> Self {
+        self.config.tolerated_failures = failures;
+        self
+    }
+
+    /// Sets the channel to notify of every view installed, or notifies none if `None`. Defaults to
+    /// `None`.
+    pub fn view_installed(mut self, tx: Option<Sender<ViewInstalled>>) -> Self {
+        self.config.view_installed = tx;
+        self
+    }
+
+    /// Sets the view to start out having installed, or starts in view 0 if `None`. Defaults to
+    /// `None`.
+    pub fn initial_view(mut self, view: Option<u32>) -> Self {
+        self.config.initial_view = view;
+        self
+    }
+
+    /// Sets whether or not to query every peer for its installed view on the first poll. Defaults
+    /// to `true`.
+    pub fn catch_up(mut self, catch_up: bool) -> Self {
+        self.config.catch_up = catch_up;
+        self
+    }
+
+    /// Sets the mapping from views to their leaders, or rotates round-robin if `None`. Defaults to
+    /// `None`.
+    pub fn leader_fn(mut self, leader_fn: Option<LeaderFn>) -> Self {
+        self.config.leader_fn = leader_fn;
+        self
+    }
+
+    /// Sets whether or not to stop timing out once a full rotation of leaders failed to take over.
+    /// Defaults to `false`.
+    pub fn stop_when_blocked(mut self, stop_when_blocked: bool) -> Self {
+        self.config.stop_when_blocked = stop_when_blocked;
+        self
+    }
+
+    /// Sets the strategy for picking the view to propose when the progress timer expires. Defaults
+    /// to proposing the very next view.
+    pub fn view_strategy<S: ViewStrategy + Send + 'static>(mut self, strategy: S) -> Self {
+        self.config.view_strategy = Box::new(strategy);
+        self
+    }
+
+    /// Sets the shortest that any timer may be, which shorter ones are clamped to. Defaults to
+    /// `MIN_TIMER_LENGTH`.
+    pub fn min_timer_length(mut self, min_timer_length: Duration) -> Self {
+        self.config.min_timer_length = min_timer_length;
+        self
+    }
+
+    /// Sets how long after starting a view change that starting it again (or one to an earlier
+    /// view) is coalesced into the one underway. Defaults to no cooldown.
+    pub fn view_change_cooldown(mut self, cooldown: Duration) -> Self {
+        self.config.view_change_cooldown = cooldown;
+        self
+    }
+
+    /// Sets the format in which each newly installed leader is output. Defaults to the human
+    /// format.
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.config.output_format = format;
+        self
+    }
+
+    /// Sets where each newly installed leader is output. Defaults to stdout.
+    pub fn output<W: Write + Send + 'static>(mut self, output: W) -> Self {
+        self.config.output = Box::new(output);
+        self
+    }
+}
+
+impl<T: Transport> PaxosConfigBuilder<T> {
+    /// Finishes building the configuration, failing if it is invalid.
+    #[throws(ConfigError)]
+    pub fn build(self) -> PaxosConfig<T> {
+        let config = self.config;
+        if config.progress_timer_length == 0 || config.vc_proof_timer_length == 0 {
+            throw!(ConfigError::ZeroTimer)
+        }
+        if config.vc_proof_timer_length >= config.progress_timer_length {
+            throw!(ConfigError::ProofTimerTooLong)
+        }
+        if config.progress_backoff_multiplier == 0 {
+            throw!(ConfigError::ZeroBackoff)
+        }
+        if config.progress_timer_cap < config.progress_timer_length {
+            throw!(ConfigError::CapBelowProgressTimer)
+        }
+        if let Some(quorum_size) = config.quorum_size {
+            if quorum_size == 0 || quorum_size > total_weight(&config.nodes) {
+                throw!(ConfigError::QuorumOutOfRange)
+            }
+        }
+        if let Some(failures) = config.tolerated_failures {
+            if config.quorum_size.is_some() {
+                throw!(ConfigError::ConflictingQuorums)
+            }
+            if total_weight(&config.nodes) < 2 * failures as usize + 1 {
+                throw!(ConfigError::TooFewNodes)
+            }
+        }
+        if let (Some(initial_view), Some(max_view)) = (config.initial_view, config.max_view) {
+            if initial_view > max_view {
+                throw!(ConfigError::InitialViewPastMax)
+            }
+        }
+        config
+    }
+}
+
+/// The default shortest that any timer may be, keeping a timer configured to zero from firing on
+/// every poll.
+pub const MIN_TIMER_LENGTH: Duration = Duration::from_millis(1);
+
+/// The longest that any timer may be, keeping deadlines far enough off that the clock can still
+/// represent them.
+const MAX_TIMER_LENGTH: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The interval between retransmissions of unacknowledged view changes in reliable mode.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long after proving a view that another proof of the same view is suppressed, which is
+/// shorter than any vc proof timer so that only redundant proofs are.
+const PROOF_SUPPRESSION_WINDOW: Duration = Duration::from_millis(250);
+
+/// The timers that the protocol services as they fire, taking turns in the order of `Timer::ALL`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Timer {
+    Progress,
+    VcProof,
+    Retransmit,
+    Heartbeat,
+    Ping,
+}
+
+impl Timer {
+    /// Every timer, in the order they take turns.
+    const ALL: [Timer; 5] =
+        [Timer::Progress, Timer::VcProof, Timer::Retransmit, Timer::Heartbeat, Timer::Ping];
 }
 
-/// An asynchronous implementation of Paxos.
-pub struct Paxos {
+/// An asynchronous implementation of Paxos, sending messages over the transport `T`.
+pub struct Paxos<T = Nodes> {
     /// the process id of the current node
     pid: u32,
     /// all the nodes in the system
-    nodes: Nodes,
-    /// the current test case being executed
-    test_case: TestCase,
-    /// the length of the progress timer
+    nodes: T,
+    /// the schedule of crashes and exits to follow
+    schedule: Box<dyn FailureSchedule + Send>,
+    /// the base length of the progress timer
     progress_length: Duration,
+    /// the factor the progress timer grows by each time it expires without a view installing
+    backoff_multiplier: u32,
+    /// the current multiple of the base length that the progress timer has backed off to
+    backoff_factor: u32,
+    /// the maximum length of the progress timer
+    progress_cap: Duration,
+    /// the upper bound (exclusive) on random jitter added to the progress timer in milliseconds
+    progress_jitter_ms: u64,
+    /// the source of randomness for jitter
+    rng: StdRng,
+    /// the clock driving the timers
+    clock: Box<dyn Clock + Send>,
     /// a delay until the progress timer is finished
-    progress_timer: Delay,
-    /// an interval for sending vcproof messages every so often
-    vc_proof_timer: Interval,
-    /// the last view we attempted to install
-    last_attempted_view: u32,
-    /// the current view that we have installed
-    current_view: u32,
-    /// a set of all the current view change messages received.
-    view_change_state: HashSet<VC>,
+    progress_timer: ClockDelay,
+    /// a ticker for sending vcproof messages every so often
+    vc_proof_timer: Ticker,
+    /// a ticker for retransmitting unacknowledged view changes in reliable mode
+    retransmit_timer: Ticker,
+    /// a ticker for sending heartbeats to our followers while we lead the installed view
+    heartbeat_timer: Ticker,
+    /// a ticker for pinging every other node, if measuring round trip times
+    ping_timer: Option<Ticker>,
+    /// the timers due to be serviced, in the order of `Timer::ALL`
+    due: [bool; 5],
+    /// the index in `Timer::ALL` of the timer that gets the first turn at the next poll
+    next_turn: usize,
+    /// the pings awaiting a pong, keyed by nonce, along with the node pinged and when
+    pings: HashMap<u32, (usize, Instant)>,
+    /// the nonce of the next ping
+    next_nonce: u32,
+    /// the last view we sent a proof of, and when, if any
+    last_proof: Option<(u32, Instant)>,
+    /// the last view we started a view change to, and when, if any
+    last_view_change: Option<(u32, Instant)>,
+    /// how long after starting a view change that starting one to no later a view is coalesced
+    view_change_cooldown: Duration,
+    /// the proposing side of this node, with the view it last attempted and the votes for it
+    proposer: Proposer,
+    /// the accepting side of this node, with the view it installed
+    acceptor: Acceptor,
+    /// the view change we last proposed, while waiting on a quorum to confirm installing it
+    proposal: Option<Proposal>,
+    /// the time from proposing a view change to a quorum confirming it installed, for the last
+    /// one confirmed
+    install_latency: Option<Duration>,
+    /// whether or not to multicast a vc proof as soon as a view installs
+    eager_proof: bool,
+    /// whether or not only the leader multicasts vc proofs on the vc proof timer
+    leader_only_proofs: bool,
+    /// the largest view the progress timer may start a view change to, if any
+    max_view: Option<u32>,
+    /// the number of view changes in a row that may fail before a livelock is reported, if any
+    livelock_threshold: Option<u32>,
+    /// the number of view changes in a row that failed since a view last installed
+    failed_view_changes: u32,
+    /// whether or not a full rotation of leaders failed to take over since a view last installed
+    blocked: bool,
+    /// whether or not to stop the progress timer once blocked
+    stop_when_blocked: bool,
+    /// the strategy for picking the view to propose when the progress timer expires
+    view_strategy: Box<dyn ViewStrategy + Send>,
+    /// the nodes suspected dead, having led a view we installed without keeping us from timing
+    /// out, until we hear from them again
+    suspected_dead: BTreeSet<u32>,
+    /// the epoch of the membership that we have installed
+    epoch: u32,
+    /// the servers we've received votes from to reconfigure the system in the next epoch, by the
+    /// number of members they voted for
+    reconfig_state: HashMap<u32, HashSet<u32>>,
+    /// the file to persist installed views into, if any
+    state_path: Option<PathBuf>,
+    /// the admin commands to carry out as they come in, if any
+    admin: Option<Receiver<AdminRequest>>,
+    /// the channel to notify of every view installed, if any
+    view_installed: Option<Sender<ViewInstalled>>,
+    /// the counters to record protocol events in
+    metrics: Arc<Metrics>,
+    /// the log to record protocol transitions in
+    events: EventLog,
+    /// the format in which each newly installed leader is output
+    output_format: OutputFormat,
+    /// where each newly installed leader is output
+    output: Box<dyn Write + Send>,
+    /// whether or not the view query to catch up with the peers is yet to be sent
+    catch_up: bool,
+    /// whether or not we queried the peers for their views and are yet to hear a proof back
+    querying: bool,
+    /// the mapping from views to their leaders, if not round-robin
+    leader_fn: Option<LeaderFn>,
+    /// whether or not the protocol has finished, ending the stream
+    done: bool,
+    /// the waker for the last poll of the stream, so that it notices when the protocol finishes
+    waker: Option<Waker>,
+}
+
+/// A view change proposed by this node, along with the nodes that confirmed installing its view.
+struct Proposal {
+    /// the view proposed
+    view: u32,
+    /// when the view change started
+    started: Instant,
+    /// the servers that confirmed installing the view, ourselves included once we have
+    confirmed: HashSet<u32>,
 }
 
-impl Paxos {
+impl<T: Transport> Paxos<T> {
     /// Creates a new instance of Paxos.
     #[throws]
-    pub fn new(config: PaxosConfig) -> Paxos {
+    pub fn new(config: PaxosConfig<T>) -> Paxos<T> {
         let PaxosConfig {
-            pid, nodes, test_case, progress_timer_length, vc_proof_timer_length
+            pid, mut nodes, schedule, progress_timer_length, progress_backoff_multiplier,
+            progress_timer_cap, vc_proof_timer_length,
+            max_retransmits, state_path, progress_jitter_ms, initial_spread_ms, seed, metrics,
+            events, clock,
+            quorum_size, eager_proof, leader_only_proofs, ping_interval, max_view,
+            livelock_threshold, tolerated_failures, view_installed, initial_view, catch_up,
+            leader_fn, stop_when_blocked, view_strategy, min_timer_length, view_change_cooldown,
+            output_format, output,
         } = config;
-        let progress_length = Duration::from_secs(progress_timer_length);
-        let proof_length = Duration::from_secs(vc_proof_timer_length);
+        // the builder rejects this too, but a config put together by hand would spin otherwise
+        if progress_timer_length == 0 {
+            throw!(ConfigError::ZeroTimer)
+        }
+        nodes.set_max_retransmits(max_retransmits);
+        let views = match state_path {
+            Some(ref path) => DurableViews::load(path)?,
+            None => DurableViews::default(),
+        };
+        let progress_length =
+            clamp_timer("progress", Duration::from_secs(progress_timer_length), min_timer_length);
+        let progress_cap =
+            clamp_timer("progress cap", Duration::from_secs(progress_timer_cap), min_timer_length)
+                .max(progress_length);
+        let proof_length =
+            clamp_timer("vc proof", Duration::from_secs(vc_proof_timer_length), min_timer_length);
+        let ping_length = ping_interval
+            .map(|secs| clamp_timer("ping", Duration::from_secs(secs), min_timer_length));
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(pid as u64)),
+            None => StdRng::from_entropy(),
+        };
+        let initial_length = progress_length
+            + initial_offset(pid, nodes.len(), seed.unwrap_or(0), initial_spread_ms)
+            + jitter(&mut rng, progress_jitter_ms);
+        // we start out in the initial view, unless we recovered views further along than it
+        let current_view = views.current_view.max(initial_view.unwrap_or(0));
+        if nodes.len() == 1 {
+            info!("running as the only node, which makes a quorum by itself");
+        }
+        let pid = u32::try_from(pid)?;
+        // tolerating f failures takes any f + 1 nodes, since at most f of them can have failed
+        let quorum_size = quorum_size.or(tolerated_failures.map(|f| f as usize + 1));
         Paxos {
-            pid: u32::try_from(pid)?,
-            nodes, test_case, progress_length,
-            progress_timer: timer::delay_for(progress_length),
-            vc_proof_timer: Interval::new_interval(proof_length),
-            last_attempted_view: 0,
-            current_view: 0,
-            view_change_state: HashSet::new(),
+            pid,
+            nodes, schedule, progress_length, progress_cap, progress_jitter_ms, rng,
+            backoff_multiplier: progress_backoff_multiplier,
+            backoff_factor: 1,
+            progress_timer: clock.delay_until(clock.now() + initial_length),
+            vc_proof_timer: Ticker::new(&*clock, proof_length),
+            retransmit_timer: Ticker::new(&*clock, RETRANSMIT_INTERVAL),
+            // heartbeats go out as often as proofs, which is more often than followers time out
+            heartbeat_timer: Ticker::new(&*clock, proof_length),
+            ping_timer: ping_length.map(|length| Ticker::new(&*clock, length)),
+            due: [false; 5],
+            next_turn: 0,
+            pings: HashMap::new(),
+            next_nonce: 0,
+            last_proof: None,
+            last_view_change: None,
+            view_change_cooldown,
+            clock,
+            // we can never have attempted a view smaller than the one we installed
+            proposer: Proposer::new(views.last_attempted_view.max(current_view), quorum_size),
+            acceptor: Acceptor::new(pid, current_view),
+            proposal: None,
+            install_latency: None,
+            eager_proof, leader_only_proofs, max_view, livelock_threshold,
+            failed_view_changes: 0,
+            blocked: false,
+            stop_when_blocked,
+            view_strategy,
+            suspected_dead: BTreeSet::new(),
+            epoch: 0,
+            reconfig_state: HashMap::new(),
+            state_path,
+            admin: None,
+            view_installed,
+            metrics, events, output_format, output,
+            catch_up, leader_fn,
+            querying: false,
+            done: false,
+            waker: None,
+        }
+    }
+
+    /// Gets the view that this node has installed.
+    pub fn current_view(&self) -> u32 {
+        self.acceptor.current_view
+    }
+
+    /// Gets the last view that this node attempted to install, which is the view it's changing to
+    /// if it's undergoing a view change, and the installed view otherwise.
+    pub fn last_attempted_view(&self) -> u32 {
+        self.proposer.last_attempted_view
+    }
+
+    /// Gets the votes received for the last attempted view, our own included, as the total weight
+    /// of the nodes that sent them (i.e. their count, unless the nodes are weighted).
+    pub fn quorum_progress(&self) -> usize {
+        self.proposer.progress(&self.nodes)
+    }
+
+    /// Gets the epoch of the membership that this node has installed.
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Proposes reconfiguring the system to consist of the first `members` nodes in the next
+    /// epoch, which happens once a majority of the current members vote for it. Fails with
+    /// `InvalidInput` unless there are between one and as many members as known nodes.
+    #[throws(io::Error)]
+    pub fn reconfigure(&mut self, members: u32) -> () {
+        if !self.valid_members(members) {
+            throw!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot have {} of the {} known nodes as members",
+                        members, self.nodes.known()),
+            ))
+        }
+        if self.voted_to_reconfigure() {
+            warn!("already voted to reconfigure in epoch {}", self.epoch);
+            return
+        }
+        self.vote_to_reconfigure(members)?;
+    }
+
+    /// Steps down from leading the installed view by starting a view change to the next view,
+    /// handing leadership over to the next node without anything crashing. Only the leader steps
+    /// down, so any other node (or a leader already changing views) warns and does nothing.
+    #[throws(io::Error)]
+    pub fn step_down(&mut self) -> () {
+        if self.current_leader() != self.pid {
+            warn!("not stepping down from view {} led by {}", self.acceptor.current_view,
+                  self.current_leader());
+            return
         }
+        if self.in_view_change() {
+            warn!("not stepping down, already changing to view {}",
+                  self.proposer.last_attempted_view);
+            return
+        }
+        let new_view = match self.acceptor.current_view.checked_add(1) {
+            Some(new_view) => new_view,
+            None => throw!(io::Error::new(
+                io::ErrorKind::Other, "view counter exhausted, cannot change views again"
+            )),
+        };
+        info!("stepping down as leader of view {}", self.acceptor.current_view);
+        self.start_view_change(new_view)?;
+    }
+
+    /// Sends a ping to the node with the given index, measuring the round trip time to it once it
+    /// answers. Any earlier ping to the node still awaiting an answer is forgotten, counting as a
+    /// failed interaction with it.
+    #[throws(io::Error)]
+    pub fn ping(&mut self, target: usize) -> () {
+        let nonce = self.next_nonce;
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        let unanswered = self.pings.len();
+        self.pings.retain(|_, (pinged, _)| *pinged != target);
+        if self.pings.len() < unanswered {
+            self.nodes.record_failure(target);
+        }
+        self.pings.insert(nonce, (target, self.clock.now()));
+        let ping = Message::Ping { server_id: self.pid, nonce };
+        shed_if_full(self.nodes.unicast_send(ping, target))?;
+    }
+
+    /// Gets the smoothed round trip time to the node with the given index, if it has answered a
+    /// ping.
+    pub fn rtt(&self, node: usize) -> Option<Duration> {
+        self.metrics.rtt(node)
+    }
+
+    /// Gets the time from this node proposing a view change to a quorum confirming that they
+    /// installed its view, for the last view change it proposed that was confirmed, if any.
+    pub fn install_latency(&self) -> Option<Duration> {
+        self.install_latency
+    }
+
+    /// Determines whether or not this node concluded that it can't make progress, having seen a
+    /// full rotation of leaders fail to take over since a view last installed.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Gets the nodes suspected dead, having led a view we installed without keeping us from
+    /// timing out, and not having been heard from since.
+    pub fn suspected_dead(&self) -> &BTreeSet<u32> {
+        &self.suspected_dead
     }
 
-    /// Computes the id of the current leader according to the installed view
+    /// Computes the id of the current leader according to the installed view, by the configured
+    /// mapping from views to leaders if there is one
     pub fn current_leader(&self) -> u32 {
+        self.leader_of(self.acceptor.current_view)
+    }
+
+    /// Computes the id of the leader of the given view, by the configured mapping from views to
+    /// leaders if there is one.
+    fn leader_of(&self, view: u32) -> u32 {
+        debug_assert!(self.nodes.len() != 0, "cannot compute a leader without any nodes");
+        if let Some(ref leader_fn) = self.leader_fn {
+            return leader_fn(view, self.nodes.len())
+        }
         match u32::try_from(self.nodes.len()) {
-            Ok(num_nodes) => self.current_view % num_nodes,
+            Ok(num_nodes) => view % num_nodes,
             // if the length (usize) can't be converted into a u32, then there are more nodes than
-            // could possibly fit into the current view counter. Thus, just the view will suffice.
-            Err(_) => self.current_view,
+            // could possibly fit into the view counter. Thus, just the view will suffice.
+            Err(_) => view,
+        }
+    }
+
+    /// Gets the counters of protocol events.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Carries out the admin commands received over the given channel as the stream is polled,
+    /// answering each one with a line describing the outcome.
+    pub fn serve_admin(&mut self, requests: Receiver<AdminRequest>) {
+        self.admin = Some(requests);
+    }
+
+    /// Carries out the given admin command, returning the response to it.
+    #[throws(io::Error)]
+    fn execute(&mut self, cmd: AdminCommand) -> String {
+        match cmd {
+            AdminCommand::View => format!(
+                "current view {}, last attempted view {}",
+                self.acceptor.current_view, self.proposer.last_attempted_view,
+            ),
+            AdminCommand::Leader => format!("leader {}", self.current_leader()),
+            AdminCommand::ForceViewChange(view)
+                if view > self.acceptor.current_view && self.cooling_down(view) => {
+                format!("view change to view {} just started, not starting view {}",
+                        self.proposer.last_attempted_view, view)
+            },
+            AdminCommand::ForceViewChange(view) if view > self.acceptor.current_view => {
+                self.start_view_change(view)?;
+                format!("started view change to view {}", view)
+            },
+            AdminCommand::ForceViewChange(view) => {
+                format!("view {} is not past the current view {}", view, self.acceptor.current_view)
+            },
+            AdminCommand::StepDown => {
+                let attempted = self.proposer.last_attempted_view;
+                self.step_down()?;
+                if self.proposer.last_attempted_view > attempted {
+                    format!("stepped down, changing to view {}", self.proposer.last_attempted_view)
+                } else {
+                    format!("not the leader of view {}", self.acceptor.current_view)
+                }
+            },
+            AdminCommand::Dump => {
+                let mut views: Vec<_> = self.proposer.votes.iter()
+                    .map(|(view, voters)| {
+                        let mut voters: Vec<_> = voters.iter().cloned().collect();
+                        voters.sort();
+                        (*view, voters)
+                    })
+                    .collect();
+                views.sort();
+                if views.is_empty() {
+                    "no view changes".to_owned()
+                } else {
+                    let views: Vec<_> = views.into_iter()
+                        .map(|(view, voters)| format!("view {}: {:?}", view, voters))
+                        .collect();
+                    views.join("; ")
+                }
+            },
         }
     }
 
     /// Determines whether or not this node is currently undergoing a view change.
     fn in_view_change(&self) -> bool {
-        self.last_attempted_view > self.current_view
+        self.proposer.last_attempted_view > self.acceptor.current_view
     }
 
-    /// Starts a view change to the given view by sending out view change messages
+    /// Starts a view change to the given view by sending out view change messages, unless one to
+    /// the same view or a later one started within the cooldown
     /// invariant: a node should only ever try to install larger views than what it has installed
     #[throws(io::Error)]
     fn start_view_change(&mut self, new_view: u32) {
+        if self.cooling_down(new_view) {
+            info!("coalescing view change to {} into the one to {} underway", new_view,
+                  self.proposer.last_attempted_view);
+            return
+        }
         info!("start view change to new view: {}", new_view);
-        assert!(new_view > self.current_view);
-
-        // clear the current view change state
-        self.view_change_state.clear();
+        assert!(new_view > self.acceptor.current_view);
+        self.last_view_change = Some((new_view, self.clock.now()));
+        Metrics::incr(&self.metrics.view_changes_started);
 
-        // set the last attempted view to this new view
-        self.last_attempted_view = new_view;
+        // attempt this new view, voting for it ourselves and forgetting the view changes for views
+        // we'll never install now
+        self.proposer.attempt(new_view, self.pid);
+        self.record(Event::ViewChangeStarted);
+        // and start timing how long it takes a quorum to install the view we're proposing
+        self.proposal = Some(Proposal {
+            view: new_view,
+            started: self.clock.now(),
+            confirmed: HashSet::new(),
+        });
 
         // send view change to all the servers
-        self.nodes.multicast_send(Message::ViewChange {
+        shed_if_full(self.nodes.multicast_send(Message::ViewChange {
             server_id: self.pid,
             attempted: new_view,
-        })?;
+        }))?;
 
         // resets the progress timer
         self.reset_progress_timer();
+
+        // if our own vote is a quorum (e.g. we're the only node), nobody else needs to weigh in
+        self.install_view_if_possible()?;
+    }
+
+    /// Determines whether or not a view change to the given view would be redundant, coming within
+    /// the cooldown of starting one to the same view or a later one. Starting over would just
+    /// send the view change out again and forget which nodes confirmed our proposal so far, or
+    /// worse, go back to an earlier view and lose track of the votes for the later one.
+    fn cooling_down(&self, view: u32) -> bool {
+        match self.last_view_change {
+            Some((started, at)) => {
+                view <= started && self.clock.now() < at + self.view_change_cooldown
+            },
+            None => false,
+        }
     }
 
     /// Installs the last attempted view if we have seen a majority attempting to install it
@@ -119,181 +1033,2146 @@ impl Paxos {
     fn install_view_if_possible(&mut self) {
         if !self.in_view_change() { return }
 
-        let vc_received = self.view_change_state.iter()
-            .filter(|vc| vc.1 == self.last_attempted_view)
-            .count();
-        // if we have a majority attempting to install the last_attempted_view, then
-        if vc_received >= (self.nodes.len() / 2) + 1 {
-            info!("proof found: majority will install view {}", self.last_attempted_view);
-            // first, invoke test case hook to see if we should crash
-            self.test_case_crash_hook();
+        // if we have a quorum attempting to install the last_attempted_view, then
+        if self.proposer.has_quorum(&self.nodes) {
+            info!("proof found: majority will install view {}", self.proposer.last_attempted_view);
+            self.record(Event::QuorumReached);
+            // first, invoke the crash hook to see if we should crash
+            self.crash_hook();
             // then, we can go ahead and install the view (since we have no reconciliation phase)
-            self.install_view()?;
+            self.install_view(None)?;
         } else {
             info!("insufficient proof to install view {}: {}",
-                  self.last_attempted_view, vc_received);
+                  self.proposer.last_attempted_view, self.quorum_progress());
         }
     }
 
-    /// Installs the last attempted view unconditionally
+    /// Traces the nodes that voted for the last attempted view so far, along with the weight of the
+    /// votes needed to install it, so that a view change stuck short of a quorum can be diagnosed.
+    fn trace_votes(&self) {
+        let view = self.proposer.last_attempted_view;
+        let voters: BTreeSet<_> = self.proposer.voters(view)
+            .map_or_else(BTreeSet::new, |voters| voters.iter().cloned().collect());
+        let (received, needed) = (self.quorum_progress(), self.view_change_quorum());
+        event!(Level::DEBUG, view, voters = ?voters, received, needed,
+               "votes for view {}: {:?}, need {}", view, voters, needed);
+    }
+
+    /// Computes the total weight of the view changes needed to install a view, which is a majority
+    /// of the total weight of the nodes unless configured otherwise (either directly or by the
+    /// number of failures to tolerate).
+    fn view_change_quorum(&self) -> usize {
+        self.proposer.quorum(&self.nodes)
+    }
+
+    /// Installs the last attempted view unconditionally, replying with our proof to just the given
+    /// node if the install answers a query (rather than multicasting it)
     /// invariant: a view can only be installed with a proof in the form of either view changes from
     /// a majority of nodes or a vc proof message from another node
     #[throws(io::Error)]
-    fn install_view(&mut self) {
+    fn install_view(&mut self, reply_to: Option<u32>) {
         // we should never install a view that is smaller than the one we already had
-        assert!(self.last_attempted_view >= self.current_view);
+        assert!(self.proposer.last_attempted_view >= self.acceptor.current_view);
 
-        self.current_view = self.last_attempted_view;
-        info!("installed view {}", self.current_view);
+        self.acceptor.current_view = self.proposer.last_attempted_view;
+        self.persist_views()?;
+        Metrics::incr(&self.metrics.views_installed);
+        self.record(Event::ViewInstalled);
+        // we made progress, so the progress timer can go back to its base length, starting over
+        self.backoff_factor = 1;
+        self.failed_view_changes = 0;
+        self.blocked = false;
+        self.reset_progress_timer();
+        info!("installed view {}", self.acceptor.current_view);
         self.output_leader();
-        self.test_case_exit_hook();
+        self.notify_installed();
+        self.exit_hook();
 
-        // send a VC proof immediately (not strictly necessary though)
-        self.nodes.multicast_send(Message::VCProof {
-            server_id: self.pid,
-            installed: self.current_view,
-        })?;
+        // we count towards confirming our own proposal of this view, if it was ours
+        self.confirm_install(self.pid, self.acceptor.current_view);
+
+        // a view learned by asking already has its proof out there, so only the node that answered
+        // needs our confirmation, and multicasting it would only set off more proofs
+        if let Some(target) = reply_to {
+            Metrics::incr(&self.metrics.proofs_sent);
+            shed_if_full(self.nodes.unicast_send(self.proof(), target as usize))?;
+        } else if self.eager_proof {
+            // send a VC proof immediately if configured to (not strictly necessary though, since
+            // the vc proof timer will send one soon enough), which confirms the install to everyone
+            self.send_proof()?;
+        } else {
+            // otherwise, we confirm it to just the nodes that proposed it, which are timing it
+            let proof = self.proof();
+            let proposers: Vec<_> = self.proposer.voters(self.acceptor.current_view)
+                .map_or(Vec::new(), |voters| voters.iter().cloned().collect());
+            for proposer in proposers.into_iter().filter(|id| *id != self.pid) {
+                shed_if_full(self.nodes.unicast_send(proof, proposer as usize))?;
+            }
+        }
     }
 
-    /// Resets the progress timer to its full length from now.
-    fn reset_progress_timer(&mut self) {
-        self.progress_timer.reset(Instant::now() + self.progress_length);
-        info!("progress timer reset!");
-    }
-
-    /// Outputs the current leader and the new view.
-    fn output_leader(&self) {
-        println!("{}: Server {} is the new leader of view {}",
-                 self.pid, self.current_leader(), self.current_view);
-    }
-
-    /// Either crash or do nothing, depending on the pid and test case.
-    ///
-    /// The behavior is defined as follows:
-    /// ```
-    /// /------------------------------\
-    /// | pid | test case  | behavior  |
-    /// |------------------------------|
-    /// | 1   | 1, 2       | nop       |
-    /// | 1   | 3, 4, 5    | crash     |
-    /// |------------------------------|
-    /// | 2   | 1, 2, 3    | nop       |
-    /// | 2   | 4, 5       | crash     |
-    /// |------------------------------|
-    /// | 3   | 1, 2, 3, 4 | nop       |
-    /// | 3   | 5          | crash     |
-    /// |------------------------------|
-    /// | 4   | *          | nop       |
-    /// |------------------------------|
-    /// | 5   | *          | nop       |
-    /// \------------------------------/
-    /// ```
-    fn test_case_crash_hook(&self) {
-        trace!("crash hook invoked");
-        use TestCase::*;
+    /// Notes that the given server confirmed installing the given view, recording the latency of
+    /// our proposal of that view (if we made one) once the confirmations make up a quorum.
+    fn confirm_install(&mut self, server_id: u32, view: u32) {
+        let quorum = self.view_change_quorum();
+        let proposal = match self.proposal {
+            Some(ref mut proposal) if proposal.view == view => proposal,
+            _ => return,
+        };
+        proposal.confirmed.insert(server_id);
+        let nodes = &self.nodes;
+        let confirmed: usize = proposal.confirmed.iter()
+            .map(|id| nodes.weight(*id as usize) as usize)
+            .sum();
+        if confirmed >= quorum {
+            let latency = self.clock.now() - proposal.started;
+            info!("view {} confirmed installed by a quorum {:?} after proposing it", view, latency);
+            self.metrics.record_install_latency(latency);
+            self.install_latency = Some(latency);
+            self.proposal = None;
+        }
+    }
 
-        match self.test_case {
-            SingleCrash if self.pid == 1 => panic!("crashing"),
-            TwoCrashes if self.pid < 3 && self.pid > 0 => panic!("crashing"),
-            ThreeCrashes if self.pid < 4 && self.pid > 0 => panic!("crashing"),
-            _ => (),
+    /// Notifies the channel of installed views (if any) of the view just installed. Notifications
+    /// are dropped if the channel is full, rather than holding up the protocol, and stop once the
+    /// receiver is gone.
+    fn notify_installed(&mut self) {
+        let installed = ViewInstalled {
+            view: self.acceptor.current_view,
+            leader: self.current_leader(),
+        };
+        let res = match self.view_installed {
+            Some(ref mut tx) => tx.try_send(installed),
+            None => return,
+        };
+        match res {
+            Ok(()) => (),
+            Err(ref e) if e.is_full() => {
+                warn!("notification channel full, dropping notice of view {}", installed.view);
+            },
+            Err(_) => {
+                info!("notification receiver gone, no longer notifying of installed views");
+                self.view_installed = None;
+            },
         }
     }
 
-    /// Either exits the program or does nothing, depending on the pid and test case.
-    fn test_case_exit_hook(&self) -> () {
-        trace!("exit hook invoked");
-        use TestCase::*;
+    /// Multicasts a proof of the installed view, unless we just sent one for the same view.
+    #[throws(io::Error)]
+    fn send_proof(&mut self) -> () {
+        let now = self.clock.now();
+        if let Some((view, sent_at)) = self.last_proof {
+            if view == self.acceptor.current_view && now < sent_at + PROOF_SUPPRESSION_WINDOW {
+                trace!("suppressing repeated proof of view {}", view);
+                return
+            }
+        }
+        self.last_proof = Some((self.acceptor.current_view, now));
+        Metrics::incr(&self.metrics.proofs_sent);
+        shed_if_full(self.nodes.multicast_send(self.proof()))?;
+    }
 
-        match self.test_case {
-            NormalCase if self.current_view == 1 => process::exit(0),
-            FullRotation if self.current_view != 0 && self.current_leader() == 0 =>
-                process::exit(0),
-            SingleCrash if self.current_view == 2 => process::exit(0),
-            TwoCrashes if self.current_view == 3 => process::exit(0),
-            ThreeCrashes if self.current_view == 4 => process::exit(0),
-            _ => (),
+    /// Builds a proof of the installed view, naming its leader as the proposer it came from.
+    fn proof(&self) -> Message {
+        Message::VCProof {
+            server_id: self.pid,
+            installed: self.acceptor.current_view,
+            proposer: self.current_leader(),
         }
     }
-}
 
-impl Sink<Message> for Paxos {
-    type Error = io::Error;
+    /// Determines whether or not this node has voted to reconfigure the system in the next epoch.
+    fn voted_to_reconfigure(&self) -> bool {
+        self.reconfig_state.values().any(|voters| voters.contains(&self.pid))
+    }
 
-    fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    /// Votes to reconfigure the system to consist of the first `members` nodes in the next epoch
+    /// by sending out reconfig messages.
+    #[throws(io::Error)]
+    fn vote_to_reconfigure(&mut self, members: u32) {
+        let epoch = match self.epoch.checked_add(1) {
+            Some(epoch) => epoch,
+            None => throw!(io::Error::new(
+                io::ErrorKind::Other, "epoch counter exhausted, cannot reconfigure again"
+            )),
+        };
+        info!("voting to reconfigure to {} members in epoch {}", members, epoch);
+
+        // record our own vote, since our multicasts are not delivered back to us
+        self.reconfig_state.entry(members).or_default().insert(self.pid);
+        shed_if_full(self.nodes.multicast_send(Message::Reconfig {
+            server_id: self.pid,
+            epoch, members,
+        }))?;
+        self.reconfigure_if_possible(members)?;
     }
 
+    /// Determines whether or not the system can be made up of the given number of members, which
+    /// takes at least one and no more than the nodes we know of.
+    fn valid_members(&self, members: u32) -> bool {
+        members > 0 && members as usize <= self.nodes.known()
+    }
+
+    /// Installs the next epoch with the given number of members if we have seen a quorum of the
+    /// current members voting for it (the same quorum, weights and all, that installs a view),
+    /// recomputing every quorum from then on with the new members.
     #[throws(io::Error)]
-    fn start_send(mut self: Pin<&mut Self>, msg: Message) -> () {
-        trace!("processing message: {:?}", msg);
-        match msg {
-            Message::ViewChange { server_id, attempted } => {
-                // this view change message is stale
-                if attempted < self.last_attempted_view {
-                    warn!("stale view change message received: {}", attempted);
-                    return
-                }
+    fn reconfigure_if_possible(&mut self, members: u32) {
+        let nodes = &self.nodes;
+        let votes: usize = self.reconfig_state.get(&members)
+            .map_or(0, |voters| voters.iter().map(|id| nodes.weight(*id as usize) as usize).sum());
+        if votes < self.view_change_quorum() {
+            info!("insufficient votes to reconfigure to {} members: {}", members, votes);
+            return
+        }
 
-                // there's an ongoing view change to a higher view
-                if attempted > self.last_attempted_view {
-                    return self.start_view_change(attempted)?
-                }
+        let epoch = self.epoch + 1;
+        self.nodes.reconfigure(epoch, members as usize)?;
+        self.epoch = epoch;
+        self.reconfig_state.clear();
+        info!("installed epoch {} with {} members", self.epoch, members);
 
-                // this message is for the view we want to install
-                self.view_change_state.insert(VC(server_id, attempted));
-                self.install_view_if_possible()?;
-            }
+        // view changes from nodes that are no longer members must not count towards a quorum,
+        // while the ones left may now be a quorum on their own
+        self.proposer.retain_members(members);
+        self.install_view_if_possible()?;
+    }
 
-            Message::VCProof { server_id, installed } => {
-                if installed == self.last_attempted_view && installed > self.current_view {
-                    info!("installing view {} based on VC Proof from {}", installed, server_id);
-                    // someone installed this view before us, so we can too!
-                    self.install_view()?;
-                }
-            }
+    /// Records the given event in the event log along with the current state of this node.
+    fn record(&mut self, event: Event) {
+        let (pid, current_view, last_attempted_view) =
+            (self.pid, self.acceptor.current_view, self.proposer.last_attempted_view);
+        self.events.record(event, pid, current_view, last_attempted_view);
+    }
+
+    /// Persists the current and last attempted views to the state file, if there is one.
+    #[throws(io::Error)]
+    fn persist_views(&self) {
+        if let Some(ref path) = self.state_path {
+            DurableViews {
+                current_view: self.acceptor.current_view,
+                last_attempted_view: self.proposer.last_attempted_view,
+            }.store(path)?;
         }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    /// Computes the length of the progress timer under the current backoff, up to the cap.
+    fn backoff_length(&self) -> Duration {
+        self.progress_length.checked_mul(self.backoff_factor)
+            .map_or(self.progress_cap, |length| length.min(self.progress_cap))
     }
 
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    /// Backs off the progress timer after it expired without a view being installed.
+    fn back_off(&mut self) {
+        if self.backoff_length() < self.progress_cap {
+            self.backoff_factor = self.backoff_factor.saturating_mul(self.backoff_multiplier);
+            info!("progress timer backed off to {:?}", self.backoff_length());
+        }
     }
-}
 
-impl Stream for Paxos {
-    type Item = io::Result<()>;
+    /// Counts a view change that the progress timer expired on, reporting a suspected livelock once
+    /// too many have failed in a row, and reporting that we're blocked for good once every node
+    /// has had its turn to lead without any taking over, since the next leader is then one we've
+    /// already seen fail.
+    fn fail_view_change(&mut self) {
+        self.failed_view_changes = self.failed_view_changes.saturating_add(1);
+        if Some(self.failed_view_changes) == self.livelock_threshold {
+            warn!("suspected livelock after {} failed view changes, stuck at view {}",
+                  self.failed_view_changes, self.acceptor.current_view);
+            self.record(Event::LivelockSuspected);
+        }
+        if !self.blocked && self.failed_view_changes as usize >= self.nodes.len() {
+            error!("cannot make progress, exceeded failure budget: a full rotation of {} leaders \
+                    failed to take over from view {}",
+                   self.nodes.len(), self.acceptor.current_view);
+            self.blocked = true;
+            self.record(Event::FailureBudgetExceeded);
+        }
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // note: we have to ensure we poll both futures each time!
-        let poll_progress_timer = Future::poll(Pin::new(&mut self.progress_timer), ctx);
-        trace!("polled progress timer");
-        let poll_vc_proof_timer = Stream::poll_next(Pin::new(&mut self.vc_proof_timer), ctx);
-        trace!("polled vc proof timer");
-
-        // if progress timer expired,
-        if let Poll::Ready(()) = poll_progress_timer {
-            trace!("progress timer expired");
-            // then we'll start a view change to the next view
-            let new_view = self.last_attempted_view + 1;
-            return Poll::Ready(Some(self.start_view_change(new_view)))
-        }
-
-        // if vc proof timer fired,
-        if let Poll::Ready(Some(_)) = poll_vc_proof_timer {
-            trace!("vc proof timer fired");
-           // then we'll multicast a vc proof to everyone 
-            let server_id = self.pid;
-            let installed = self.current_view;
-            return Poll::Ready(Some(self.nodes.multicast_send(
-                Message::VCProof { server_id, installed }
-            )));
-        }
-
-        trace!("both timers pending");
-        Poll::Pending
+    /// Resets the progress timer to its full length (plus jitter) from now.
+    fn reset_progress_timer(&mut self) {
+        let length = self.backoff_length() + jitter(&mut self.rng, self.progress_jitter_ms);
+        self.progress_timer = self.clock.delay_until(self.clock.now() + length);
+        info!("progress timer reset to {:?}!", length);
+    }
+
+    /// Outputs the current leader and the new view in the configured format. Failing to output
+    /// them does not fail the protocol, and is only logged.
+    fn output_leader(&mut self) {
+        let (pid, leader, view) = (self.pid, self.current_leader(), self.acceptor.current_view);
+        let res = match self.output_format {
+            OutputFormat::Human => writeln!(
+                self.output, "{}: Server {} is the new leader of view {}", pid, leader, view
+            ),
+            OutputFormat::Json => writeln!(
+                self.output, "{{\"pid\":{},\"leader\":{},\"view\":{}}}", pid, leader, view
+            ),
+        };
+        if let Err(e) = res {
+            warn!("failed to output the leader of view {}: {}", view, e);
+        }
+    }
+
+    /// Either crashes or does nothing, depending on the pid, attempted view, and failure schedule.
+    fn crash_hook(&self) {
+        trace!("crash hook invoked");
+        if self.schedule.should_crash(self.pid, self.proposer.last_attempted_view) {
+            panic!("crashing");
+        }
+    }
+
+    /// Either finishes the protocol or does nothing, depending on the pid, installed view, and
+    /// failure schedule.
+    fn exit_hook(&mut self) -> () {
+        trace!("exit hook invoked");
+        if self.schedule.should_exit(self.pid, self.acceptor.current_view, self.current_leader()) {
+            self.finish();
+        }
+    }
+
+    /// Finishes the protocol, ending the stream the next time it's polled.
+    fn finish(&mut self) {
+        info!("protocol finished in view {}", self.acceptor.current_view);
+        self.done = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Services the given timer that fired, returning the outcome of whatever it set off, if it
+    /// set off anything at all.
+    fn service(&mut self, timer: Timer) -> Option<io::Result<()>> {
+        match timer {
+            // if progress timer expired,
+            Timer::Progress => {
+                event!(Level::TRACE, timer = "progress", "timer expired");
+                // then the view change underway (if any) failed,
+                if self.in_view_change() {
+                    self.fail_view_change();
+                } else if self.current_leader() != self.pid {
+                    // or else the leader of the installed view missed its heartbeats,
+                    let leader = self.current_leader();
+                    if self.suspected_dead.insert(leader) {
+                        warn!("suspecting leader {} of view {} is dead",
+                              leader, self.acceptor.current_view);
+                    }
+                }
+                // and if that leaves us blocked for good, we may as well stop trying
+                if self.blocked && self.stop_when_blocked {
+                    error!("stopping the progress timer, since no view can install");
+                    self.progress_timer = Box::pin(future::pending());
+                    return Some(Ok(()))
+                }
+                // and we'll back off and start a view change to the next view (by our strategy)
+                self.back_off();
+                // along with any node the transport suspects of failing to answer us
+                let mut suspected_dead = self.suspected_dead.clone();
+                suspected_dead.extend(self.nodes.suspected_dead().iter().map(|idx| *idx as u32));
+                let next_view = self.view_strategy.next_view(
+                    self.proposer.last_attempted_view, self.nodes.len(),
+                    &|view| self.leader_of(view), &suspected_dead,
+                );
+                let new_view = match next_view {
+                    Some(new_view) => new_view,
+                    None => return Some(Err(io::Error::new(
+                        io::ErrorKind::Other, "view counter exhausted, cannot change views again"
+                    ))),
+                };
+                // unless that would climb past the ceiling, in which case we stop timing out
+                // entirely (though views other nodes install are still installed, restarting it)
+                if let Some(max_view) = self.max_view.filter(|max_view| new_view > *max_view) {
+                    error!("refusing to change to view {} past the maximum view {}, stopping the \
+                            progress timer", new_view, max_view);
+                    self.progress_timer = Box::pin(future::pending());
+                    return Some(Ok(()))
+                }
+                event!(Level::TRACE, new_view, "starting view change");
+                Some(self.start_view_change(new_view))
+            },
+            // if vc proof timer fired,
+            Timer::VcProof => {
+                event!(Level::TRACE, timer = "vc_proof", "timer fired");
+                // then we'll multicast a vc proof to everyone, unless that's left to the leader
+                if !self.leader_only_proofs || self.current_leader() == self.pid {
+                    return Some(self.send_proof())
+                }
+                event!(Level::TRACE, "leaving vc proofs to the leader");
+                None
+            },
+            // if retransmit timer fired,
+            Timer::Retransmit => {
+                event!(Level::TRACE, timer = "retransmit", "timer fired");
+                // then we'll resend any unacknowledged view changes
+                Some(shed_if_full(self.nodes.retransmit()))
+            },
+            // if heartbeat timer fired while we lead the installed view,
+            Timer::Heartbeat => {
+                if self.in_view_change() || self.current_leader() != self.pid {
+                    return None
+                }
+                event!(Level::TRACE, timer = "heartbeat", "timer fired");
+                // then we'll let our followers know we're still around
+                let (server_id, view) = (self.pid, self.acceptor.current_view);
+                let heartbeat = Message::Heartbeat { server_id, view };
+                Some(shed_if_full(self.nodes.multicast_send(heartbeat)))
+            },
+            // if ping timer fired,
+            Timer::Ping => {
+                event!(Level::TRACE, timer = "ping", "timer fired");
+                // then we'll measure the round trip time to everyone else
+                let others: Vec<_> = (0..self.nodes.len())
+                    .filter(|idx| *idx != self.pid as usize)
+                    .collect();
+                Some(others.into_iter().map(|idx| self.ping(idx)).collect())
+            },
+        }
+    }
+}
+
+/// Clamps the length of the named timer to between the given minimum and `MAX_TIMER_LENGTH`,
+/// warning if it had to.
+fn clamp_timer(name: &str, length: Duration, min: Duration) -> Duration {
+    let clamped = length.min(MAX_TIMER_LENGTH).max(min);
+    if clamped != length {
+        warn!("clamping the {} timer from {:?} to {:?}", name, length, clamped);
+    }
+    clamped
+}
+
+/// Offsets the first progress timeout of the node with the given pid among the given number of
+/// nodes, spreading the nodes evenly across the given span in milliseconds. The seed rotates which
+/// node goes first, and nodes sharing a seed never share an offset.
+fn initial_offset(pid: usize, len: usize, seed: u64, spread_ms: u64) -> Duration {
+    if spread_ms == 0 || len == 0 { return Duration::from_millis(0) }
+    let len = len as u64;
+    let slot = (pid as u64 % len + seed % len) % len;
+    Duration::from_millis(spread_ms * slot / len)
+}
+
+/// Picks a uniformly random amount of jitter below the given bound in milliseconds.
+fn jitter(rng: &mut StdRng, bound_ms: u64) -> Duration {
+    if bound_ms == 0 { return Duration::from_millis(0) }
+    Duration::from_millis(rng.gen_range(0, bound_ms))
+}
+
+/// Drops a message that the transport had no room for, as if it were lost in the network, since
+/// the protocol's timers already recover from lost messages.
+fn shed_if_full(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            warn!("outgoing queue full, dropping message: {}", e);
+            Ok(())
+        },
+        res => res,
+    }
+}
+
+impl<T: Transport + Unpin> Sink<Message> for Paxos<T> {
+    type Error = io::Error;
+
+    /// Waits for room in the transport before accepting another message, since handling one may
+    /// send replies. This pushes back on the incoming socket while the outgoing one is behind.
+    fn poll_ready(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.nodes.poll_ready(ctx)
+    }
+
+    #[throws(io::Error)]
+    fn start_send(mut self: Pin<&mut Self>, msg: Message) -> () {
+        trace!("processing message: {:?}", msg);
+        if self.done {
+            trace!("protocol finished, ignoring message");
+            return
+        }
+        // hearing from a node at all is enough to stop suspecting that it's dead
+        if self.suspected_dead.remove(&msg.server_id()) {
+            info!("no longer suspecting {} is dead", msg.server_id());
+        }
+        if (msg.server_id() as usize) < self.nodes.len() {
+            self.nodes.record_success(msg.server_id() as usize);
+        }
+
+        match msg {
+            Message::ViewChange { server_id, attempted } => {
+                // a view change from a node we don't know about must not count towards a quorum
+                if (server_id as usize) >= self.nodes.len() {
+                    warn!("rejecting view change from unknown node {}", server_id);
+                    Metrics::incr(&self.metrics.rejected_messages);
+                    return
+                }
+
+                // in reliable mode, let the sender know that we received its view change
+                if self.nodes.is_reliable() {
+                    let ack = self.acceptor.ack(attempted);
+                    shed_if_full(self.nodes.unicast_send(ack, server_id as usize))?;
+                }
+
+                match self.acceptor.judge(attempted, self.proposer.last_attempted_view) {
+                    // this view change message is stale
+                    Judgement::Stale { behind } => {
+                        warn!("stale view change message received: {}", attempted);
+                        Metrics::incr(&self.metrics.stale_messages);
+                        // if the sender is behind what we installed, let it know so it can catch up
+                        if behind {
+                            let nack = self.acceptor.nack();
+                            shed_if_full(self.nodes.unicast_send(nack, server_id as usize))?;
+                        }
+                    }
+
+                    // there's an ongoing view change to a higher view
+                    Judgement::Newer => return self.start_view_change(attempted)?,
+
+                    // this message is for the view we want to install
+                    Judgement::Vote => {
+                        self.proposer.vote(server_id, attempted);
+                        self.trace_votes();
+                        self.install_view_if_possible()?;
+                    }
+                }
+            }
+
+            Message::VCProof { server_id, installed, proposer } => {
+                // a proof from a node we don't know about must not move us to its view
+                if (server_id as usize) >= self.nodes.len() {
+                    warn!("rejecting vc proof from unknown node {}", server_id);
+                    Metrics::incr(&self.metrics.rejected_messages);
+                    return
+                }
+                self.record(Event::ProofReceived);
+                // a proof confirms that its sender installed the view, which we may have proposed
+                self.confirm_install(server_id, installed);
+                // the first proof to arrive after querying our peers is taken as the answer
+                let reply_to = if self.querying { Some(server_id) } else { None };
+                self.querying = false;
+                // a duplicate (or any later proof) of the view we installed has nothing to add
+                if installed == self.acceptor.current_view {
+                    trace!("ignoring proof of view {} (proposed by {}) from {}, already installed",
+                           installed, proposer, server_id);
+                    return
+                }
+                // a proof of a view past the one we're attempting comes from a node far ahead of
+                // us, and since it's a proof, we can skip straight to that view rather than go on
+                // proposing one that will never install
+                if installed > self.proposer.last_attempted_view {
+                    info!("fast-forwarding from view {} to view {} based on VC Proof from {}",
+                          self.proposer.last_attempted_view, installed, server_id);
+                    self.proposer.last_attempted_view = installed;
+                    self.proposer.prune();
+                }
+                if installed == self.proposer.last_attempted_view
+                    && installed > self.acceptor.current_view
+                {
+                    info!("installing view {} (proposed by {}) based on VC Proof from {}",
+                          installed, proposer, server_id);
+                    // someone installed this view before us, so we can too!
+                    self.install_view(reply_to)?;
+                }
+            }
+
+            Message::Ack { server_id, acking } => {
+                trace!("view change to {} acknowledged by {}", acking, server_id);
+                let key = (self.pid, acking);
+                self.nodes.acknowledge(server_id as usize, key);
+            }
+
+            Message::Nack { server_id, current_view } => {
+                // a nack from a node we don't know about must not move us to its view
+                if (server_id as usize) >= self.nodes.len() {
+                    warn!("rejecting nack from unknown node {}", server_id);
+                    Metrics::incr(&self.metrics.rejected_messages);
+                    return
+                }
+                // the nacking node installed this view, which is as good a proof as a vc proof
+                if current_view >= self.proposer.last_attempted_view
+                    && current_view > self.acceptor.current_view
+                {
+                    info!("fast-forwarding to view {} based on Nack from {}",
+                          current_view, server_id);
+                    self.proposer.last_attempted_view = current_view;
+                    self.proposer.prune();
+                    self.install_view(None)?;
+                }
+            }
+
+            Message::Heartbeat { server_id, view } => {
+                // only the leader of the view we installed (and aren't changing from) can keep us
+                // from timing out
+                if view == self.acceptor.current_view && !self.in_view_change()
+                    && server_id == self.current_leader() {
+                    trace!("heartbeat received from leader {} of view {}", server_id, view);
+                    self.reset_progress_timer();
+                }
+            }
+
+            Message::Reconfig { server_id, epoch, members } => {
+                // only the current members can vote on who the members are next
+                if (server_id as usize) >= self.nodes.len() {
+                    warn!("rejecting reconfig from unknown node {}", server_id);
+                    Metrics::incr(&self.metrics.rejected_messages);
+                    return
+                }
+
+                // a reconfiguration we could never install must not get as far as a vote
+                if !self.valid_members(members) {
+                    warn!("rejecting reconfig from {} to {} of the {} known nodes",
+                          server_id, members, self.nodes.known());
+                    Metrics::incr(&self.metrics.rejected_messages);
+                    return
+                }
+
+                // we can only move on to the epoch right after the one we installed
+                if self.epoch.checked_add(1) != Some(epoch) {
+                    warn!("ignoring reconfig for epoch {} in epoch {}", epoch, self.epoch);
+                    if epoch <= self.epoch {
+                        Metrics::incr(&self.metrics.stale_messages);
+                    }
+                    return
+                }
+
+                self.reconfig_state.entry(members).or_default().insert(server_id);
+                // like a view change, join the first reconfiguration we hear of
+                if !self.voted_to_reconfigure() {
+                    return self.vote_to_reconfigure(members)?
+                }
+                self.reconfigure_if_possible(members)?;
+            }
+
+            Message::Ping { server_id, nonce } => {
+                if (server_id as usize) >= self.nodes.len() {
+                    warn!("ping from unknown node {}", server_id);
+                    return
+                }
+                let pong = Message::Pong { server_id: self.pid, nonce };
+                shed_if_full(self.nodes.unicast_send(pong, server_id as usize))?;
+            }
+
+            Message::Pong { server_id, nonce } => {
+                match self.pings.remove(&nonce) {
+                    Some((pinged, sent)) if pinged == server_id as usize => {
+                        let rtt = self.clock.now() - sent;
+                        trace!("round trip time to {} measured at {:?}", server_id, rtt);
+                        self.metrics.record_rtt(pinged, rtt);
+                    },
+                    Some((pinged, sent)) => {
+                        warn!("pong for ping to {} came from {}", pinged, server_id);
+                        // the ping is still outstanding, so keep waiting for its real answer
+                        self.pings.insert(nonce, (pinged, sent));
+                    },
+                    None => trace!("pong for unknown ping {} from {}", nonce, server_id),
+                }
+            }
+
+            Message::ViewQuery { server_id } => {
+                if (server_id as usize) >= self.nodes.len() {
+                    warn!("view query from unknown node {}", server_id);
+                    return
+                }
+                info!("answering view query from {} with view {}",
+                      server_id, self.acceptor.current_view);
+                // reply directly to the querier with a proof of our installed view
+                shed_if_full(self.nodes.unicast_send(self.proof(), server_id as usize))?;
+                Metrics::incr(&self.metrics.proofs_sent);
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Transport + Unpin> Stream for Paxos<T> {
+    type Item = io::Result<()>;
+
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // every poll gets its own span, so that traces can be filtered by node and view
+        let current_view = self.acceptor.current_view;
+        let span = trace_span!("poll", pid = self.pid as u64, current_view);
+        let _entered = span.enter();
+
+        // once the protocol has finished, the stream has ended
+        if self.done {
+            event!(Level::TRACE, "protocol finished, ending stream");
+            return Poll::Ready(None)
+        }
+        self.waker = Some(ctx.waker().clone());
+
+        // a fresh (or restarted) node asks its peers for their views first thing, rather than
+        // waiting until the next proofs happen to arrive
+        if self.catch_up {
+            self.catch_up = false;
+            event!(Level::TRACE, "querying peers to catch up");
+            let query = Message::ViewQuery { server_id: self.pid };
+            self.querying = true;
+            return Poll::Ready(Some(shed_if_full(self.nodes.multicast_send(query))))
+        }
+
+        // note: we have to ensure we poll every timer each time!
+        let this = &mut *self;
+        let clock = &*this.clock;
+        let fired = [
+            this.progress_timer.as_mut().poll(ctx).is_ready(),
+            this.vc_proof_timer.poll_tick(clock, ctx).is_ready(),
+            this.retransmit_timer.poll_tick(clock, ctx).is_ready(),
+            this.heartbeat_timer.poll_tick(clock, ctx).is_ready(),
+            this.ping_timer.as_mut().map_or(false, |ping| ping.poll_tick(clock, ctx).is_ready()),
+        ];
+        // the progress timer stays expired until it's reset, but tickers only fire once, so each
+        // ticker that fired stays due until its turn comes around
+        this.due[0] = fired[0];
+        for (due, fired) in this.due.iter_mut().zip(fired.iter()).skip(1) {
+            *due |= *fired;
+        }
+
+        // due timers take turns round-robin, starting from the one after the last serviced, so one
+        // that keeps firing can't starve the others: every due timer is serviced within five polls
+        for turn in 0..Timer::ALL.len() {
+            let idx = (self.next_turn + turn) % Timer::ALL.len();
+            if !self.due[idx] {
+                continue
+            }
+            self.due[idx] = false;
+            self.next_turn = (idx + 1) % Timer::ALL.len();
+            if let Some(res) = self.service(Timer::ALL[idx]) {
+                return Poll::Ready(Some(res))
+            }
+        }
+
+        // if an admin command came in,
+        let poll_admin = match self.admin {
+            Some(ref mut admin) => admin.poll_recv(ctx),
+            None => Poll::Pending,
+        };
+        match poll_admin {
+            Poll::Ready(Some((cmd, reply))) => {
+                event!(Level::TRACE, "admin command: {:?}", cmd);
+                // then we'll carry it out and answer with the outcome
+                let res = self.execute(cmd);
+                let response = match res {
+                    Ok(ref response) => response.clone(),
+                    Err(ref e) => format!("failed: {}", e),
+                };
+                // the admin may have hung up already, but the command was carried out regardless
+                let _ = reply.send(response);
+                return Poll::Ready(Some(res.map(|_| ())))
+            },
+            Poll::Ready(None) => self.admin = None,
+            Poll::Pending => (),
+        }
+
+        event!(Level::TRACE, "all timers and admin commands pending");
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::Ordering;
+
+    use futures::future::{self, Either};
+    use futures::stream::StreamExt;
+    use futures::task::noop_waker_ref;
+    use tracing::Subscriber;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+
+    use crate::{MockClock, TestCase};
+
+    use super::*;
+
+    /// A transport of a fixed number of nodes that records every message sent, rather than
+    /// delivering it anywhere.
+    #[derive(Default)]
+    struct Recorder {
+        /// the number of nodes in the system
+        len: usize,
+        /// every message sent, along with the index of its target, or `None` if multicast
+        sent: Vec<(Option<usize>, Message)>,
+        /// the weight of each node by index, where nodes without one weigh one
+        weights: Vec<u32>,
+        /// the number of nodes known, members or not
+        known: usize,
+    }
+
+    impl Recorder {
+        /// Creates a transport of the given number of nodes, which knows of as many again that a
+        /// reconfiguration can make members.
+        fn new(len: usize) -> Recorder {
+            Recorder { len, known: 2 * len, ..Recorder::default() }
+        }
+    }
+
+    impl Transport for Recorder {
+        fn len(&self) -> usize {
+            self.len
+        }
+
+        fn weight(&self, idx: usize) -> u32 {
+            self.weights.get(idx).cloned().unwrap_or(1)
+        }
+
+        fn known(&self) -> usize {
+            self.known
+        }
+
+        fn multicast_send(&mut self, msg: Message) -> io::Result<()> {
+            self.sent.push((None, msg));
+            Ok(())
+        }
+
+        fn unicast_send(&mut self, msg: Message, target: usize) -> io::Result<()> {
+            self.sent.push((Some(target), msg));
+            Ok(())
+        }
+
+        fn reconfigure(&mut self, _epoch: u32, members: usize) -> io::Result<()> {
+            self.len = members;
+            Ok(())
+        }
+    }
+
+    /// Creates the node with the given pid among the given number of nodes.
+    fn paxos(pid: usize, len: usize) -> Paxos<Recorder> {
+        paxos_with(pid, len, |_| ())
+    }
+
+    /// Creates the node with the given pid among the given number of nodes, configured further by
+    /// the given function.
+    fn paxos_with<F>(pid: usize, len: usize, configure: F) -> Paxos<Recorder>
+    where
+        F: FnOnce(&mut PaxosConfig<Recorder>),
+    {
+        let mut config = PaxosConfig {
+            pid,
+            nodes: Recorder::new(len),
+            // only finishes once leadership has come all the way back around to node 0
+            schedule: Box::new(TestCase::FullRotation),
+            progress_timer_length: 3,
+            progress_backoff_multiplier: 1,
+            progress_timer_cap: 60,
+            vc_proof_timer_length: 1,
+            max_retransmits: None,
+            state_path: None,
+            progress_jitter_ms: 0,
+            initial_spread_ms: 0,
+            seed: None,
+            metrics: Arc::default(),
+            events: EventLog::disabled(),
+            clock: Box::new(TokioClock),
+            quorum_size: None,
+            eager_proof: true,
+            leader_only_proofs: false,
+            ping_interval: None,
+            max_view: None,
+            livelock_threshold: None,
+            tolerated_failures: None,
+            view_installed: None,
+            initial_view: None,
+            // the catch up query would only get in the way of what most tests check for
+            catch_up: false,
+            leader_fn: None,
+            stop_when_blocked: false,
+            view_strategy: Box::new(Successor),
+            min_timer_length: MIN_TIMER_LENGTH,
+            view_change_cooldown: Duration::from_secs(0),
+            output_format: OutputFormat::Human,
+            output: Box::new(io::stdout()),
+        };
+        configure(&mut config);
+        Paxos::new(config).unwrap()
+    }
+
+    /// Delivers the given message to the node.
+    fn receive(paxos: &mut Paxos<Recorder>, msg: Message) {
+        Pin::new(paxos).start_send(msg).unwrap();
+    }
+
+    /// Polls the node until none of its timers are due.
+    fn poll_until_pending(paxos: &mut Paxos<Recorder>) {
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        while let Poll::Ready(Some(res)) = Pin::new(&mut *paxos).poll_next(&mut ctx) {
+            res.unwrap();
+        }
+    }
+
+    #[test]
+    fn builder_fills_in_defaults_and_chained_settings() {
+        let config = PaxosConfig::builder(1, Recorder::new(3))
+            .progress_timer_length(10)
+            .vc_proof_timer_length(2)
+            .progress_backoff_multiplier(2)
+            .max_retransmits(Some(4))
+            .build()
+            .unwrap();
+        assert_eq!(config.pid, 1);
+        assert_eq!(config.nodes.len(), 3);
+        assert_eq!(config.progress_timer_length, 10);
+        assert_eq!(config.vc_proof_timer_length, 2);
+        assert_eq!(config.progress_backoff_multiplier, 2);
+        assert_eq!(config.progress_timer_cap, 60);
+        assert_eq!(config.max_retransmits, Some(4));
+        assert_eq!(config.progress_jitter_ms, 0);
+        assert_eq!(config.initial_spread_ms, 0);
+        assert_eq!(config.quorum_size, None);
+        assert!(config.eager_proof);
+        assert_eq!(config.max_view, None);
+        assert_eq!(config.livelock_threshold, None);
+        assert_eq!(config.tolerated_failures, None);
+        assert!(!config.schedule.should_crash(1, 1) && !config.schedule.should_exit(1, 1, 1));
+    }
+
+    #[test]
+    fn builder_rejects_each_invalid_configuration() {
+        // builds a default configuration adjusted by the given function, keeping only its error
+        fn build<F>(configure: F) -> Option<ConfigError>
+        where
+            F: FnOnce(PaxosConfigBuilder<Recorder>) -> PaxosConfigBuilder<Recorder>,
+        {
+            configure(PaxosConfig::builder(0, Recorder::new(3))).build().err()
+        }
+        assert_eq!(build(|builder| builder), None);
+        assert_eq!(build(|builder| builder.vc_proof_timer_length(0)), Some(ConfigError::ZeroTimer));
+        assert_eq!(
+            build(|builder| builder.progress_timer_length(0).vc_proof_timer_length(0)),
+            Some(ConfigError::ZeroTimer),
+        );
+        assert_eq!(
+            build(|builder| builder.vc_proof_timer_length(3)),
+            Some(ConfigError::ProofTimerTooLong),
+        );
+        assert_eq!(
+            build(|builder| builder.progress_backoff_multiplier(0)),
+            Some(ConfigError::ZeroBackoff),
+        );
+        assert_eq!(
+            build(|builder| builder.progress_timer_cap(2)),
+            Some(ConfigError::CapBelowProgressTimer),
+        );
+        assert_eq!(build(|builder| builder.quorum_size(Some(3))), None);
+        assert_eq!(
+            build(|builder| builder.quorum_size(Some(4))),
+            Some(ConfigError::QuorumOutOfRange),
+        );
+        assert_eq!(
+            build(|builder| builder.quorum_size(Some(0))),
+            Some(ConfigError::QuorumOutOfRange),
+        );
+        assert_eq!(
+            build(|builder| builder.quorum_size(Some(2)).tolerated_failures(Some(1))),
+            Some(ConfigError::ConflictingQuorums),
+        );
+        assert_eq!(
+            build(|builder| builder.initial_view(Some(5)).max_view(Some(4))),
+            Some(ConfigError::InitialViewPastMax),
+        );
+    }
+
+    #[test]
+    fn zero_progress_timer_put_together_by_hand_is_rejected() {
+        let mut config = PaxosConfig::builder(0, Recorder::new(3)).build().unwrap();
+        config.progress_timer_length = 0;
+        assert!(Paxos::new(config).is_err());
+    }
+
+    #[test]
+    fn timers_shorter_than_the_minimum_are_clamped_to_it() {
+        for (proof_secs, proves) in &[(0, true), (1, false)] {
+            let clock = MockClock::new();
+            let mut node = paxos_with(0, 3, |config| {
+                config.vc_proof_timer_length = *proof_secs;
+                config.min_timer_length = Duration::from_millis(100);
+                config.clock = Box::new(clock.clone());
+            });
+            // a zero second vc proof timer fires once the minimum is up, while a normal one waits
+            clock.advance(Duration::from_millis(100));
+            poll_until_pending(&mut node);
+            let proof = (None, Message::VCProof { server_id: 0, installed: 0, proposer: 0 });
+            assert_eq!(node.nodes.sent.contains(&proof), *proves, "{} second timer", proof_secs);
+        }
+    }
+
+    #[test]
+    fn ping_intervals_out_of_range_are_clamped() {
+        // the nodes the given node has pinged so far
+        fn pinged(node: &Paxos<Recorder>) -> Vec<Option<usize>> {
+            node.nodes.sent.iter()
+                .filter_map(|(target, msg)| match msg {
+                    Message::Ping { .. } => Some(*target),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        // a zero period would have the ticker fire on every poll, so it waits out the minimum
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 3, |config| {
+            config.ping_interval = Some(0);
+            config.min_timer_length = Duration::from_millis(100);
+            config.clock = Box::new(clock.clone());
+        });
+        poll_until_pending(&mut node);
+        assert_eq!(pinged(&node), vec![]);
+        clock.advance(Duration::from_millis(100));
+        poll_until_pending(&mut node);
+        assert_eq!(pinged(&node), vec![Some(1), Some(2)]);
+
+        // while scheduling the first ping u64::MAX seconds from now would overflow the clock
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 3, |config| {
+            config.ping_interval = Some(u64::MAX);
+            config.clock = Box::new(clock.clone());
+        });
+        clock.advance(MAX_TIMER_LENGTH);
+        poll_until_pending(&mut node);
+        assert!(!pinged(&node).is_empty());
+    }
+
+    #[test]
+    fn node_starts_out_in_the_initial_view() {
+        let config = PaxosConfig::builder(0, Recorder::new(3))
+            .initial_view(Some(4))
+            .build()
+            .unwrap();
+        let node = Paxos::new(config).unwrap();
+        assert_eq!(node.current_view(), 4);
+        assert_eq!(node.last_attempted_view(), 4);
+        assert_eq!(node.current_leader(), 1);
+    }
+
+    #[test]
+    fn tolerating_two_failures_takes_five_nodes_and_a_quorum_of_three() {
+        let config = PaxosConfig::builder(0, Recorder::new(5))
+            .tolerated_failures(Some(2))
+            .build()
+            .unwrap();
+        assert_eq!(Paxos::new(config).unwrap().view_change_quorum(), 3);
+
+        let err = PaxosConfig::builder(0, Recorder::new(4))
+            .tolerated_failures(Some(2))
+            .build()
+            .err();
+        assert_eq!(err, Some(ConfigError::TooFewNodes));
+    }
+
+    #[test]
+    fn repeated_view_changes_within_the_cooldown_are_coalesced() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 7, |config| {
+            config.clock = Box::new(clock.clone());
+            config.view_change_cooldown = Duration::from_secs(1);
+        });
+        let started = |node: &Paxos<Recorder>| node.nodes.sent.iter()
+            .filter(|(_, msg)| match msg {
+                Message::ViewChange { .. } => true,
+                _ => false,
+            })
+            .count();
+
+        // a peer sets off our view change to view 2, and more join in while it's forced again in
+        // between (to the same view, and then to an earlier one, which would forget view 2)
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 2 });
+        for (server_id, forced) in &[(2, 2), (3, 1)] {
+            node.execute(AdminCommand::ForceViewChange(*forced)).unwrap();
+            receive(&mut node, Message::ViewChange { server_id: *server_id, attempted: 2 });
+        }
+        assert_eq!(node.proposer.last_attempted_view, 2);
+        let voters: BTreeSet<_> = node.proposer.votes[&2].iter().cloned().collect();
+        assert_eq!(voters, [0, 2, 3].iter().cloned().collect());
+        assert_eq!(started(&node), 1);
+
+        // once the cooldown is up, forcing it starts it over
+        clock.advance(Duration::from_secs(1));
+        node.execute(AdminCommand::ForceViewChange(2)).unwrap();
+        assert_eq!(started(&node), 2);
+    }
+
+    #[test]
+    fn lone_node_installs_the_next_view_when_it_times_out() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 1, |config| config.clock = Box::new(clock.clone()));
+        clock.advance(Duration::from_secs(3));
+        poll_until_pending(&mut node);
+        assert_eq!(node.acceptor.current_view, 1);
+        assert_eq!(node.current_leader(), 0);
+        // leadership came all the way back around to node 0, which is all the test case asks for
+        assert!(node.done);
+    }
+
+    #[tokio::test]
+    async fn heavy_node_and_any_other_are_a_weighted_majority() {
+        // the total weight is five, so a quorum needs a weight of three
+        let weighted = |pid| paxos_with(pid, 3, |config| config.nodes.weights = vec![3, 1, 1]);
+
+        // the light nodes are a majority of the nodes, but not of the weight
+        let mut node = weighted(1);
+        node.start_view_change(1).unwrap();
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 0);
+
+        for pid in &[1, 2] {
+            let mut node = weighted(*pid);
+            node.start_view_change(1).unwrap();
+            receive(&mut node, Message::ViewChange { server_id: 0, attempted: 1 });
+            assert_eq!(node.acceptor.current_view, 1, "node {}", pid);
+        }
+    }
+
+    #[tokio::test]
+    async fn lazy_proof_leaves_installed_views_to_the_timer() {
+        for eager_proof in &[true, false] {
+            let mut node = paxos_with(0, 3, |config| config.eager_proof = *eager_proof);
+            node.proposer.last_attempted_view = 1;
+            receive(&mut node, Message::VCProof { server_id: 1, installed: 1, proposer: 1 });
+            assert_eq!(node.acceptor.current_view, 1);
+            let proof = (None, Message::VCProof { server_id: 0, installed: 1, proposer: 1 });
+            assert_eq!(node.nodes.sent.contains(&proof), *eager_proof);
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_quorum_of_four_needs_more_than_a_majority_of_five() {
+        let mut node = paxos_with(0, 5, |config| config.quorum_size = Some(4));
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 3, attempted: 1 });
+        // our own vote and those of 2 and 3 would be a majority, but not a quorum of four
+        assert_eq!(node.acceptor.current_view, 0);
+        receive(&mut node, Message::ViewChange { server_id: 4, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+    }
+
+    #[tokio::test]
+    async fn restarted_node_resumes_from_its_durable_views() {
+        let path = env::temp_dir().join(format!("paxos-state-{}-resume", process::id()));
+        DurableViews { current_view: 3, last_attempted_view: 5 }.store(&path).unwrap();
+        let mut node = paxos_with(0, 3, |config| config.state_path = Some(path.clone()));
+        assert_eq!(node.acceptor.current_view, 3);
+        assert_eq!(node.proposer.last_attempted_view, 5);
+
+        receive(&mut node, Message::VCProof { server_id: 1, installed: 5, proposer: 2 });
+        let views = DurableViews::load(&path).unwrap();
+        assert_eq!(views, DurableViews { current_view: 5, last_attempted_view: 5 });
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn nodes_sharing_a_seed_spread_their_first_timeouts_apart() {
+        let clock = MockClock::new();
+        let mut nodes: Vec<_> = (0..2).map(|pid| {
+            paxos_with(pid, 3, |config| {
+                config.vc_proof_timer_length = 10;
+                config.initial_spread_ms = 3000;
+                config.seed = Some(7);
+                config.clock = Box::new(clock.clone());
+            })
+        }).collect();
+
+        // steps the shared clock along until each node's first progress timer fires
+        let mut deadlines = [None; 2];
+        for step in 1..=60 {
+            clock.advance(Duration::from_millis(100));
+            for (deadline, node) in deadlines.iter_mut().zip(&mut nodes) {
+                poll_until_pending(node);
+                if deadline.is_none() && node.proposer.last_attempted_view > 0 {
+                    *deadline = Some(step * 100);
+                }
+            }
+        }
+        // with a 3 second timer, the seed puts node 0 in the second slot and node 1 in the third
+        assert_eq!(deadlines, [Some(4000), Some(5000)]);
+        assert_eq!(initial_offset(2, 3, 7, 3000), Duration::from_millis(0));
+        assert_eq!(initial_offset(2, 3, 7, 0), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn seeded_jitter_is_repeatable_and_bounded() {
+        // draws the first few jitters of the node with the given pid, as `Paxos::new` seeds them
+        let jitters = |pid: u64| {
+            let mut rng = StdRng::seed_from_u64(7u64.wrapping_add(pid));
+            (0..8).map(|_| jitter(&mut rng, 1000)).collect::<Vec<_>>()
+        };
+        assert_eq!(jitters(0), jitters(0));
+        assert_ne!(jitters(0), jitters(1));
+        assert!(jitters(0).iter().all(|jitter| *jitter < Duration::from_millis(1000)));
+        assert_eq!(jitter(&mut StdRng::seed_from_u64(7), 0), Duration::from_millis(0));
+    }
+
+    #[tokio::test]
+    async fn failing_view_changes_back_off_until_an_install() {
+        let mut node = paxos_with(0, 3, |config| {
+            config.progress_backoff_multiplier = 2;
+            config.progress_timer_cap = 20;
+        });
+        // the lengths of the progress timer after each of its expiries, up to the cap
+        let lengths: Vec<_> = (0..5)
+            .map(|_| {
+                node.back_off();
+                node.backoff_length().as_secs()
+            })
+            .collect();
+        assert_eq!(lengths, vec![6, 12, 20, 20, 20]);
+
+        node.proposer.last_attempted_view = 4;
+        receive(&mut node, Message::VCProof { server_id: 1, installed: 4, proposer: 1 });
+        assert_eq!(node.acceptor.current_view, 4);
+        assert_eq!(node.backoff_length(), Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn getters_follow_the_node_through_two_view_changes() {
+        let state = |node: &Paxos<Recorder>| {
+            (node.current_view(), node.last_attempted_view(), node.quorum_progress())
+        };
+        let mut node = paxos(0, 5);
+        assert_eq!(state(&node), (0, 0, 0));
+
+        // our own view change is a vote, and a second one arrives, still one short of a quorum
+        node.start_view_change(1).unwrap();
+        receive(&mut node, Message::ViewChange { server_id: 3, attempted: 1 });
+        assert_eq!(state(&node), (0, 1, 2));
+        receive(&mut node, Message::ViewChange { server_id: 4, attempted: 1 });
+        assert_eq!(state(&node), (1, 1, 3));
+        assert_eq!(node.current_leader(), 1);
+
+        node.start_view_change(2).unwrap();
+        assert_eq!(state(&node), (1, 2, 1));
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 2 });
+        assert_eq!(state(&node), (1, 2, 2));
+    }
+
+    #[tokio::test]
+    async fn duplicate_proofs_install_and_are_proven_once() {
+        let mut node = paxos(0, 3);
+        node.proposer.last_attempted_view = 2;
+        let proof = Message::VCProof { server_id: 1, installed: 2, proposer: 2 };
+        receive(&mut node, proof);
+        receive(&mut node, proof);
+        assert_eq!(node.acceptor.current_view, 2);
+        assert_eq!(node.metrics().views_installed.load(Ordering::Relaxed), 1);
+
+        // nor does proving the view again right away send anything more
+        node.send_proof().unwrap();
+        let proof = Message::VCProof { server_id: 0, installed: 2, proposer: 2 };
+        assert_eq!(node.nodes.sent, vec![(None, proof)]);
+    }
+
+    #[tokio::test]
+    async fn five_recorded_nodes_change_views_together() {
+        let mut nodes: Vec<_> = (0..5).map(|pid| paxos(pid, 5)).collect();
+        for node in &mut nodes {
+            node.start_view_change(1).unwrap();
+        }
+
+        // every node multicasts its view change
+        let multicasts: Vec<_> = nodes.iter_mut().flat_map(|node| node.nodes.sent.drain(..))
+            .collect();
+        let expected: Vec<_> = (0..5)
+            .map(|server_id| (None, Message::ViewChange { server_id, attempted: 1 }))
+            .collect();
+        assert_eq!(multicasts, expected);
+
+        for (sender, (_, msg)) in multicasts.into_iter().enumerate() {
+            for (pid, node) in nodes.iter_mut().enumerate() {
+                if pid != sender {
+                    receive(node, msg);
+                }
+            }
+        }
+        for node in &mut nodes {
+            assert_eq!(node.acceptor.current_view, 1);
+            let proof = Message::VCProof { server_id: node.pid, installed: 1, proposer: 1 };
+            assert_eq!(node.nodes.sent, vec![(None, proof)]);
+        }
+    }
+
+    #[test]
+    fn progress_timer_starts_a_view_change_once_the_clock_passes_it() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 3, |config| {
+            config.vc_proof_timer_length = 5;
+            config.clock = Box::new(clock.clone());
+        });
+        // retransmissions aside, nothing fires until the progress timer does
+        clock.advance(Duration::from_millis(2999));
+        poll_until_pending(&mut node);
+        assert!(node.nodes.sent.is_empty());
+        assert_eq!(node.proposer.last_attempted_view, 0);
+
+        clock.advance(Duration::from_millis(1));
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let fired = Pin::new(&mut node).poll_next(&mut ctx);
+        assert!(match fired { Poll::Ready(Some(Ok(()))) => true, _ => false });
+        assert_eq!(node.proposer.last_attempted_view, 1);
+        let view_change = Message::ViewChange { server_id: 0, attempted: 1 };
+        assert_eq!(node.nodes.sent, vec![(None, view_change)]);
+    }
+
+    #[test]
+    fn fresh_node_queries_its_peers_on_its_first_poll() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(1, 3, |config| {
+            config.catch_up = true;
+            config.clock = Box::new(clock.clone());
+        });
+        assert!(node.nodes.sent.is_empty());
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let polled = Pin::new(&mut node).poll_next(&mut ctx);
+        assert!(match polled { Poll::Ready(Some(Ok(()))) => true, _ => false });
+        assert_eq!(node.nodes.sent, vec![(None, Message::ViewQuery { server_id: 1 })]);
+
+        // and only on its first
+        poll_until_pending(&mut node);
+        assert_eq!(node.nodes.sent.len(), 1);
+    }
+
+    #[test]
+    fn install_answering_a_query_replies_to_the_answering_node_only() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(1, 3, |config| {
+            config.catch_up = true;
+            config.clock = Box::new(clock.clone());
+        });
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let _ = Pin::new(&mut node).poll_next(&mut ctx);
+        node.nodes.sent.clear();
+
+        // the answer to the query installs the view, which is confirmed back to its sender alone
+        node.proposer.last_attempted_view = 1;
+        receive(&mut node, Message::VCProof { server_id: 2, installed: 1, proposer: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+        let proof = Message::VCProof { server_id: 1, installed: 1, proposer: 1 };
+        assert_eq!(node.nodes.sent, vec![(Some(2), proof)]);
+
+        // while later installs by proof go out to everyone as before
+        node.nodes.sent.clear();
+        node.proposer.last_attempted_view = 2;
+        receive(&mut node, Message::VCProof { server_id: 0, installed: 2, proposer: 2 });
+        let proof = Message::VCProof { server_id: 1, installed: 2, proposer: 2 };
+        assert_eq!(node.nodes.sent, vec![(None, proof)]);
+    }
+
+    #[test]
+    fn progress_timer_stops_at_the_maximum_view() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 3, |config| {
+            config.vc_proof_timer_length = 5;
+            config.clock = Box::new(clock.clone());
+            config.max_view = Some(3);
+        });
+        // time out over and over again, with nobody ever answering
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(3));
+            poll_until_pending(&mut node);
+        }
+        let attempted: Vec<_> = node.nodes.sent.iter()
+            .filter_map(|(_, msg)| match msg {
+                Message::ViewChange { attempted, .. } => Some(*attempted),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(attempted, vec![1, 2, 3]);
+        assert_eq!(node.proposer.last_attempted_view, 3);
+    }
+
+    #[test]
+    fn timeout_skips_the_view_of_a_leader_suspected_dead() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(2, 3, |config| {
+            config.clock = Box::new(clock.clone());
+            config.view_strategy = Box::new(SkipSuspected);
+        });
+        node.suspected_dead.insert(1);
+        clock.advance(Duration::from_secs(3));
+        poll_until_pending(&mut node);
+        let attempted: Vec<_> = node.nodes.sent.iter()
+            .filter_map(|(_, msg)| match msg {
+                Message::ViewChange { attempted, .. } => Some(*attempted),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(attempted, vec![2]);
+        // the leader of view 0 never kept us from timing out either, so it's suspected too
+        assert_eq!(node.suspected_dead().iter().collect::<Vec<_>>(), vec![&0, &1]);
+
+        // until we hear from it again
+        receive(&mut node, Message::ViewChange { server_id: 0, attempted: 2 });
+        assert_eq!(node.suspected_dead().iter().collect::<Vec<_>>(), vec![&1]);
+    }
+
+    #[test]
+    fn only_the_leader_sends_proofs_on_the_timer_in_leader_only_mode() {
+        for (pid, sends_proof) in &[(0, true), (1, false)] {
+            let clock = MockClock::new();
+            let mut node = paxos_with(*pid, 3, |config| {
+                config.leader_only_proofs = true;
+                config.clock = Box::new(clock.clone());
+            });
+            clock.advance(Duration::from_secs(1));
+            poll_until_pending(&mut node);
+            let proof = Message::VCProof { server_id: *pid as u32, installed: 0, proposer: 0 };
+            let proof = (None, proof);
+            assert_eq!(node.nodes.sent.contains(&proof), *sends_proof, "node {}", pid);
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_is_answered_with_a_pong_that_measures_the_round_trip_time() {
+        let mut node = paxos(1, 3);
+        receive(&mut node, Message::Ping { server_id: 0, nonce: 7 });
+        assert_eq!(node.nodes.sent, vec![(Some(0), Message::Pong { server_id: 1, nonce: 7 })]);
+
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 3, |config| config.clock = Box::new(clock.clone()));
+        node.ping(2).unwrap();
+        let nonce = match node.nodes.sent.as_slice() {
+            [(Some(2), Message::Ping { server_id: 0, nonce })] => *nonce,
+            sent => panic!("expected a single ping to node 2, sent {:?}", sent),
+        };
+        clock.advance(Duration::from_millis(40));
+        receive(&mut node, Message::Pong { server_id: 2, nonce });
+        assert_eq!(node.rtt(2), Some(Duration::from_millis(40)));
+        assert_eq!(node.rtt(1), None);
+    }
+
+    #[test]
+    fn heartbeat_from_the_leader_resets_the_progress_timer() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(1, 3, |config| {
+            config.vc_proof_timer_length = 10;
+            config.clock = Box::new(clock.clone());
+        });
+        clock.advance(Duration::from_secs(2));
+        receive(&mut node, Message::Heartbeat { server_id: 0, view: 0 });
+        // a heartbeat from anyone but the leader doesn't count
+        receive(&mut node, Message::Heartbeat { server_id: 2, view: 0 });
+        // so the progress timer would have fired a second later, but now has another three to go
+        clock.advance(Duration::from_millis(2999));
+        poll_until_pending(&mut node);
+        assert_eq!(node.proposer.last_attempted_view, 0);
+        assert!(node.nodes.sent.is_empty());
+
+        clock.advance(Duration::from_millis(1));
+        poll_until_pending(&mut node);
+        assert_eq!(node.proposer.last_attempted_view, 1);
+    }
+
+    #[test]
+    fn installing_a_view_by_proof_resets_the_progress_timer() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(1, 3, |config| {
+            config.vc_proof_timer_length = 10;
+            config.clock = Box::new(clock.clone());
+        });
+        node.proposer.last_attempted_view = 1;
+        clock.advance(Duration::from_secs(2));
+        receive(&mut node, Message::VCProof { server_id: 0, installed: 1, proposer: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+
+        // the timer would have fired a second later, but now has another three to go
+        node.nodes.sent.clear();
+        clock.advance(Duration::from_millis(2999));
+        poll_until_pending(&mut node);
+        assert_eq!(node.proposer.last_attempted_view, 1);
+        assert!(node.nodes.sent.is_empty());
+
+        clock.advance(Duration::from_millis(1));
+        poll_until_pending(&mut node);
+        assert_eq!(node.proposer.last_attempted_view, 2);
+    }
+
+    #[tokio::test]
+    async fn view_query_is_answered_with_the_installed_view() {
+        let mut node = paxos(0, 3);
+        node.acceptor.current_view = 4;
+        node.proposer.last_attempted_view = 4;
+        receive(&mut node, Message::ViewQuery { server_id: 2 });
+        assert_eq!(node.nodes.sent, vec![
+            (Some(2), Message::VCProof { server_id: 0, installed: 4, proposer: 1 }),
+        ]);
+
+        // a query from a node we don't know about goes unanswered
+        node.nodes.sent.clear();
+        receive(&mut node, Message::ViewQuery { server_id: 3 });
+        assert!(node.nodes.sent.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn admin_view_command_answers_with_the_installed_view() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::{UnixListener, UnixStream};
+        use tokio::sync::mpsc;
+
+        let mut node = paxos(0, 3);
+        node.acceptor.current_view = 2;
+        node.proposer.last_attempted_view = 2;
+        let path = env::temp_dir().join(format!("paxos-admin-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let (tx, rx) = mpsc::channel(1);
+        node.serve_admin(rx);
+        tokio::spawn(async move { crate::admin::serve(listener, tx).await.unwrap() });
+
+        let driver = async {
+            while let Some(res) = node.next().await {
+                res.unwrap();
+            }
+        };
+        let client = async {
+            let mut conn = BufReader::new(UnixStream::connect(&path).await.unwrap());
+            conn.get_mut().write_all(b"view\n").await.unwrap();
+            let mut response = String::new();
+            conn.read_line(&mut response).await.unwrap();
+            response
+        };
+        let response = match future::select(Box::pin(driver), Box::pin(client)).await {
+            Either::Left(..) => panic!("node finished before answering"),
+            Either::Right((response, _)) => response,
+        };
+        assert_eq!(response, "current view 2, last attempted view 2\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn last_view_can_still_be_changed_to_and_led() {
+        let last = u32::max_value();
+        let mut node = paxos(0, 4);
+        node.acceptor.current_view = last - 1;
+        node.proposer.last_attempted_view = last - 1;
+        node.start_view_change(last).unwrap();
+
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: last });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: last });
+        assert_eq!(node.acceptor.current_view, last);
+        assert_eq!(node.current_leader(), last % 4);
+    }
+
+    #[test]
+    fn progress_timeout_past_the_last_view_fails_cleanly() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 4, |config| config.clock = Box::new(clock.clone()));
+        node.acceptor.current_view = u32::max_value();
+        node.proposer.last_attempted_view = u32::max_value();
+
+        clock.advance(Duration::from_secs(3));
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let err = loop {
+            match Pin::new(&mut node).poll_next(&mut ctx) {
+                Poll::Ready(Some(Ok(()))) => continue,
+                Poll::Ready(Some(Err(e))) => break e,
+                _ => panic!("progress timer never failed"),
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(err.to_string().contains("view counter exhausted"), "{}", err);
+        assert_eq!(node.proposer.last_attempted_view, u32::max_value());
+    }
+
+    #[tokio::test]
+    async fn stale_view_change_is_nacked_with_our_view() {
+        let mut node = paxos(0, 3);
+        node.acceptor.current_view = 3;
+        node.proposer.last_attempted_view = 3;
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 2 });
+        assert_eq!(node.nodes.sent, vec![
+            (Some(1), Message::Nack { server_id: 0, current_view: 3 }),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn nack_fast_forwards_a_node_that_fell_behind() {
+        let mut node = paxos(1, 3);
+        node.proposer.last_attempted_view = 2;
+        receive(&mut node, Message::Nack { server_id: 0, current_view: 4 });
+        assert_eq!(node.acceptor.current_view, 4);
+        assert_eq!(node.proposer.last_attempted_view, 4);
+
+        // but a nack never takes a node back to an older view
+        receive(&mut node, Message::Nack { server_id: 2, current_view: 3 });
+        assert_eq!(node.acceptor.current_view, 4);
+    }
+
+    #[tokio::test]
+    async fn nack_from_an_unknown_node_is_rejected() {
+        let mut node = paxos(1, 3);
+        node.proposer.last_attempted_view = 2;
+        receive(&mut node, Message::Nack { server_id: 3, current_view: u32::MAX });
+        assert_eq!(node.acceptor.current_view, 0);
+        assert_eq!(node.proposer.last_attempted_view, 2);
+        assert_eq!(node.metrics().rejected_messages.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn reconfiguring_three_nodes_to_five_raises_the_quorum() {
+        let mut node = paxos(0, 3);
+        node.reconfigure(5).unwrap();
+        assert_eq!(node.nodes.sent, vec![
+            (None, Message::Reconfig { server_id: 0, epoch: 1, members: 5 }),
+        ]);
+        // two of the three original members are a majority for the reconfiguration
+        receive(&mut node, Message::Reconfig { server_id: 1, epoch: 1, members: 5 });
+        assert_eq!(node.epoch(), 1);
+        assert_eq!(node.nodes.len(), 5);
+
+        // the first view change only gets us to start our own, and two votes are no longer a
+        // quorum once there are five members
+        receive(&mut node, Message::ViewChange { server_id: 4, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 3, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 0);
+        // but three are, including one from a new member
+        receive(&mut node, Message::ViewChange { server_id: 4, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+    }
+
+    #[tokio::test]
+    async fn reconfiguration_to_no_members_or_unknown_nodes_is_rejected() {
+        let mut node = paxos(0, 3);
+        for members in &[0, 7] {
+            let err = node.reconfigure(*members).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+            // and when another member votes for it, we survive without ever joining the vote
+            for server_id in 1..3 {
+                receive(&mut node, Message::Reconfig { server_id, epoch: 1, members: *members });
+            }
+        }
+        assert!(node.nodes.sent.is_empty());
+        assert_eq!(node.epoch(), 0);
+        assert_eq!(node.nodes.len(), 3);
+        assert_eq!(node.metrics().rejected_messages.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn reconfiguration_takes_a_weighted_quorum() {
+        // node 0 carries three of the five votes, so two lighter nodes are no quorum without it
+        let mut node = paxos_with(1, 3, |config| config.nodes.weights = vec![3, 1, 1]);
+        node.reconfigure(2).unwrap();
+        receive(&mut node, Message::Reconfig { server_id: 2, epoch: 1, members: 2 });
+        assert_eq!(node.epoch(), 0);
+
+        receive(&mut node, Message::Reconfig { server_id: 0, epoch: 1, members: 2 });
+        assert_eq!(node.epoch(), 1);
+        assert_eq!(node.nodes.len(), 2);
+    }
+
+    #[test]
+    fn proposer_records_the_latency_until_a_quorum_confirms_the_install() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(0, 5, |config| {
+            config.eager_proof = false;
+            config.clock = Box::new(clock.clone());
+        });
+        node.start_view_change(1).unwrap();
+        clock.advance(Duration::from_millis(30));
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+        // without eager proofs, the install is confirmed to just the nodes that proposed it
+        let mut confirmed: Vec<_> = node.nodes.sent.iter()
+            .filter_map(|(target, msg)| match msg {
+                Message::VCProof { installed: 1, .. } => *target,
+                _ => None,
+            })
+            .collect();
+        confirmed.sort();
+        assert_eq!(confirmed, vec![1, 2]);
+
+        // our own install and one other confirmation are short of a quorum of three
+        clock.advance(Duration::from_millis(20));
+        receive(&mut node, Message::VCProof { server_id: 3, installed: 1, proposer: 1 });
+        assert_eq!(node.install_latency(), None);
+        // confirmations of any other view don't count towards it either
+        receive(&mut node, Message::VCProof { server_id: 4, installed: 2, proposer: 2 });
+        assert_eq!(node.install_latency(), None);
+
+        clock.advance(Duration::from_millis(10));
+        receive(&mut node, Message::VCProof { server_id: 4, installed: 1, proposer: 1 });
+        assert_eq!(node.install_latency(), Some(Duration::from_millis(60)));
+        assert_eq!(node.metrics().install_latencies(), 1);
+        let bucket = "paxos_install_latency_seconds_bucket{le=\"0.1\"} 1";
+        assert!(node.metrics().render().lines().any(|line| line == bucket));
+    }
+
+    #[tokio::test]
+    async fn custom_leader_function_picks_the_leader_of_every_view() {
+        let inverted: LeaderFn = Arc::new(|view, num_nodes| {
+            (num_nodes - 1 - view as usize % num_nodes) as u32
+        });
+        let mut node = paxos_with(0, 3, |config| config.leader_fn = Some(inverted));
+        let leaders: Vec<_> = (0..4)
+            .map(|view| {
+                node.acceptor.current_view = view;
+                node.current_leader()
+            })
+            .collect();
+        assert_eq!(leaders, vec![2, 1, 0, 2]);
+    }
+
+    #[tokio::test]
+    async fn proof_sent_after_installing_names_the_leader_as_its_proposer() {
+        // with the leaders inverted, the leader of view 1 is neither us nor the default of 1
+        let inverted: LeaderFn = Arc::new(|view, num_nodes| {
+            (num_nodes - 1 - view as usize % num_nodes) as u32
+        });
+        let mut node = paxos_with(1, 3, |config| {
+            config.eager_proof = true;
+            config.leader_fn = Some(inverted);
+        });
+        receive(&mut node, Message::ViewChange { server_id: 0, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+        assert_eq!(node.current_leader(), 2);
+        let proof = Message::VCProof { server_id: 1, installed: 1, proposer: 2 };
+        assert!(node.nodes.sent.contains(&(None, proof)), "sent {:?}", node.nodes.sent);
+    }
+
+    // the check is a debug assertion, which release builds leave out
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "cannot compute a leader without any nodes")]
+    fn leader_of_no_nodes_fails_loudly() {
+        paxos_with(0, 0, |config| config.clock = Box::new(MockClock::new())).current_leader();
+    }
+
+    #[tokio::test]
+    async fn proof_of_a_view_far_ahead_fast_forwards_a_lagging_node() {
+        let mut node = paxos(0, 5);
+        node.start_view_change(1).unwrap();
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 0);
+
+        receive(&mut node, Message::VCProof { server_id: 3, installed: 7, proposer: 2 });
+        assert_eq!(node.acceptor.current_view, 7);
+        assert_eq!(node.proposer.last_attempted_view, 7);
+        // the votes for the view we were stuck proposing are of no use anymore
+        assert_eq!(node.proposer.voters(1), None);
+
+        // while proofs of the views behind it change nothing
+        receive(&mut node, Message::VCProof { server_id: 4, installed: 3, proposer: 3 });
+        assert_eq!(node.acceptor.current_view, 7);
+        assert_eq!(node.proposer.last_attempted_view, 7);
+    }
+
+    #[tokio::test]
+    async fn proof_from_an_unknown_node_changes_nothing() {
+        let mut node = paxos(0, 3);
+        node.start_view_change(1).unwrap();
+        receive(&mut node, Message::VCProof { server_id: 3, installed: u32::MAX, proposer: 0 });
+        assert_eq!(node.acceptor.current_view, 0);
+        assert_eq!(node.proposer.last_attempted_view, 1);
+        assert_eq!(node.metrics().rejected_messages.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn exit_hook_ends_the_stream_instead_of_the_process() {
+        let mut node = paxos_with(0, 3, |config| config.schedule = Box::new(TestCase::NormalCase));
+        node.proposer.last_attempted_view = 1;
+        receive(&mut node, Message::VCProof { server_id: 1, installed: 1, proposer: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let ended = Pin::new(&mut node).poll_next(&mut ctx);
+        assert!(match ended { Poll::Ready(None) => true, _ => false });
+        // and once finished, the node ignores whatever else arrives
+        node.nodes.sent.clear();
+        receive(&mut node, Message::ViewQuery { server_id: 1 });
+        assert!(node.nodes.sent.is_empty());
+    }
+
+    #[tokio::test]
+    async fn serving_node_keeps_running_past_the_views_that_finish_tests() {
+        // serving runs with the schedule that never crashes or finishes
+        let mut node = paxos_with(0, 3, |config| config.schedule = Box::new(NeverFail));
+        node.proposer.last_attempted_view = 1;
+        receive(&mut node, Message::VCProof { server_id: 1, installed: 1, proposer: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let polled = Pin::new(&mut node).poll_next(&mut ctx);
+        assert!(match polled { Poll::Ready(None) => false, _ => true });
+        assert!(!node.done);
+        node.nodes.sent.clear();
+        receive(&mut node, Message::ViewQuery { server_id: 1 });
+        let proof = Message::VCProof { server_id: 0, installed: 1, proposer: 1 };
+        assert_eq!(node.nodes.sent, vec![(Some(1), proof)]);
+    }
+
+    #[tokio::test]
+    async fn installing_a_view_notifies_the_embedding_application() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let mut node = paxos_with(0, 3, |config| config.view_installed = Some(tx));
+        node.proposer.last_attempted_view = 1;
+        receive(&mut node, Message::VCProof { server_id: 1, installed: 1, proposer: 1 });
+        assert_eq!(rx.recv().await, Some(ViewInstalled { view: 1, leader: 1 }));
+
+        // and the protocol carries on without notifications once the receiver is gone
+        drop(rx);
+        node.proposer.last_attempted_view = 2;
+        receive(&mut node, Message::VCProof { server_id: 2, installed: 2, proposer: 2 });
+        assert_eq!(node.acceptor.current_view, 2);
+        assert!(node.view_installed.is_none());
+    }
+
+    #[tokio::test]
+    async fn leader_steps_down_by_changing_to_the_next_view() {
+        let mut leader = paxos(0, 3);
+        leader.step_down().unwrap();
+        let view_change = Message::ViewChange { server_id: 0, attempted: 1 };
+        assert_eq!(leader.nodes.sent, vec![(None, view_change)]);
+        assert_eq!(leader.proposer.last_attempted_view, 1);
+
+        // stepping down again while the view change is underway does nothing, as it does for
+        // nodes that aren't leading in the first place
+        leader.step_down().unwrap();
+        assert_eq!(leader.nodes.sent.len(), 1);
+        let mut follower = paxos(1, 3);
+        follower.step_down().unwrap();
+        assert!(follower.nodes.sent.is_empty());
+        assert_eq!(follower.proposer.last_attempted_view, 0);
+    }
+
+    /// A failure schedule crashing the given node once it finds a quorum for the given view.
+    struct CrashAt {
+        /// the pid of the node to crash
+        pid: u32,
+        /// the view at which the node crashes
+        view: u32,
+    }
+
+    impl FailureSchedule for CrashAt {
+        fn should_crash(&self, pid: u32, view: u32) -> bool {
+            pid == self.pid && view == self.view
+        }
+
+        fn should_exit(&self, _pid: u32, _view: u32, _leader: u32) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "crashing")]
+    async fn custom_schedule_crashes_its_node_at_its_view() {
+        let mut node =
+            paxos_with(4, 5, |config| config.schedule = Box::new(CrashAt { pid: 4, view: 2 }));
+        receive(&mut node, Message::ViewChange { server_id: 0, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+
+        receive(&mut node, Message::ViewChange { server_id: 0, attempted: 2 });
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 2 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 2 });
+    }
+
+    #[tokio::test]
+    async fn view_change_from_unknown_node_does_not_count() {
+        let mut node = paxos(0, 5);
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 9999, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        let votes: HashSet<_> = [0, 1].iter().cloned().collect();
+        assert_eq!(node.proposer.votes.get(&1), Some(&votes));
+        assert_eq!(node.acceptor.current_view, 0);
+        assert_eq!(node.metrics().rejected_messages.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn initiator_and_one_peer_are_a_quorum_of_three() {
+        let mut node = paxos(0, 3);
+        node.start_view_change(1).unwrap();
+        // were our own view change ever delivered back to us, it still wouldn't count twice
+        receive(&mut node, Message::ViewChange { server_id: 0, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 0);
+
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        assert_eq!(node.acceptor.current_view, 1);
+    }
+
+    #[tokio::test]
+    async fn view_changes_below_the_attempted_view_are_pruned() {
+        let mut node = paxos(0, 5);
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        assert_eq!(node.proposer.votes.get(&1).map(HashSet::len), Some(2));
+
+        receive(&mut node, Message::ViewChange { server_id: 3, attempted: 3 });
+        receive(&mut node, Message::ViewChange { server_id: 4, attempted: 2 });
+        let views: Vec<_> = node.proposer.votes.keys().cloned().collect();
+        assert_eq!(views, vec![3]);
+        let votes: HashSet<_> = [0, 3].iter().cloned().collect();
+        assert_eq!(node.proposer.votes.get(&3), Some(&votes));
+        assert_eq!(node.acceptor.current_view, 0);
+    }
+
+    #[tokio::test]
+    async fn driven_messages_are_counted_in_the_metrics() {
+        let mut node = paxos(0, 3);
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 0 });
+        let rendered = node.metrics().render();
+        for line in &[
+            "paxos_views_installed_total 1", "paxos_view_changes_started_total 1",
+            "paxos_proofs_sent_total 1", "paxos_stale_messages_total 1",
+        ] {
+            assert!(rendered.lines().any(|rendered| rendered == *line), "{}", line);
+        }
+    }
+
+    /// A writer into a buffer that stays readable after the writer is handed off.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn install_is_recorded_in_the_event_log() {
+        let buffer = SharedBuffer::default();
+        let events = EventLog::new(buffer.clone());
+        let mut node = paxos_with(0, 3, |config| config.events = events);
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let expected = [("view_change_started", 0), ("quorum_reached", 0), ("view_installed", 1)];
+        assert_eq!(log.lines().count(), expected.len());
+        for (line, (event, current_view)) in log.lines().zip(expected.iter()) {
+            let fields = format!(
+                "{{\"event\":\"{}\",\"pid\":0,\"current_view\":{},\"last_attempted_view\":1,\
+                 \"timestamp\":", event, current_view,
+            );
+            assert!(line.starts_with(&fields), "{}", line);
+            let timestamp = &line[fields.len()..line.len() - 1];
+            assert!(timestamp.parse::<u128>().is_ok() && line.ends_with('}'), "{}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn install_is_sent_to_the_syslog_collector() {
+        let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = collector.local_addr().unwrap();
+        let events = EventLog::disabled().syslog_to(addr, "node-0").unwrap();
+        let mut node = paxos_with(0, 3, |config| config.events = events);
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 2, attempted: 1 });
+
+        // the view change and quorum come first, each in a datagram of its own
+        let mut buf = [0; 1024];
+        let lines: Vec<_> = (0..3).map(|_| {
+            let len = collector.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        }).collect();
+        let line = &lines[2];
+        // local0.info, version 1
+        assert!(line.starts_with("<134>1 "), "{}", line);
+        let fields: Vec<_> = line.splitn(8, ' ').collect();
+        assert!(fields[1].ends_with('Z') && fields[1].contains('T'), "{}", line);
+        assert_eq!(&fields[2..4], &["node-0", "prj2"]);
+        assert_eq!(fields[5], "view_installed");
+        assert_eq!(
+            fields[6..].join(" "),
+            "[paxos@32473 pid=\"0\" current_view=\"1\" last_attempted_view=\"1\"] view_installed",
+        );
+    }
+
+    #[test]
+    fn new_leader_is_output_as_json_in_json_mode() {
+        let buffer = SharedBuffer::default();
+        let output = buffer.clone();
+        let mut node = paxos_with(2, 3, |config| {
+            config.output_format = OutputFormat::Json;
+            config.output = Box::new(output);
+        });
+        receive(&mut node, Message::ViewChange { server_id: 0, attempted: 1 });
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 1, "{}", output);
+        let fields: Vec<_> = lines[0].trim_start_matches('{').trim_end_matches('}')
+            .split(',')
+            .map(|field| {
+                let mut parts = field.splitn(2, ':');
+                (parts.next().unwrap(), parts.next().unwrap().parse::<u32>().unwrap())
+            })
+            .collect();
+        assert_eq!(fields, vec![("\"pid\"", 2), ("\"leader\"", 1), ("\"view\"", 1)]);
+    }
+
+    #[test]
+    fn livelock_is_reported_once_enough_view_changes_fail_in_a_row() {
+        let clock = MockClock::new();
+        let buffer = SharedBuffer::default();
+        let events = EventLog::new(buffer.clone());
+        let mut node = paxos_with(0, 5, |config| {
+            config.vc_proof_timer_length = 5;
+            config.clock = Box::new(clock.clone());
+            config.events = events;
+            config.livelock_threshold = Some(3);
+        });
+        let events = || {
+            let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            log.lines().filter(|line| line.contains("\"event\":\"livelock_suspected\""))
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        // the first expiry only starts a view change, which then fails on each one after it
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(3));
+            poll_until_pending(&mut node);
+        }
+        assert!(events().is_empty());
+
+        clock.advance(Duration::from_secs(3));
+        poll_until_pending(&mut node);
+        let reported = events();
+        assert_eq!(reported.len(), 1);
+        assert!(reported[0].contains("\"current_view\":0,\"last_attempted_view\":3"));
+        assert_eq!(node.proposer.last_attempted_view, 4);
+    }
+
+    /// The name and fields of a span or event captured by a `TraceCapture`.
+    type Traced = (String, HashMap<String, String>);
+
+    /// A subscriber capturing every span and event, along with their fields.
+    #[derive(Clone, Default)]
+    struct TraceCapture {
+        spans: Arc<std::sync::Mutex<Vec<Traced>>>,
+        events: Arc<std::sync::Mutex<Vec<Traced>>>,
+    }
+
+    /// A visitor collecting the fields it visits, formatted for debugging.
+    #[derive(Default)]
+    struct Fields(HashMap<String, String>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name().to_owned(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for TraceCapture {
+        fn enabled(&self, _: &tracing::Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((span.metadata().name().to_owned(), fields.0));
+            // span ids must be non-zero
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &tracing::Event) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.events.lock().unwrap().push((event.metadata().name().to_owned(), fields.0));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn each_poll_is_traced_in_a_span_of_the_node_and_its_view() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(2, 3, |config| {
+            config.vc_proof_timer_length = 5;
+            config.clock = Box::new(clock.clone());
+        });
+        let capture = TraceCapture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            clock.advance(Duration::from_secs(3));
+            poll_until_pending(&mut node);
+        });
+
+        // the timer fires in a poll at view 0, which then ends up pending at view 0 as well
+        let spans = capture.spans.lock().unwrap();
+        assert!(spans.len() >= 2);
+        for (name, fields) in spans.iter() {
+            assert_eq!(name, "poll");
+            assert_eq!(fields["pid"], "2");
+            assert_eq!(fields["current_view"], "0");
+        }
+        // string fields are recorded by their debug formatting, quotes and all
+        let events = capture.events.lock().unwrap();
+        assert!(events.iter().any(|(_, fields)| {
+            fields.get("timer").map(String::as_str) == Some("\"progress\"")
+        }));
+        assert!(events.iter().any(|(_, fields)| {
+            fields.get("new_view").map(String::as_str) == Some("1")
+        }));
+    }
+
+    #[test]
+    fn each_vote_received_is_traced_with_every_voter_and_the_quorum_needed() {
+        let mut node = paxos(0, 5);
+        receive(&mut node, Message::ViewChange { server_id: 1, attempted: 1 });
+        let capture = TraceCapture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            receive(&mut node, Message::ViewChange { server_id: 3, attempted: 1 });
+        });
+
+        // we voted when we started the view change, but the vote that started it isn't counted
+        let events = capture.events.lock().unwrap();
+        let votes: Vec<_> = events.iter()
+            .filter(|(_, fields)| fields.contains_key("voters"))
+            .map(|(_, fields)| fields)
+            .collect();
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0]["view"], "1");
+        assert_eq!(votes[0]["voters"], "{0, 3}");
+        assert_eq!(votes[0]["received"], "2");
+        assert_eq!(votes[0]["needed"], "3");
+        assert_eq!(votes[0]["message"], "votes for view 1: {0, 3}, need 3");
+    }
+
+    #[test]
+    fn timers_firing_together_are_all_serviced_in_turn() {
+        let clock = MockClock::new();
+        let mut node = paxos_with(1, 3, |config| {
+            config.vc_proof_timer_length = 3;
+            config.clock = Box::new(clock.clone());
+        });
+        clock.advance(Duration::from_secs(3));
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let mut poll = |node: &mut Paxos<Recorder>| {
+            match Pin::new(node).poll_next(&mut ctx) {
+                Poll::Ready(Some(Ok(()))) => (),
+                _ => panic!("expected a timer to be serviced"),
+            }
+        };
+
+        // the progress timer has the first turn, and the vc proof timer the next despite both
+        // having fired back when the first poll serviced the progress timer
+        poll(&mut node);
+        let view_change = Message::ViewChange { server_id: 1, attempted: 1 };
+        assert_eq!(node.nodes.sent, vec![(None, view_change)]);
+        poll(&mut node);
+        let proof = Message::VCProof { server_id: 1, installed: 0, proposer: 0 };
+        assert_eq!(node.nodes.sent[1..], [(None, proof)]);
     }
 }
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::msg::Message;
+use crate::net::Transport;
+
+/// The proposing side of a node: the view it's trying to install, and the votes (i.e. view
+/// changes) it has gathered towards a quorum installing it.
+#[derive(Debug)]
+pub(crate) struct Proposer {
+    /// the last view that we attempted to install
+    pub(crate) last_attempted_view: u32,
+    /// the servers we've received view changes from, by the view they're attempting to install,
+    /// only ever tracking views at least as large as the last attempted view
+    pub(crate) votes: HashMap<u32, HashSet<u32>>,
+    /// the total weight of view changes needed to install a view in place of a majority, if any
+    quorum_size: Option<usize>,
+}
+
+impl Proposer {
+    /// Creates a proposer that last attempted the given view, without any votes yet, needing view
+    /// changes of the given total weight to install a view, or a majority of the total weight of
+    /// the nodes if `None`.
+    pub(crate) fn new(last_attempted_view: u32, quorum_size: Option<usize>) -> Proposer {
+        Proposer { last_attempted_view, votes: HashMap::new(), quorum_size }
+    }
+
+    /// Attempts the given view, voting for it as the server with the given id (since our own
+    /// multicasts are not delivered back to us), and forgetting the votes for views we'll never
+    /// install now.
+    pub(crate) fn attempt(&mut self, view: u32, pid: u32) {
+        self.last_attempted_view = view;
+        self.prune();
+        self.vote(pid, view);
+    }
+
+    /// Records a vote from the given server for the given view.
+    pub(crate) fn vote(&mut self, server_id: u32, view: u32) {
+        self.votes.entry(view).or_default().insert(server_id);
+    }
+
+    /// Gets the servers that voted for the given view, if any did.
+    pub(crate) fn voters(&self, view: u32) -> Option<&HashSet<u32>> {
+        self.votes.get(&view)
+    }
+
+    /// Computes the votes received for the last attempted view as the total weight of the given
+    /// nodes that sent them (i.e. their count, unless the nodes are weighted).
+    pub(crate) fn progress<T: Transport>(&self, nodes: &T) -> usize {
+        self.voters(self.last_attempted_view)
+            .map_or(0, |voters| voters.iter().map(|id| nodes.weight(*id as usize) as usize).sum())
+    }
+
+    /// Computes the total weight of the view changes needed to install a view, which is a majority
+    /// of the total weight of the given nodes unless configured otherwise.
+    pub(crate) fn quorum<T: Transport>(&self, nodes: &T) -> usize {
+        self.quorum_size.unwrap_or((total_weight(nodes) / 2) + 1)
+    }
+
+    /// Determines whether or not the votes for the last attempted view make up a quorum of the
+    /// given nodes.
+    pub(crate) fn has_quorum<T: Transport>(&self, nodes: &T) -> bool {
+        self.progress(nodes) >= self.quorum(nodes)
+    }
+
+    /// Drops the votes for views below the last attempted view, since we'll never install them.
+    pub(crate) fn prune(&mut self) {
+        let last_attempted_view = self.last_attempted_view;
+        self.votes.retain(|view, _| *view >= last_attempted_view);
+    }
+
+    /// Drops the votes of servers that are not among the given number of members.
+    pub(crate) fn retain_members(&mut self, members: u32) {
+        for voters in self.votes.values_mut() {
+            voters.retain(|server_id| *server_id < members);
+        }
+    }
+}
+
+/// Sums the weights of every node in the system.
+pub(crate) fn total_weight<T: Transport>(nodes: &T) -> usize {
+    (0..nodes.len()).map(|idx| nodes.weight(idx) as usize).sum()
+}
+
+/// How an acceptor judges a view change it received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Judgement {
+    /// the view change is for a view before the last attempted one, so it can never install, and
+    /// its sender is `behind` if it's even before the installed view
+    Stale { behind: bool },
+    /// the view change is for a view past the last attempted one, which we should attempt too
+    Newer,
+    /// the view change is a vote for the last attempted view
+    Vote,
+}
+
+/// The accepting side of a node: the view it installed, and how it responds to the view changes
+/// of the others.
+#[derive(Debug)]
+pub(crate) struct Acceptor {
+    /// the id of the local node, which sends the responses
+    pid: u32,
+    /// the current view that we have installed
+    pub(crate) current_view: u32,
+}
+
+impl Acceptor {
+    /// Creates the acceptor of the node with the given id, which installed the given view.
+    pub(crate) fn new(pid: u32, current_view: u32) -> Acceptor {
+        Acceptor { pid, current_view }
+    }
+
+    /// Judges a view change attempting the given view, given the view our proposer last attempted.
+    pub(crate) fn judge(&self, attempted: u32, last_attempted_view: u32) -> Judgement {
+        if attempted < last_attempted_view {
+            Judgement::Stale { behind: attempted < self.current_view }
+        } else if attempted > last_attempted_view {
+            Judgement::Newer
+        } else {
+            Judgement::Vote
+        }
+    }
+
+    /// Builds the acknowledgement of a view change attempting the given view, for reliable mode.
+    pub(crate) fn ack(&self, attempted: u32) -> Message {
+        Message::Ack { server_id: self.pid, acking: attempted }
+    }
+
+    /// Builds the notice to a node behind us that we installed the current view, so that it can
+    /// catch up.
+    pub(crate) fn nack(&self) -> Message {
+        Message::Nack { server_id: self.pid, current_view: self.current_view }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    /// A transport of the given number of nodes, which only counts them.
+    struct Counted(usize);
+
+    impl Transport for Counted {
+        fn len(&self) -> usize {
+            self.0
+        }
+
+        fn multicast_send(&mut self, _msg: Message) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn unicast_send(&mut self, _msg: Message, _target: usize) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn proposer_installs_once_a_majority_votes_for_its_attempted_view() {
+        let nodes = Counted(5);
+        let mut proposer = Proposer::new(0, None);
+        proposer.attempt(1, 0);
+        assert_eq!(proposer.quorum(&nodes), 3);
+        assert!(!proposer.has_quorum(&nodes));
+
+        // votes for any other view don't count towards this one
+        proposer.vote(1, 2);
+        proposer.vote(2, 1);
+        assert_eq!(proposer.progress(&nodes), 2);
+        assert!(!proposer.has_quorum(&nodes));
+
+        // and a vote counts once, however many times it's received
+        proposer.vote(2, 1);
+        assert!(!proposer.has_quorum(&nodes));
+        proposer.vote(4, 1);
+        assert!(proposer.has_quorum(&nodes));
+    }
+
+    #[test]
+    fn proposer_attempting_a_later_view_forgets_the_votes_before_it() {
+        let nodes = Counted(3);
+        let mut proposer = Proposer::new(0, Some(3));
+        proposer.attempt(1, 0);
+        proposer.vote(1, 1);
+        proposer.vote(2, 3);
+        proposer.attempt(2, 0);
+
+        assert_eq!(proposer.voters(1), None);
+        assert_eq!(proposer.voters(2).map(HashSet::len), Some(1));
+        assert_eq!(proposer.voters(3).map(HashSet::len), Some(1));
+        assert_eq!(proposer.quorum(&nodes), 3);
+    }
+
+    #[test]
+    fn proposer_drops_the_votes_of_servers_no_longer_members() {
+        let nodes = Counted(3);
+        let mut proposer = Proposer::new(0, None);
+        proposer.attempt(1, 0);
+        proposer.vote(4, 1);
+        assert_eq!(proposer.progress(&nodes), 2);
+        proposer.retain_members(3);
+        assert_eq!(proposer.progress(&nodes), 1);
+    }
+
+    #[test]
+    fn acceptor_judges_view_changes_against_its_views() {
+        let acceptor = Acceptor::new(1, 2);
+        assert_eq!(acceptor.judge(1, 3), Judgement::Stale { behind: true });
+        assert_eq!(acceptor.judge(2, 3), Judgement::Stale { behind: false });
+        assert_eq!(acceptor.judge(3, 3), Judgement::Vote);
+        assert_eq!(acceptor.judge(4, 3), Judgement::Newer);
+    }
+
+    #[test]
+    fn acceptor_responses_come_from_it_and_carry_its_view() {
+        let acceptor = Acceptor::new(1, 2);
+        assert_eq!(acceptor.ack(3), Message::Ack { server_id: 1, acking: 3 });
+        assert_eq!(acceptor.nack(), Message::Nack { server_id: 1, current_view: 2 });
+    }
+}
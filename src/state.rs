@@ -0,0 +1,125 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use bytes::Buf;
+use fehler::{throw, throws};
+use log::info;
+
+/// The views of a node that are persisted so that they survive crashes and restarts.
+///
+/// They're stored as two big-endian `u32`s: the current view followed by the last attempted view.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DurableViews {
+    /// the current view that was installed
+    pub current_view: u32,
+    /// the last view that was attempted
+    pub last_attempted_view: u32,
+}
+
+impl DurableViews {
+    /// Loads the views stored in the given file, defaulting to view 0 if it's missing. Fails if
+    /// it's truncated, since starting over from view 0 could go back on views already installed.
+    #[throws(io::Error)]
+    pub fn load<P: AsRef<Path>>(path: P) -> DurableViews {
+        let mut buffer = Vec::new();
+        match File::open(path.as_ref()) {
+            Ok(mut file) => { file.read_to_end(&mut buffer)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("no state file at {}, starting from view 0", path.as_ref().display());
+                return DurableViews::default()
+            },
+            Err(e) => throw!(e),
+        }
+
+        if buffer.len() < 8 {
+            throw!(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "truncated state file at {} ({} of 8 bytes)", path.as_ref().display(), buffer.len()
+            )))
+        }
+
+        let mut buf = Cursor::new(&buffer[..]);
+        let views = DurableViews {
+            current_view: buf.get_u32_be(),
+            last_attempted_view: buf.get_u32_be(),
+        };
+        info!("recovered {:?} from {}", views, path.as_ref().display());
+        views
+    }
+
+    /// Stores the views in the given file, returning only once they've been synced to disk. They're
+    /// written to a temporary file alongside it first, which then replaces it, so that a crash
+    /// partway through leaves either the old views or the new ones, and never a torn file.
+    #[throws(io::Error)]
+    pub fn store<P: AsRef<Path>>(&self, path: P) -> () {
+        let path = path.as_ref();
+        let tmp = temporary_path(path);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.current_view.to_be_bytes())?;
+        file.write_all(&self.last_attempted_view.to_be_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        // the rename itself only survives a crash once the directory holding it is synced
+        #[cfg(unix)]
+        {
+            let dir = match path.parent() {
+                Some(dir) if dir != Path::new("") => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+    }
+}
+
+/// Gets the path of the temporary file the views are written to before replacing the given file.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map_or_else(OsString::new, |name| name.to_owned());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    /// Gets a path for a state file unique to the given test, removing any left by an earlier run.
+    fn scratch(test: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("paxos-state-{}-{}", process::id(), test));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn stored_views_are_loaded() {
+        let path = scratch("roundtrip");
+        let views = DurableViews { current_view: 3, last_attempted_view: 5 };
+        views.store(&path).unwrap();
+        assert_eq!(DurableViews::load(&path).unwrap(), views);
+
+        let later = DurableViews { current_view: 5, last_attempted_view: 5 };
+        later.store(&path).unwrap();
+        assert_eq!(DurableViews::load(&path).unwrap(), later);
+        assert!(!temporary_path(&path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_starts_from_view_zero() {
+        let path = scratch("missing");
+        assert_eq!(DurableViews::load(&path).unwrap(), DurableViews::default());
+    }
+
+    #[test]
+    fn truncated_file_fails_to_load() {
+        let path = scratch("truncated");
+        fs::write(&path, &[0, 0, 0, 3, 0]).unwrap();
+        let err = DurableViews::load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! Embeds the protocol the way another program would, through the public API of the library alone.
+
+use std::io;
+use std::net::UdpSocket;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Sink;
+
+use prj2::{
+    EventLog, Message, MessageCodec, OutputFormat, Paxos, PaxosConfig, Successor, System, TestCase,
+    TokioClock, MIN_TIMER_LENGTH,
+};
+
+#[tokio::test]
+async fn handcrafted_view_changes_install_a_view() {
+    // borrow a free port from the OS, assuming the one after it is free too
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let hosts = vec!["127.0.0.1".to_string(), "127.0.0.2".to_string(), "127.0.0.3".to_string()];
+    let system =
+        System::from_hosts(hosts, "127.0.0.1", port, MessageCodec::datagram()).await.unwrap();
+    let config = PaxosConfig {
+        pid: system.pid(),
+        nodes: system.nodes(),
+        // this test case only finishes the protocol once view 4 is installed
+        schedule: Box::new(TestCase::ThreeCrashes),
+        progress_timer_length: 3,
+        progress_backoff_multiplier: 1,
+        progress_timer_cap: 60,
+        vc_proof_timer_length: 1,
+        max_retransmits: None,
+        state_path: None,
+        progress_jitter_ms: 0,
+        initial_spread_ms: 0,
+        seed: None,
+        metrics: Arc::default(),
+        events: EventLog::disabled(),
+        clock: Box::new(TokioClock),
+        quorum_size: None,
+        eager_proof: true,
+        leader_only_proofs: false,
+        ping_interval: None,
+        max_view: None,
+        livelock_threshold: None,
+        tolerated_failures: None,
+        view_installed: None,
+        initial_view: None,
+        catch_up: true,
+        leader_fn: None,
+        stop_when_blocked: false,
+        view_strategy: Box::new(Successor),
+        min_timer_length: MIN_TIMER_LENGTH,
+        view_change_cooldown: Duration::from_secs(0),
+        output_format: OutputFormat::Human,
+        output: Box::new(io::stdout()),
+    };
+    let mut paxos = Paxos::new(config).unwrap();
+
+    // the first view change only gets us to start our own, one vote short of a quorum of two
+    Pin::new(&mut paxos).start_send(Message::ViewChange { server_id: 1, attempted: 1 }).unwrap();
+    assert_eq!(paxos.current_leader(), 0);
+
+    Pin::new(&mut paxos).start_send(Message::ViewChange { server_id: 2, attempted: 1 }).unwrap();
+    assert_eq!(paxos.current_leader(), 1);
+}
@@ -0,0 +1,243 @@
+//! Runs whole clusters of nodes in a single process, wired together through an in-memory bus and
+//! driven by a shared mock clock, so that the protocol can be exercised end to end without Docker.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{Poll, Sink, Stream};
+use futures::task::{noop_waker_ref, Context};
+
+use prj2::{
+    EventLog, Message, MockClock, Paxos, PaxosConfig, PaxosConfigBuilder, TestCase, Transport,
+};
+
+/// The messages in flight between nodes, along with the index of the node each is headed to.
+type Bus = Arc<Mutex<VecDeque<(usize, Message)>>>;
+
+/// A transport that posts every message onto a bus shared by the whole cluster.
+struct Loopback {
+    /// the index of the local node
+    pid: usize,
+    /// the number of nodes in the system
+    len: usize,
+    /// the bus shared by every node in the system
+    bus: Bus,
+}
+
+impl Transport for Loopback {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn multicast_send(&mut self, msg: Message) -> io::Result<()> {
+        let mut bus = self.bus.lock().unwrap();
+        bus.extend((0..self.len).filter(|idx| *idx != self.pid).map(|idx| (idx, msg)));
+        Ok(())
+    }
+
+    fn unicast_send(&mut self, msg: Message, target: usize) -> io::Result<()> {
+        self.bus.lock().unwrap().push_back((target, msg));
+        Ok(())
+    }
+}
+
+/// A cluster of nodes running in-process, sharing a bus and a clock.
+struct Cluster {
+    /// every node in the cluster, indexed by pid
+    nodes: Vec<Paxos<Loopback>>,
+    /// whether or not each node has finished the protocol
+    done: Vec<bool>,
+    /// whether or not each node has crashed, following its test case, after which it neither
+    /// sends nor receives anything
+    crashed: Vec<bool>,
+    /// the messages in flight between nodes
+    bus: Bus,
+    /// the clock driving every node's timers
+    clock: MockClock,
+}
+
+impl Cluster {
+    /// Creates a cluster of the given number of nodes, all following the given test case.
+    fn new(len: usize, test_case: TestCase) -> Cluster {
+        Cluster::configured(len, test_case, |_, builder| builder)
+    }
+
+    /// Creates a cluster like `new`, with each node's configuration adjusted by the given function
+    /// of its pid.
+    fn configured<F>(len: usize, test_case: TestCase, configure: F) -> Cluster
+    where
+        F: Fn(usize, PaxosConfigBuilder<Loopback>) -> PaxosConfigBuilder<Loopback>,
+    {
+        let bus = Bus::default();
+        let clock = MockClock::new();
+        let nodes = (0..len)
+            .map(|pid| {
+                let transport = Loopback { pid, len, bus: bus.clone() };
+                let builder = PaxosConfig::builder(pid, transport)
+                    .schedule(test_case)
+                    .clock(clock.clone());
+                let config = configure(pid, builder).build().unwrap();
+                Paxos::new(config).unwrap()
+            })
+            .collect();
+        Cluster { nodes, done: vec![false; len], crashed: vec![false; len], bus, clock }
+    }
+
+    /// Fires every due timer and delivers every message in flight until the cluster goes quiet.
+    /// Nodes crash by panicking, which is caught so that the rest of the cluster carries on.
+    fn settle(&mut self) {
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        loop {
+            for pid in 0..self.nodes.len() {
+                while !self.done[pid] && !self.crashed[pid] {
+                    let node = &mut self.nodes[pid];
+                    match panic::catch_unwind(AssertUnwindSafe(|| {
+                        Pin::new(&mut *node).poll_next(&mut ctx)
+                    })) {
+                        Ok(Poll::Ready(Some(res))) => res.unwrap(),
+                        Ok(Poll::Ready(None)) => self.done[pid] = true,
+                        Ok(Poll::Pending) => break,
+                        Err(_) => self.crashed[pid] = true,
+                    }
+                }
+            }
+
+            let in_flight: Vec<_> = self.bus.lock().unwrap().drain(..).collect();
+            if in_flight.is_empty() { return }
+            for (target, msg) in in_flight {
+                // crashed nodes are gone, and finished ones have stopped listening
+                if self.crashed[target] || self.done[target] { continue }
+                let node = &mut self.nodes[target];
+                match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(node).start_send(msg))) {
+                    Ok(res) => res.unwrap(),
+                    Err(_) => self.crashed[target] = true,
+                }
+            }
+        }
+    }
+
+    /// Runs the cluster in steps of simulated time for the given duration, or until every node
+    /// that hasn't crashed finishes the protocol, whichever is sooner.
+    fn run(&mut self, duration: Duration) {
+        let step = Duration::from_millis(100);
+        let mut elapsed = Duration::from_millis(0);
+        self.settle();
+        while !self.finished() && elapsed < duration {
+            self.clock.advance(step);
+            elapsed += step;
+            self.settle();
+        }
+    }
+
+    /// Runs the cluster like `run`, failing if it doesn't finish within the given duration.
+    fn run_for(&mut self, limit: Duration) {
+        self.run(limit);
+        assert!(self.finished(), "cluster did not finish within {:?}", limit);
+    }
+
+    /// Determines whether or not every node that hasn't crashed has finished the protocol.
+    fn finished(&self) -> bool {
+        self.done.iter().zip(self.crashed.iter()).all(|(done, crashed)| *done || *crashed)
+    }
+
+    /// Gets the pids of the nodes that crashed.
+    fn crashed(&self) -> Vec<usize> {
+        (0..self.nodes.len()).filter(|pid| self.crashed[*pid]).collect()
+    }
+
+    /// Gets the view installed by every node that hasn't crashed, asserting that they all agree on
+    /// it.
+    fn converged_view(&self) -> u32 {
+        let views: Vec<_> = self.nodes.iter().zip(self.crashed.iter())
+            .filter(|(_, crashed)| !**crashed)
+            .map(|(node, _)| node.current_view())
+            .collect();
+        assert!(views.iter().all(|other| *other == views[0]), "nodes diverged: {:?}", views);
+        views[0]
+    }
+}
+
+#[test]
+fn normal_case_converges_on_view_one() {
+    let mut cluster = Cluster::new(5, TestCase::NormalCase);
+    cluster.run_for(Duration::from_secs(30));
+    assert_eq!(cluster.converged_view(), 1);
+    assert!(cluster.nodes.iter().all(|node| node.current_leader() == 1));
+}
+
+#[test]
+fn full_rotation_converges_back_on_the_first_leader() {
+    let mut cluster = Cluster::new(5, TestCase::FullRotation);
+    cluster.run_for(Duration::from_secs(120));
+    assert_eq!(cluster.converged_view(), 5);
+    assert!(cluster.nodes.iter().all(|node| node.current_leader() == 0));
+}
+
+#[test]
+fn single_crash_moves_past_the_crashed_leader_to_view_two() {
+    let mut cluster = Cluster::new(5, TestCase::SingleCrash);
+    cluster.run_for(Duration::from_secs(60));
+    assert_eq!(cluster.crashed(), vec![1]);
+    assert_eq!(cluster.converged_view(), 2);
+}
+
+#[test]
+fn two_crashes_move_past_both_crashed_leaders_to_view_three() {
+    let mut cluster = Cluster::new(5, TestCase::TwoCrashes);
+    cluster.run_for(Duration::from_secs(60));
+    assert_eq!(cluster.crashed(), vec![1, 2]);
+    assert_eq!(cluster.converged_view(), 3);
+}
+
+#[test]
+fn three_crashes_leave_the_survivors_stuck_without_a_quorum() {
+    let mut cluster = Cluster::new(5, TestCase::ThreeCrashes);
+    cluster.run(Duration::from_secs(120));
+    assert_eq!(cluster.crashed(), vec![1, 2, 3]);
+    // the survivors installed the view the crashed nodes helped attempt, but no view after it
+    assert!(!cluster.finished());
+    assert_eq!(cluster.converged_view(), 1);
+}
+
+/// An event log shared by every node of a cluster.
+#[derive(Clone, Default)]
+struct SharedLog(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn three_crashes_leave_the_survivors_concluding_they_are_blocked() {
+    let log = SharedLog::default();
+    let mut cluster = Cluster::configured(5, TestCase::ThreeCrashes, |_, builder| {
+        builder.events(EventLog::new(log.clone())).stop_when_blocked(true)
+    });
+    cluster.run(Duration::from_secs(120));
+    assert_eq!(cluster.crashed(), vec![1, 2, 3]);
+    assert_eq!(cluster.converged_view(), 1);
+
+    // each survivor gave up once every node had failed to lead it past view 1, in views 2 to 6
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    let blocked: Vec<_> = log.lines()
+        .filter(|line| line.contains("\"event\":\"failure_budget_exceeded\""))
+        .collect();
+    assert_eq!(blocked.len(), 2, "{}", log);
+    for pid in &[0, 4] {
+        let node = &cluster.nodes[*pid];
+        assert!(node.is_blocked());
+        assert_eq!(node.last_attempted_view(), 6);
+        let event = format!("\"pid\":{},\"current_view\":1,\"last_attempted_view\":6", pid);
+        assert!(blocked.iter().any(|line| line.contains(&event)), "{}", log);
+    }
+}